@@ -4,7 +4,7 @@ use concordium_rust_sdk::{
     postgres::{DatabaseClient, QueryOrder},
     types::ContractAddress,
 };
-use futures::StreamExt;
+use futures::TryStreamExt;
 use structopt::StructOpt;
 use tokio_postgres::NoTls;
 
@@ -38,10 +38,11 @@ async fn main() -> anyhow::Result<()> {
     let rows = db
         .query_account(&addr, 50, QueryOrder::Ascending { start: None })
         .await?;
-    rows.for_each(|entry| async move {
+    rows.try_for_each(|entry| async move {
         println!("{:?}", entry.id);
+        Ok(())
     })
-    .await;
+    .await?;
 
     let rows = db
         .query_contract(
@@ -50,18 +51,20 @@ async fn main() -> anyhow::Result<()> {
             QueryOrder::Ascending { start: None },
         )
         .await?;
-    rows.for_each(|entry| async move {
+    rows.try_for_each(|entry| async move {
         println!("{:?}", entry);
+        Ok(())
     })
-    .await;
+    .await?;
 
     let rows = db
         .query_account(&addr, 20, QueryOrder::Descending { start: None })
         .await?;
-    rows.for_each(|entry| async move {
+    rows.try_for_each(|entry| async move {
         println!("{:?}", entry);
+        Ok(())
     })
-    .await;
+    .await?;
 
     let rows = db
         .query_contract(
@@ -70,16 +73,18 @@ async fn main() -> anyhow::Result<()> {
             QueryOrder::Descending { start: None },
         )
         .await?;
-    rows.for_each(|entry| async move {
+    rows.try_for_each(|entry| async move {
         println!("{:?}", entry);
+        Ok(())
     })
-    .await;
+    .await?;
 
     let rows = db.iterate_account(&addr, None).await?;
-    rows.for_each(|entry| async move {
+    rows.try_for_each(|entry| async move {
         println!("{:?}", entry.id);
+        Ok(())
     })
-    .await;
+    .await?;
 
     Ok(())
 }
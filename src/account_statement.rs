@@ -0,0 +1,867 @@
+//! Account statements computed from the transaction index.
+//!
+//! A statement lists every change to the public balance of an account over a
+//! range of blocks, starting from the balance at the start of the range. Fees
+//! are listed separately from the amounts transferred, so that the effect of
+//! each transaction on the balance is clear. CCD received with a release
+//! schedule is part of the balance but locked until it is released, which is
+//! shown as a separate entry at the time of the release.
+//!
+//! The [StatementBuilder] computes a statement from [DatabaseRow]s, and
+//! [account_statement] queries the rows and the opening balance, and checks
+//! the result against the balance the node reports at the end of the range.
+use crate::{
+    endpoints::{self, QueryError},
+    postgres::{
+        BlockRange, DatabaseClient, DatabaseRow, DatabaseSummaryEntry, QueryOrder, RowError,
+    },
+    types::{
+        hashes::{BlockHash, TransactionHash},
+        AbsoluteBlockHeight, AccountTransactionEffects, Address, BlockItemSummaryDetails,
+        ContractTraceElement, Memo, SpecialTransactionOutcome,
+    },
+};
+use crypto_common::{
+    types::{Amount, Timestamp},
+    SerdeSerialize,
+};
+use futures::StreamExt;
+use id::types::AccountAddress;
+use thiserror::Error;
+
+#[derive(SerdeSerialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+/// What caused a change of the balance.
+pub enum EntryKind {
+    /// A transfer to or from another account.
+    Transfer,
+    /// A transfer with a release schedule. The amount is locked until it is
+    /// released.
+    ScheduledTransfer,
+    /// Release of an amount received with a release schedule. This does not
+    /// change the balance, only the locked amount.
+    ScheduledRelease,
+    /// Initialization of, or a call to, a smart contract, including transfers
+    /// from contracts to the account.
+    Contract,
+    /// Transfer from the public to the shielded balance.
+    TransferToShielded,
+    /// Transfer from the shielded to the public balance.
+    TransferToPublic,
+    /// A transfer between the shielded balances of two accounts. The amount
+    /// is encrypted and does not change the public balance, so only the fee
+    /// is paid from it.
+    EncryptedTransfer,
+    /// A transaction that only changes the balance by its fee.
+    Fee,
+    /// A transaction that was rejected. Only the fee is paid.
+    Rejected,
+    /// Reward for baking blocks in an epoch.
+    BakingReward,
+    /// Reward for taking part in finalization.
+    FinalizationReward,
+    /// Share of the transaction fees of a block, paid to its baker or to the
+    /// foundation.
+    BlockReward,
+    /// Newly minted CCD paid to the foundation.
+    Mint,
+}
+
+impl EntryKind {
+    /// Name of the kind in exports.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EntryKind::Transfer => "transfer",
+            EntryKind::ScheduledTransfer => "scheduledTransfer",
+            EntryKind::ScheduledRelease => "scheduledRelease",
+            EntryKind::Contract => "contract",
+            EntryKind::TransferToShielded => "transferToShielded",
+            EntryKind::TransferToPublic => "transferToPublic",
+            EntryKind::EncryptedTransfer => "encryptedTransfer",
+            EntryKind::Fee => "fee",
+            EntryKind::Rejected => "rejected",
+            EntryKind::BakingReward => "bakingReward",
+            EntryKind::FinalizationReward => "finalizationReward",
+            EntryKind::BlockReward => "blockReward",
+            EntryKind::Mint => "mint",
+        }
+    }
+}
+
+#[derive(SerdeSerialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+/// A change of the balance of the account.
+pub struct StatementEntry {
+    /// Id of the row of the transaction index the entry is computed from.
+    /// `None` for scheduled releases.
+    pub row_id:           Option<i64>,
+    /// The block the change happened in. `None` for scheduled releases.
+    pub block:            Option<(AbsoluteBlockHeight, BlockHash)>,
+    /// The time of the change. This is the slot time of the block, or the
+    /// time of a scheduled release.
+    pub time:             Timestamp,
+    /// What caused the change.
+    pub kind:             EntryKind,
+    /// Hash of the transaction, if the entry is for a transaction.
+    pub transaction_hash: Option<TransactionHash>,
+    /// The account or contract that sent or received the amount, if any.
+    pub counterparty:     Option<Address>,
+    /// The memo included in the transfer, if any.
+    pub memo:             Option<Memo>,
+    /// Amount added to the balance, excluding fees.
+    pub credit:           Amount,
+    /// Amount removed from the balance, excluding fees.
+    pub debit:            Amount,
+    /// Fee paid by the account for the transaction.
+    pub fee:              Amount,
+    /// Amount released from the locked balance.
+    pub released:         Amount,
+    /// Balance after the change.
+    pub balance:          Amount,
+    /// Part of the balance that is locked in release schedules after the
+    /// change.
+    pub locked:           Amount,
+    /// Amount by which the debit and fee of the entry exceed the balance before
+    /// it. The balance is then shown as zero. This is only non-zero if the
+    /// statement is inconsistent, e.g., because the opening balance is wrong.
+    pub shortfall:        Amount,
+}
+
+#[derive(SerdeSerialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+/// Comparison of the closing balance of a statement with the balance reported
+/// by the node.
+pub struct Reconciliation {
+    /// The block at which the balances were compared.
+    pub block_hash: BlockHash,
+    /// The closing balance of the statement.
+    pub expected:   Amount,
+    /// The balance reported by the node.
+    pub actual:     Amount,
+}
+
+impl Reconciliation {
+    /// Whether the balances agree.
+    pub fn is_match(&self) -> bool { self.expected == self.actual }
+}
+
+#[derive(SerdeSerialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+/// Statement of the changes to the balance of an account.
+pub struct AccountStatement {
+    /// The account.
+    pub account:         AccountAddress,
+    /// Balance before the first entry.
+    pub opening_balance: Amount,
+    /// Balance after the last entry.
+    pub closing_balance: Amount,
+    /// Changes of the balance, in the order they happened.
+    pub entries:         Vec<StatementEntry>,
+    /// Comparison of the closing balance with the balance reported by the
+    /// node, if it was made.
+    pub reconciliation:  Option<Reconciliation>,
+}
+
+impl AccountStatement {
+    /// The entries whose debits exceed the balance, see
+    /// [StatementEntry::shortfall]. A consistent statement has none.
+    pub fn overdrawn_entries(&self) -> impl Iterator<Item = &StatementEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.shortfall.microgtu > 0)
+    }
+}
+
+fn add(target: &mut Amount, amount: Amount) { target.microgtu += amount.microgtu; }
+
+/// Subtract the amount from the target. If the target is too small it is set to
+/// zero, and the part of the amount that was missing is returned.
+fn sub(target: &mut Amount, amount: Amount) -> Amount {
+    match target.microgtu.checked_sub(amount.microgtu) {
+        Some(rest) => {
+            target.microgtu = rest;
+            zero()
+        }
+        None => {
+            let missing = amount.microgtu - target.microgtu;
+            target.microgtu = 0;
+            Amount { microgtu: missing }
+        }
+    }
+}
+
+fn zero() -> Amount { Amount { microgtu: 0 } }
+
+fn address_to_string(address: &Address) -> String {
+    match address {
+        Address::Account(acc) => acc.to_string(),
+        Address::Contract(c) => format!("<{},{}>", u64::from(c.index), u64::from(c.subindex)),
+    }
+}
+
+impl AccountStatement {
+    /// Write the entries as CSV, with one row per entry. Amounts are written
+    /// in CCD, and memos in hex.
+    pub fn write_csv<W: std::io::Write>(&self, out: W) -> csv::Result<()> {
+        let mut writer = csv::Writer::from_writer(out);
+        writer.write_record(&[
+            "row_id",
+            "block_height",
+            "block_hash",
+            "time",
+            "kind",
+            "transaction_hash",
+            "counterparty",
+            "memo",
+            "credit",
+            "debit",
+            "fee",
+            "released",
+            "balance",
+            "locked",
+            "shortfall",
+        ])?;
+        for entry in self.entries.iter() {
+            let (height, block_hash) = match entry.block {
+                Some((height, block_hash)) => (height.to_string(), block_hash.to_string()),
+                None => (String::new(), String::new()),
+            };
+            writer.write_record(&[
+                entry.row_id.map(|id| id.to_string()).unwrap_or_default(),
+                height,
+                block_hash,
+                entry.time.millis.to_string(),
+                entry.kind.as_str().to_string(),
+                entry
+                    .transaction_hash
+                    .map(|hash| hash.to_string())
+                    .unwrap_or_default(),
+                entry
+                    .counterparty
+                    .as_ref()
+                    .map(address_to_string)
+                    .unwrap_or_default(),
+                entry
+                    .memo
+                    .as_ref()
+                    .map(|memo| hex::encode(AsRef::<Vec<u8>>::as_ref(memo)))
+                    .unwrap_or_default(),
+                entry.credit.to_string(),
+                entry.debit.to_string(),
+                entry.fee.to_string(),
+                entry.released.to_string(),
+                entry.balance.to_string(),
+                entry.locked.to_string(),
+                entry.shortfall.to_string(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Write the statement as JSON.
+    pub fn write_json<W: std::io::Write>(&self, out: W) -> serde_json::Result<()> {
+        serde_json::to_writer_pretty(out, self)
+    }
+}
+
+/// Computes a statement from the rows of the transaction index for an account,
+/// which must be added in order of increasing id.
+pub struct StatementBuilder {
+    account:  AccountAddress,
+    opening:  Amount,
+    balance:  Amount,
+    locked:   Amount,
+    /// Releases that have not yet happened, in order of time.
+    releases: Vec<(Timestamp, Amount)>,
+    entries:  Vec<StatementEntry>,
+}
+
+impl StatementBuilder {
+    /// Start a statement with the given opening balance.
+    pub fn new(account: AccountAddress, opening_balance: Amount) -> Self {
+        Self {
+            account,
+            opening: opening_balance,
+            balance: opening_balance,
+            locked: zero(),
+            releases: Vec::new(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Add a release of a locked amount, for amounts received before the start
+    /// of the statement. Amounts received with a release schedule during the
+    /// statement are added automatically.
+    pub fn add_pending_release(&mut self, time: Timestamp, amount: Amount) {
+        add(&mut self.locked, amount);
+        let position = self
+            .releases
+            .iter()
+            .position(|(t, _)| t.millis > time.millis)
+            .unwrap_or(self.releases.len());
+        self.releases.insert(position, (time, amount));
+    }
+
+    /// Add entries for the releases that happen at or before the given time.
+    fn release_until(&mut self, time: Timestamp) {
+        while let Some((release_time, amount)) = self.releases.first().copied() {
+            if release_time.millis > time.millis {
+                break;
+            }
+            self.releases.remove(0);
+            // Releases are only added together with their amount, so the locked
+            // amount always covers them.
+            sub(&mut self.locked, amount);
+            self.entries.push(StatementEntry {
+                row_id:           None,
+                block:            None,
+                time:             release_time,
+                kind:             EntryKind::ScheduledRelease,
+                transaction_hash: None,
+                counterparty:     None,
+                memo:             None,
+                credit:           zero(),
+                debit:            zero(),
+                fee:              zero(),
+                released:         amount,
+                balance:          self.balance,
+                locked:           self.locked,
+                shortfall:        zero(),
+            });
+        }
+    }
+
+    /// Add the row to the statement. Rows that do not change the balance, such
+    /// as account creations, are ignored.
+    pub fn add_row(&mut self, row: &DatabaseRow) {
+        self.release_until(row.block_time);
+        let acc = self.account;
+        let mut entry = StatementEntry {
+            row_id:           Some(row.id),
+            block:            Some((row.block_height, row.block_hash)),
+            time:             row.block_time,
+            kind:             EntryKind::Fee,
+            transaction_hash: None,
+            counterparty:     None,
+            memo:             None,
+            credit:           zero(),
+            debit:            zero(),
+            fee:              zero(),
+            released:         zero(),
+            balance:          zero(),
+            locked:           zero(),
+            shortfall:        zero(),
+        };
+        match &row.summary {
+            DatabaseSummaryEntry::BlockItem(item) => {
+                let at = match &item.details {
+                    BlockItemSummaryDetails::AccountTransaction(at) => at,
+                    _ => return,
+                };
+                entry.transaction_hash = Some(item.hash);
+                let is_sender = at.sender == acc;
+                if is_sender {
+                    entry.fee = at.cost;
+                }
+                // Counterparty of a transfer, from the point of view of the
+                // account.
+                let other =
+                    |to: &AccountAddress| Address::Account(if is_sender { *to } else { at.sender });
+                match &at.effects {
+                    AccountTransactionEffects::None { .. } => entry.kind = EntryKind::Rejected,
+                    AccountTransactionEffects::AccountTransfer { amount, to }
+                    | AccountTransactionEffects::AccountTransferWithMemo { amount, to, .. } => {
+                        entry.kind = EntryKind::Transfer;
+                        entry.counterparty = Some(other(to));
+                        if is_sender {
+                            entry.debit = *amount;
+                        }
+                        if *to == acc {
+                            entry.credit = *amount;
+                        }
+                    }
+                    AccountTransactionEffects::TransferredWithSchedule { to, amount }
+                    | AccountTransactionEffects::TransferredWithScheduleAndMemo {
+                        to,
+                        amount,
+                        ..
+                    } => {
+                        entry.kind = EntryKind::ScheduledTransfer;
+                        entry.counterparty = Some(other(to));
+                        let mut total = zero();
+                        for (_, release) in amount.iter() {
+                            add(&mut total, *release);
+                        }
+                        if is_sender {
+                            entry.debit = total;
+                        }
+                        if *to == acc {
+                            entry.credit = total;
+                            for (time, release) in amount.iter() {
+                                self.add_pending_release(*time, *release);
+                            }
+                        }
+                    }
+                    AccountTransactionEffects::ContractInitialized { data } => {
+                        entry.kind = EntryKind::Contract;
+                        entry.counterparty = Some(Address::Contract(data.address));
+                        entry.debit = data.amount;
+                    }
+                    AccountTransactionEffects::ContractUpdateIssued { effects } => {
+                        entry.kind = EntryKind::Contract;
+                        for effect in effects {
+                            match effect {
+                                ContractTraceElement::Updated { data } => {
+                                    if data.instigator == Address::Account(acc) {
+                                        add(&mut entry.debit, data.amount);
+                                        entry
+                                            .counterparty
+                                            .get_or_insert(Address::Contract(data.address));
+                                    }
+                                }
+                                ContractTraceElement::Transferred { from, amount, to } => {
+                                    if *to == acc {
+                                        add(&mut entry.credit, *amount);
+                                        entry.counterparty.get_or_insert(Address::Contract(*from));
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    AccountTransactionEffects::TransferredToEncrypted { data } => {
+                        entry.kind = EntryKind::TransferToShielded;
+                        entry.debit = data.amount;
+                    }
+                    AccountTransactionEffects::TransferredToPublic { amount, .. } => {
+                        entry.kind = EntryKind::TransferToPublic;
+                        entry.credit = *amount;
+                    }
+                    AccountTransactionEffects::EncryptedAmountTransferred { added, .. }
+                    | AccountTransactionEffects::EncryptedAmountTransferredWithMemo {
+                        added, ..
+                    } => {
+                        entry.kind = EntryKind::EncryptedTransfer;
+                        entry.counterparty = Some(other(&added.receiver));
+                    }
+                    _ => (),
+                }
+                match &at.effects {
+                    AccountTransactionEffects::AccountTransferWithMemo { memo, .. }
+                    | AccountTransactionEffects::EncryptedAmountTransferredWithMemo {
+                        memo, ..
+                    }
+                    | AccountTransactionEffects::TransferredWithScheduleAndMemo { memo, .. } => {
+                        entry.memo = Some(memo.clone())
+                    }
+                    _ => (),
+                }
+            }
+            DatabaseSummaryEntry::ProtocolEvent(outcome) => match outcome {
+                SpecialTransactionOutcome::BakingRewards { baker_rewards, .. } => {
+                    entry.kind = EntryKind::BakingReward;
+                    entry.credit = baker_rewards.get(&acc).copied().unwrap_or_else(zero);
+                }
+                SpecialTransactionOutcome::FinalizationRewards {
+                    finalization_rewards,
+                    ..
+                } => {
+                    entry.kind = EntryKind::FinalizationReward;
+                    entry.credit = finalization_rewards.get(&acc).copied().unwrap_or_else(zero);
+                }
+                SpecialTransactionOutcome::Mint {
+                    foundation_account,
+                    mint_platform_development_charge,
+                    ..
+                } => {
+                    entry.kind = EntryKind::Mint;
+                    if *foundation_account == acc {
+                        entry.credit = *mint_platform_development_charge;
+                    }
+                }
+                SpecialTransactionOutcome::BlockReward {
+                    baker_reward,
+                    foundation_charge,
+                    baker,
+                    foundation_account,
+                    ..
+                } => {
+                    entry.kind = EntryKind::BlockReward;
+                    if *baker == acc {
+                        add(&mut entry.credit, *baker_reward);
+                    }
+                    if *foundation_account == acc {
+                        add(&mut entry.credit, *foundation_charge);
+                    }
+                }
+            },
+        }
+        add(&mut self.balance, entry.credit);
+        entry.shortfall = sub(&mut self.balance, entry.debit);
+        add(&mut entry.shortfall, sub(&mut self.balance, entry.fee));
+        entry.balance = self.balance;
+        entry.locked = self.locked;
+        self.entries.push(entry);
+    }
+
+    /// Finish the statement at the given time. Releases that happen at or
+    /// before it are included.
+    pub fn finish(mut self, closing_time: Timestamp) -> AccountStatement {
+        self.release_until(closing_time);
+        AccountStatement {
+            account:         self.account,
+            opening_balance: self.opening,
+            closing_balance: self.balance,
+            entries:         self.entries,
+            reconciliation:  None,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+/// Errors that can occur when computing a statement.
+pub enum StatementError {
+    #[error("Error querying the node: {0}")]
+    /// Querying the node failed.
+    Query(#[from] QueryError),
+    #[error("Database error: {0}")]
+    /// Querying the transaction index failed.
+    Database(#[from] tokio_postgres::Error),
+    #[error("Error reading the transaction index: {0}")]
+    /// A row of the transaction index could not be read. The statement would
+    /// be incomplete without it.
+    Row(#[from] RowError),
+}
+
+/// Balance and release schedule of the account in the given block. Zero if the
+/// account does not exist in the block.
+async fn balance_at(
+    client: &mut endpoints::Client,
+    account: AccountAddress,
+    block_hash: &BlockHash,
+) -> Result<(Amount, Vec<(Timestamp, Amount)>), QueryError> {
+    match client.get_account_info(account, block_hash).await {
+        Ok(info) => {
+            let releases = info
+                .account_release_schedule
+                .schedule
+                .iter()
+                .map(|release| {
+                    let millis = release.timestamp.timestamp_millis() as u64;
+                    (Timestamp::from(millis), release.amount)
+                })
+                .collect();
+            Ok((info.account_amount, releases))
+        }
+        Err(QueryError::NotFound) => Ok((zero(), Vec::new())),
+        Err(e) => Err(e),
+    }
+}
+
+/// Compute the statement of the account for the finalized blocks in the given
+/// range of heights, both ends inclusive. The opening balance is the balance
+/// at the end of the block preceding `from`. The closing balance is compared
+/// to the balance the node reports at the end of block `to`, and the result is
+/// recorded in the [reconciliation](AccountStatement::reconciliation).
+///
+/// Rows of the transaction index that cannot be parsed are not skipped, since
+/// the statement would silently be wrong. Instead the computation fails with
+/// [StatementError::Row]. A mismatch therefore means that the transaction
+/// index is incomplete.
+pub async fn account_statement(
+    db: &DatabaseClient,
+    client: &mut endpoints::Client,
+    account: AccountAddress,
+    from: AbsoluteBlockHeight,
+    to: AbsoluteBlockHeight,
+) -> Result<AccountStatement, StatementError> {
+    let (opening_balance, releases) = if from.height == 0 {
+        (zero(), Vec::new())
+    } else {
+        let before = AbsoluteBlockHeight::from(from.height - 1);
+        let bh = client.get_finalized_block_at_height(before).await?;
+        balance_at(client, account, &bh).await?
+    };
+    let closing_hash = client.get_finalized_block_at_height(to).await?;
+    let closing_info = client.get_block_info(&closing_hash).await?;
+
+    let mut builder = StatementBuilder::new(account, opening_balance);
+    for (time, amount) in releases {
+        builder.add_pending_release(time, amount);
+    }
+    let rows = db
+        .query_account_range_strict(
+            &account,
+            BlockRange::Height { from, to },
+            i64::MAX,
+            QueryOrder::Ascending { start: None },
+        )
+        .await?;
+    futures::pin_mut!(rows);
+    while let Some(row) = rows.next().await {
+        builder.add_row(&row?);
+    }
+
+    let closing_time = closing_info.block_slot_time.timestamp_millis() as u64;
+    let mut statement = builder.finish(Timestamp::from(closing_time));
+    let (actual, _) = balance_at(client, account, &closing_hash).await?;
+    statement.reconciliation = Some(Reconciliation {
+        block_hash: closing_hash,
+        expected: statement.closing_balance,
+        actual,
+    });
+    Ok(statement)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        smart_contracts::{Parameter, ReceiveName},
+        AccountTransactionDetails, BlockItemSummary, ContractAddress, Energy, InstanceUpdatedEvent,
+        RejectReason, TransactionIndex,
+    };
+
+    fn account(byte: u8) -> AccountAddress { AccountAddress([byte; 32]) }
+
+    fn ccd(microgtu: u64) -> Amount { Amount { microgtu } }
+
+    /// A row with an account transaction in a block at the given time.
+    fn row(
+        id: i64,
+        time: u64,
+        sender: AccountAddress,
+        cost: u64,
+        effects: AccountTransactionEffects,
+    ) -> DatabaseRow {
+        DatabaseRow {
+            id,
+            block_hash: BlockHash::new([id as u8; 32]),
+            block_time: Timestamp::from(time),
+            block_height: AbsoluteBlockHeight::from(id as u64),
+            summary: DatabaseSummaryEntry::BlockItem(BlockItemSummary {
+                index:       TransactionIndex { index: 0 },
+                energy_cost: Energy { energy: 500 },
+                hash:        TransactionHash::new([id as u8; 32]),
+                details:     BlockItemSummaryDetails::AccountTransaction(
+                    AccountTransactionDetails {
+                        cost: ccd(cost),
+                        sender,
+                        effects,
+                    },
+                ),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_sent_transfer() {
+        let mut builder = StatementBuilder::new(account(1), ccd(1000));
+        builder.add_row(&row(
+            1,
+            10,
+            account(1),
+            5,
+            AccountTransactionEffects::AccountTransfer {
+                amount: ccd(100),
+                to:     account(2),
+            },
+        ));
+        let statement = builder.finish(Timestamp::from(20));
+        assert_eq!(statement.entries.len(), 1);
+        let entry = &statement.entries[0];
+        assert_eq!(entry.kind, EntryKind::Transfer);
+        assert_eq!(entry.counterparty, Some(Address::Account(account(2))));
+        assert_eq!(entry.debit, ccd(100));
+        assert_eq!(entry.credit, ccd(0));
+        assert_eq!(entry.fee, ccd(5));
+        assert_eq!(entry.balance, ccd(895));
+        assert_eq!(statement.closing_balance, ccd(895));
+    }
+
+    #[test]
+    fn test_shortfall() {
+        let mut builder = StatementBuilder::new(account(1), ccd(50));
+        builder.add_row(&row(
+            1,
+            10,
+            account(1),
+            5,
+            AccountTransactionEffects::AccountTransfer {
+                amount: ccd(100),
+                to:     account(2),
+            },
+        ));
+        builder.add_row(&row(
+            2,
+            20,
+            account(3),
+            5,
+            AccountTransactionEffects::AccountTransfer {
+                amount: ccd(10),
+                to:     account(1),
+            },
+        ));
+        let statement = builder.finish(Timestamp::from(30));
+        assert_eq!(statement.entries[0].balance, ccd(0));
+        assert_eq!(statement.entries[0].shortfall, ccd(55));
+        assert_eq!(statement.entries[1].balance, ccd(10));
+        assert_eq!(statement.entries[1].shortfall, ccd(0));
+        let overdrawn: Vec<_> = statement.overdrawn_entries().map(|e| e.row_id).collect();
+        assert_eq!(overdrawn, vec![Some(1)]);
+        assert_eq!(statement.closing_balance, ccd(10));
+    }
+
+    #[test]
+    fn test_received_scheduled_transfer() {
+        let mut builder = StatementBuilder::new(account(1), ccd(0));
+        builder.add_row(&row(
+            1,
+            10,
+            account(2),
+            5,
+            AccountTransactionEffects::TransferredWithSchedule {
+                to:     account(1),
+                amount: vec![
+                    (Timestamp::from(20), ccd(30)),
+                    (Timestamp::from(40), ccd(70)),
+                ],
+            },
+        ));
+        let entry = &builder.entries[0];
+        assert_eq!(entry.kind, EntryKind::ScheduledTransfer);
+        assert_eq!(entry.counterparty, Some(Address::Account(account(2))));
+        // The fee is paid by the sender.
+        assert_eq!(entry.fee, ccd(0));
+        assert_eq!(entry.credit, ccd(100));
+        assert_eq!(entry.balance, ccd(100));
+        assert_eq!(entry.locked, ccd(100));
+
+        // Only the first release happens before the next row.
+        builder.add_row(&row(
+            2,
+            30,
+            account(2),
+            5,
+            AccountTransactionEffects::AccountTransfer {
+                amount: ccd(1),
+                to:     account(1),
+            },
+        ));
+        let kinds = builder
+            .entries
+            .iter()
+            .map(|entry| entry.kind)
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, vec![
+            EntryKind::ScheduledTransfer,
+            EntryKind::ScheduledRelease,
+            EntryKind::Transfer
+        ]);
+        let release = &builder.entries[1];
+        assert_eq!(release.time, Timestamp::from(20));
+        assert_eq!(release.released, ccd(30));
+        assert_eq!(release.balance, ccd(100));
+        assert_eq!(release.locked, ccd(70));
+
+        let statement = builder.finish(Timestamp::from(40));
+        let last = statement
+            .entries
+            .last()
+            .expect("The last release is included.");
+        assert_eq!(last.kind, EntryKind::ScheduledRelease);
+        assert_eq!(last.released, ccd(70));
+        assert_eq!(last.locked, ccd(0));
+        assert_eq!(statement.closing_balance, ccd(101));
+    }
+
+    #[test]
+    fn test_self_transfer() {
+        let mut builder = StatementBuilder::new(account(1), ccd(1000));
+        builder.add_row(&row(
+            1,
+            10,
+            account(1),
+            5,
+            AccountTransactionEffects::AccountTransfer {
+                amount: ccd(100),
+                to:     account(1),
+            },
+        ));
+        let statement = builder.finish(Timestamp::from(20));
+        let entry = &statement.entries[0];
+        assert_eq!(entry.counterparty, Some(Address::Account(account(1))));
+        assert_eq!(entry.debit, ccd(100));
+        assert_eq!(entry.credit, ccd(100));
+        // Only the fee changes the balance.
+        assert_eq!(statement.closing_balance, ccd(995));
+    }
+
+    #[test]
+    fn test_rejected_transaction() {
+        let mut builder = StatementBuilder::new(account(1), ccd(1000));
+        builder.add_row(&row(
+            1,
+            10,
+            account(1),
+            5,
+            AccountTransactionEffects::None {
+                transaction_type: None,
+                reject_reason:    RejectReason::ModuleNotWF,
+            },
+        ));
+        let statement = builder.finish(Timestamp::from(20));
+        let entry = &statement.entries[0];
+        assert_eq!(entry.kind, EntryKind::Rejected);
+        assert_eq!(entry.debit, ccd(0));
+        assert_eq!(entry.credit, ccd(0));
+        assert_eq!(entry.fee, ccd(5));
+        assert_eq!(statement.closing_balance, ccd(995));
+    }
+
+    #[test]
+    fn test_contract_call_with_transfer_back() {
+        let contract = ContractAddress::new(3.into(), 0.into());
+        let update = InstanceUpdatedEvent {
+            address:      contract,
+            instigator:   Address::Account(account(1)),
+            amount:       ccd(100),
+            message:      Parameter::from(Vec::new()),
+            receive_name: ReceiveName {
+                name: "auction.bid".into(),
+            },
+            events:       Vec::new(),
+        };
+        let mut builder = StatementBuilder::new(account(1), ccd(1000));
+        builder.add_row(&row(
+            1,
+            10,
+            account(1),
+            5,
+            AccountTransactionEffects::ContractUpdateIssued {
+                effects: vec![
+                    ContractTraceElement::Updated { data: update },
+                    ContractTraceElement::Transferred {
+                        from:   contract,
+                        amount: ccd(40),
+                        to:     account(1),
+                    },
+                    // A transfer to another account does not affect the balance.
+                    ContractTraceElement::Transferred {
+                        from:   contract,
+                        amount: ccd(60),
+                        to:     account(2),
+                    },
+                ],
+            },
+        ));
+        let statement = builder.finish(Timestamp::from(20));
+        let entry = &statement.entries[0];
+        assert_eq!(entry.kind, EntryKind::Contract);
+        assert_eq!(entry.counterparty, Some(Address::Contract(contract)));
+        assert_eq!(entry.debit, ccd(100));
+        assert_eq!(entry.credit, ccd(40));
+        assert_eq!(entry.fee, ccd(5));
+        assert_eq!(statement.closing_balance, ccd(935));
+    }
+}
@@ -0,0 +1,777 @@
+//! Management of the baker of an account.
+//!
+//! The [BakerManager] reports the baker state of an account, including when
+//! any pending change of the baker takes effect, and submits the baker
+//! transactions after checking that they are allowed in the current state.
+//! The checks mirror the rules the node applies, so that transactions that
+//! would be rejected with [BakerInCooldown](RejectReason::BakerInCooldown),
+//! [StakeUnderMinimumThresholdForBaking](
+//! RejectReason::StakeUnderMinimumThresholdForBaking) and similar reasons are
+//! not submitted and do not cost the account a transaction fee.
+//!
+//! The manager also supports rotating the baker keys together with the
+//! credentials file the node reads them from, see
+//! [BakerManager::rotate_keys].
+use crate::{
+    clock::{ChainClock, ClockError},
+    constants::DEFAULT_NETWORK_ID,
+    endpoints::{self, QueryError, RPCError},
+    types::{
+        hashes::{BlockHash, TransactionHash},
+        network::NetworkId,
+        transactions::{
+            send, AccountTransaction, BakerAddKeysPayload, BakerUpdateKeysPayload, BlockItem,
+            EncodedPayload, ExactSizeTransactionSigner,
+        },
+        AccountBaker, AccountTransactionEffects, BakerCredentials, BakerId, BakerKeyPairs,
+        BakerKeysEvent, BakerPendingChange, BlockItemSummary, BlockItemSummaryDetails, Epoch,
+        Nonce, RejectReason, TransactionStatus,
+    },
+};
+use crypto_common::types::{Amount, TransactionTime};
+use id::types::AccountAddress;
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+/// Reasons why a baker transaction is not allowed in the current state of the
+/// account. These correspond to the [RejectReason]s the node would reject the
+/// transaction with.
+pub enum BakerTransitionError {
+    #[error("The account is already baker {0}.")]
+    AlreadyABaker(BakerId),
+    #[error("The account is not a baker.")]
+    NotABaker,
+    #[error("The baker has a pending change and is in cooldown.")]
+    BakerInCooldown,
+    #[error("The stake {stake} is under the minimum threshold {threshold} for baking.")]
+    StakeUnderMinimumThresholdForBaking {
+        stake:     Amount,
+        threshold: Amount,
+    },
+    #[error("The stake {stake} exceeds the balance {balance} of the account.")]
+    InsufficientBalanceForBakerStake { stake: Amount, balance: Amount },
+}
+
+impl BakerTransitionError {
+    /// The reason the node would reject the transaction with.
+    pub fn reject_reason(&self, account: AccountAddress) -> RejectReason {
+        match self {
+            BakerTransitionError::AlreadyABaker(id) => {
+                RejectReason::AlreadyABaker { contents: *id }
+            }
+            BakerTransitionError::NotABaker => RejectReason::NotABaker { contents: account },
+            BakerTransitionError::BakerInCooldown => RejectReason::BakerInCooldown,
+            BakerTransitionError::StakeUnderMinimumThresholdForBaking { .. } => {
+                RejectReason::StakeUnderMinimumThresholdForBaking
+            }
+            BakerTransitionError::InsufficientBalanceForBakerStake { .. } => {
+                RejectReason::InsufficientBalanceForBakerStake
+            }
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+/// Errors that can occur when managing a baker.
+pub enum BakerManagerError {
+    #[error("The transaction is not allowed: {0}")]
+    Transition(#[from] BakerTransitionError),
+    #[error("Query error: {0}")]
+    Query(#[from] QueryError),
+    #[error("Could not determine the epochs of the chain: {0}")]
+    Clock(#[from] ClockError),
+    #[error("The node did not accept the transaction.")]
+    NotAccepted,
+    #[error("The transaction was rejected: {0:?}")]
+    Rejected(RejectReason),
+    #[error("The transaction had unexpected effects.")]
+    UnexpectedOutcome,
+    #[error(
+        "The keys of the finalized update do not match the new credentials, which are kept in \
+         {0:?}."
+    )]
+    KeysMismatch(PathBuf),
+    #[error("Error accessing the credentials file: {0}")]
+    Io(#[from] io::Error),
+    #[error("Error (de)serializing the credentials: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl From<RPCError> for BakerManagerError {
+    fn from(e: RPCError) -> Self { Self::Query(e.into()) }
+}
+
+#[derive(Debug)]
+/// A pending change of a baker together with the time it takes effect.
+pub struct ScheduledBakerChange {
+    /// The change.
+    pub change:         BakerPendingChange,
+    /// The epoch in which the change takes effect.
+    pub epoch:          Epoch,
+    /// The wall-clock time at which the epoch starts.
+    pub effective_time: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug)]
+/// The baker state of an account in a given block.
+pub struct BakerState {
+    /// The block the state is reported for.
+    pub block:          BlockHash,
+    /// The total balance of the account, including the staked amount.
+    pub balance:        Amount,
+    /// The baker of the account, if the account is a baker.
+    pub baker:          Option<AccountBaker>,
+    /// The pending change of the baker, if any.
+    pub pending_change: Option<ScheduledBakerChange>,
+    /// The minimum stake required for baking.
+    pub threshold:      Amount,
+    /// Number of epochs that a stake reduction or baker removal takes.
+    pub cooldown:       Epoch,
+}
+
+impl BakerState {
+    fn require_baker(&self) -> Result<&AccountBaker, BakerTransitionError> {
+        self.baker.as_ref().ok_or(BakerTransitionError::NotABaker)
+    }
+
+    fn require_stake(&self, stake: Amount) -> Result<(), BakerTransitionError> {
+        if stake.microgtu < self.threshold.microgtu {
+            return Err(BakerTransitionError::StakeUnderMinimumThresholdForBaking {
+                stake,
+                threshold: self.threshold,
+            });
+        }
+        if stake.microgtu > self.balance.microgtu {
+            return Err(BakerTransitionError::InsufficientBalanceForBakerStake {
+                stake,
+                balance: self.balance,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check whether the account can become a baker with the given stake.
+    pub fn check_add(&self, stake: Amount) -> Result<(), BakerTransitionError> {
+        if let Some(baker) = &self.baker {
+            return Err(BakerTransitionError::AlreadyABaker(baker.baker_id));
+        }
+        self.require_stake(stake)
+    }
+
+    /// Check whether the stake of the baker can be changed to the given
+    /// amount. A baker with a pending change cannot change its stake.
+    pub fn check_update_stake(&self, new_stake: Amount) -> Result<(), BakerTransitionError> {
+        let baker = self.require_baker()?;
+        if baker.pending_change.is_some() {
+            return Err(BakerTransitionError::BakerInCooldown);
+        }
+        self.require_stake(new_stake)
+    }
+
+    /// Check whether the baker can be removed. A baker with a pending change
+    /// cannot be removed.
+    pub fn check_remove(&self) -> Result<(), BakerTransitionError> {
+        let baker = self.require_baker()?;
+        if baker.pending_change.is_some() {
+            return Err(BakerTransitionError::BakerInCooldown);
+        }
+        Ok(())
+    }
+
+    /// Check whether the keys or the restake flag of the baker can be
+    /// updated. This only requires the account to be a baker.
+    pub fn check_update(&self) -> Result<(), BakerTransitionError> {
+        self.require_baker().map(|_| ())
+    }
+}
+
+/// Manager of the baker of a single account. The manager queries the node for
+/// the state of the account and submits baker transactions signed by the
+/// given signer.
+pub struct BakerManager<S> {
+    client:     endpoints::Client,
+    account:    AccountAddress,
+    signer:     S,
+    network_id: NetworkId,
+    expiry:     chrono::Duration,
+}
+
+impl<S: ExactSizeTransactionSigner> BakerManager<S> {
+    /// Construct a manager for the given account. Transactions are sent on the
+    /// default network and expire 5 minutes after they are constructed.
+    pub fn new(client: endpoints::Client, account: AccountAddress, signer: S) -> Self {
+        Self {
+            client,
+            account,
+            signer,
+            network_id: DEFAULT_NETWORK_ID,
+            expiry: chrono::Duration::minutes(5),
+        }
+    }
+
+    /// Use the given network ID when sending transactions.
+    pub fn with_network_id(self, network_id: NetworkId) -> Self { Self { network_id, ..self } }
+
+    /// Set how long after construction transactions expire.
+    pub fn with_expiry(self, expiry: chrono::Duration) -> Self { Self { expiry, ..self } }
+
+    /// The account whose baker is managed.
+    pub fn account(&self) -> AccountAddress { self.account }
+
+    /// Get the baker state of the account in the last finalized block.
+    pub async fn state(&mut self) -> Result<BakerState, BakerManagerError> {
+        let consensus_info = self.client.get_consensus_status().await?;
+        // The clock is constructed afterwards, so that it knows the era of the
+        // block.
+        let clock = ChainClock::from_client(&mut self.client).await?;
+        let block = consensus_info.last_finalized_block;
+        let account_info = self.client.get_account_info(self.account, &block).await?;
+        let summary = self.client.get_block_summary(&block).await?;
+        let chain_parameters = summary.updates.chain_parameters;
+        let pending_change = account_info
+            .account_baker
+            .as_ref()
+            .and_then(|baker| baker.pending_change.as_ref())
+            .and_then(|change| {
+                let epoch = match change {
+                    BakerPendingChange::ReduceStake { epoch, .. } => *epoch,
+                    BakerPendingChange::RemoveBaker { epoch } => *epoch,
+                };
+                // Epochs are counted from the start of the era of the block.
+                let effective_time = clock.epoch_start(consensus_info.genesis_index, epoch)?;
+                Some(ScheduledBakerChange {
+                    change: *change,
+                    epoch,
+                    effective_time,
+                })
+            });
+        Ok(BakerState {
+            block,
+            balance: account_info.account_amount,
+            baker: account_info.account_baker,
+            pending_change,
+            threshold: chain_parameters.minimum_threshold_for_baking,
+            cooldown: chain_parameters.baker_cooldown_epochs,
+        })
+    }
+
+    /// Sign the transaction with the next nonce of the account and send it.
+    async fn submit(
+        &mut self,
+        make: impl FnOnce(&S, Nonce, TransactionTime) -> AccountTransaction<EncodedPayload>,
+    ) -> Result<TransactionHash, BakerManagerError> {
+        let nonce = self
+            .client
+            .get_next_account_nonce(&self.account)
+            .await?
+            .nonce;
+        let expiry =
+            TransactionTime::from_seconds((chrono::Utc::now() + self.expiry).timestamp() as u64);
+        let item = BlockItem::AccountTransaction(make(&self.signer, nonce, expiry));
+        if self.client.send_transaction(self.network_id, &item).await? {
+            Ok(item.hash())
+        } else {
+            Err(BakerManagerError::NotAccepted)
+        }
+    }
+
+    /// Register the account as a baker with the given keys and stake.
+    pub async fn add_baker<R: rand::Rng>(
+        &mut self,
+        keys: &BakerKeyPairs,
+        stake: Amount,
+        restake_earnings: bool,
+        csprng: &mut R,
+    ) -> Result<TransactionHash, BakerManagerError> {
+        self.state().await?.check_add(stake)?;
+        let payload = BakerAddKeysPayload::new(keys, self.account, csprng);
+        let sender = self.account;
+        self.submit(|signer, nonce, expiry| {
+            send::add_baker(
+                signer,
+                sender,
+                nonce,
+                expiry,
+                stake,
+                restake_earnings,
+                payload,
+            )
+        })
+        .await
+    }
+
+    /// Change the stake of the baker. Lowering the stake takes effect after the
+    /// cooldown period.
+    pub async fn update_stake(
+        &mut self,
+        new_stake: Amount,
+    ) -> Result<TransactionHash, BakerManagerError> {
+        self.state().await?.check_update_stake(new_stake)?;
+        let sender = self.account;
+        self.submit(|signer, nonce, expiry| {
+            send::update_baker_stake(signer, sender, nonce, expiry, new_stake)
+        })
+        .await
+    }
+
+    /// Replace the keys of the baker.
+    pub async fn update_keys<R: rand::Rng>(
+        &mut self,
+        keys: &BakerKeyPairs,
+        csprng: &mut R,
+    ) -> Result<TransactionHash, BakerManagerError> {
+        self.state().await?.check_update()?;
+        let payload = BakerUpdateKeysPayload::new(keys, self.account, csprng);
+        let sender = self.account;
+        self.submit(|signer, nonce, expiry| {
+            send::update_baker_keys(signer, sender, nonce, expiry, payload)
+        })
+        .await
+    }
+
+    /// Set whether earnings of the baker are added to its stake.
+    pub async fn update_restake_earnings(
+        &mut self,
+        restake_earnings: bool,
+    ) -> Result<TransactionHash, BakerManagerError> {
+        self.state().await?.check_update()?;
+        let sender = self.account;
+        self.submit(|signer, nonce, expiry| {
+            send::update_baker_restake_earnings(signer, sender, nonce, expiry, restake_earnings)
+        })
+        .await
+    }
+
+    /// Deregister the baker. The baker is removed after the cooldown period.
+    pub async fn remove_baker(&mut self) -> Result<TransactionHash, BakerManagerError> {
+        self.state().await?.check_remove()?;
+        let sender = self.account;
+        self.submit(|signer, nonce, expiry| send::remove_baker(signer, sender, nonce, expiry))
+            .await
+    }
+
+    /// Wait until the transaction is finalized and return its outcome. The
+    /// status is checked every `poll_interval`.
+    pub async fn wait_until_finalized(
+        &mut self,
+        hash: &TransactionHash,
+        poll_interval: std::time::Duration,
+    ) -> Result<(BlockHash, BlockItemSummary), BakerManagerError> {
+        loop {
+            if let TransactionStatus::Finalized(outcomes) =
+                self.client.get_transaction_status(hash).await?
+            {
+                // There is exactly one outcome for a finalized transaction.
+                return outcomes
+                    .into_iter()
+                    .next()
+                    .ok_or(BakerManagerError::UnexpectedOutcome);
+            }
+            tokio::time::sleep(poll_interval).await;
+        }
+    }
+
+    /// Rotate the keys of the baker.
+    ///
+    /// New keys are generated and first written to a pending file next to the
+    /// credentials file, so that they are not lost if the process is
+    /// interrupted after the update is submitted. Once the update is finalized
+    /// the current credentials file is backed up (see [backup_path]) and
+    /// atomically replaced by the new credentials. If the update is rejected
+    /// the pending file is removed and the credentials file is left untouched.
+    /// If the keys in the finalized update do not match the new credentials
+    /// (see [verify_keys_event]) both files are left untouched and
+    /// [BakerManagerError::KeysMismatch] is returned.
+    pub async fn rotate_keys<R: rand::Rng + rand::CryptoRng>(
+        &mut self,
+        credentials_path: &Path,
+        poll_interval: std::time::Duration,
+        csprng: &mut R,
+    ) -> Result<KeyRotation, BakerManagerError> {
+        let baker_id = self.state().await?.require_baker()?.baker_id;
+        let keys = BakerKeyPairs::generate(csprng);
+        let credentials = BakerCredentials::new(baker_id, keys);
+        let pending = pending_path(credentials_path);
+        write_credentials(&pending, &credentials)?;
+        let hash = self.update_keys(credentials.keys(), csprng).await?;
+        let (block, summary) = self.wait_until_finalized(&hash, poll_interval).await?;
+        let event = match summary.details {
+            BlockItemSummaryDetails::AccountTransaction(at) => match at.effects {
+                AccountTransactionEffects::BakerKeysUpdated { data } => *data,
+                AccountTransactionEffects::None { reject_reason, .. } => {
+                    fs::remove_file(&pending)?;
+                    return Err(BakerManagerError::Rejected(reject_reason));
+                }
+                _ => return Err(BakerManagerError::UnexpectedOutcome),
+            },
+            _ => return Err(BakerManagerError::UnexpectedOutcome),
+        };
+        install_pending_credentials(credentials_path, &credentials, &event)?;
+        Ok(KeyRotation {
+            transaction: hash,
+            block,
+            event,
+        })
+    }
+
+    /// Check that the keys of the baker in the last finalized block match the
+    /// keys in the credentials file.
+    pub async fn verify_credentials_file(
+        &mut self,
+        credentials_path: &Path,
+    ) -> Result<bool, BakerManagerError> {
+        let credentials = read_credentials(credentials_path)?;
+        let baker = match self.state().await?.baker {
+            Some(baker) => baker,
+            None => return Ok(false),
+        };
+        Ok(baker.baker_id == credentials.baker_id()
+            && keys_match(
+                &credentials,
+                &baker.baker_signature_verify_key,
+                &baker.baker_election_verify_key,
+                &baker.baker_aggregation_verify_key,
+            ))
+    }
+}
+
+#[derive(Debug)]
+/// The result of a successful key rotation.
+pub struct KeyRotation {
+    /// Hash of the key update transaction.
+    pub transaction: TransactionHash,
+    /// The block in which the key update was finalized.
+    pub block:       BlockHash,
+    /// The event recording the new keys on the chain.
+    pub event:       BakerKeysEvent,
+}
+
+/// Path of the file the new credentials are written to while the key update
+/// is in progress.
+pub fn pending_path(credentials_path: &Path) -> PathBuf {
+    append_extension(credentials_path, "pending")
+}
+
+/// Path of the backup of the previous credentials that is made when the keys
+/// are rotated.
+pub fn backup_path(credentials_path: &Path) -> PathBuf { append_extension(credentials_path, "bak") }
+
+/// Replace the credentials file by the pending credentials once the key update
+/// recorded in the event has been finalized. The current credentials file, if
+/// any, is backed up first. If the keys in the event do not match the
+/// credentials neither file is touched.
+fn install_pending_credentials(
+    credentials_path: &Path,
+    credentials: &BakerCredentials,
+    event: &BakerKeysEvent,
+) -> Result<(), BakerManagerError> {
+    let pending = pending_path(credentials_path);
+    if !verify_keys_event(event, credentials) {
+        return Err(BakerManagerError::KeysMismatch(pending));
+    }
+    if credentials_path.exists() {
+        let backup = backup_path(credentials_path);
+        fs::copy(credentials_path, &backup)?;
+        fs::File::open(&backup)?.sync_all()?;
+    }
+    fs::rename(&pending, credentials_path)?;
+    sync_parent(credentials_path)?;
+    Ok(())
+}
+
+fn append_extension(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// Make sure the directory entry of the file is persisted.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::File::open(parent)?.sync_all(),
+        _ => fs::File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> io::Result<()> { Ok(()) }
+
+/// Read baker credentials in the format used by the node.
+pub fn read_credentials(path: &Path) -> Result<BakerCredentials, BakerManagerError> {
+    let file = fs::File::open(path)?;
+    Ok(serde_json::from_reader(io::BufReader::new(file))?)
+}
+
+/// Write the credentials to the given file in the format used by the node. The
+/// file is replaced atomically, i.e., either the old or the new contents are
+/// in the file even if the process is interrupted. On unix the file is only
+/// readable by the owner since it contains secret keys.
+pub fn write_credentials(
+    path: &Path,
+    credentials: &BakerCredentials,
+) -> Result<(), BakerManagerError> {
+    let tmp = append_extension(path, "tmp");
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&tmp)?;
+    serde_json::to_writer_pretty(&mut file, credentials)?;
+    file.flush()?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    sync_parent(path)?;
+    Ok(())
+}
+
+fn keys_match(
+    credentials: &BakerCredentials,
+    sign_key: &crate::types::BakerSignatureVerifyKey,
+    election_key: &crate::types::BakerElectionVerifyKey,
+    aggregation_key: &crate::types::BakerAggregationVerifyKey,
+) -> bool {
+    use crypto_common::to_bytes;
+    let keys = credentials.keys();
+    to_bytes(&keys.signature_verify) == to_bytes(sign_key)
+        && to_bytes(&keys.election_verify) == to_bytes(election_key)
+        && to_bytes(&keys.aggregation_verify) == to_bytes(aggregation_key)
+}
+
+/// Check that the keys recorded on the chain by a key update match the
+/// credentials.
+pub fn verify_keys_event(event: &BakerKeysEvent, credentials: &BakerCredentials) -> bool {
+    event.baker_id == credentials.baker_id()
+        && keys_match(
+            credentials,
+            &event.sign_key,
+            &event.election_key,
+            &event.aggregation_key,
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(baker: Option<AccountBaker>) -> BakerState {
+        BakerState {
+            block: BlockHash::new([0u8; 32]),
+            balance: Amount { microgtu: 1000 },
+            baker,
+            pending_change: None,
+            threshold: Amount { microgtu: 100 },
+            cooldown: Epoch { epoch: 2 },
+        }
+    }
+
+    fn baker(pending_change: Option<BakerPendingChange>) -> AccountBaker {
+        let keys = BakerKeyPairs::generate(&mut rand::thread_rng());
+        AccountBaker {
+            staked_amount: Amount { microgtu: 500 },
+            restake_earnings: true,
+            baker_id: BakerId { id: 3 },
+            baker_election_verify_key: keys.election_verify,
+            baker_signature_verify_key: keys.signature_verify,
+            baker_aggregation_verify_key: keys.aggregation_verify,
+            pending_change,
+        }
+    }
+
+    fn cooldown() -> Option<BakerPendingChange> {
+        Some(BakerPendingChange::RemoveBaker {
+            epoch: Epoch { epoch: 10 },
+        })
+    }
+
+    /// A fresh directory for a test that is removed when the test succeeds.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "concordium-baker-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).expect("Cannot create test directory.");
+            TestDir(dir)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) { let _ = fs::remove_dir_all(&self.0); }
+    }
+
+    fn credentials(id: u64) -> BakerCredentials {
+        BakerCredentials::new(
+            BakerId { id },
+            BakerKeyPairs::generate(&mut rand::thread_rng()),
+        )
+    }
+
+    fn keys_event(credentials: &BakerCredentials) -> BakerKeysEvent {
+        let keys = credentials.keys();
+        BakerKeysEvent {
+            baker_id:        credentials.baker_id(),
+            account:         AccountAddress([1u8; 32]),
+            sign_key:        keys.signature_verify.clone(),
+            election_key:    keys.election_verify.clone(),
+            aggregation_key: keys.aggregation_verify.clone(),
+        }
+    }
+
+    #[test]
+    fn test_credentials_round_trip() {
+        let dir = TestDir::new("round-trip");
+        let path = dir.0.join("credentials.json");
+        let written = credentials(3);
+        write_credentials(&path, &written).expect("Cannot write credentials.");
+        let read = read_credentials(&path).expect("Cannot read credentials.");
+        assert_eq!(read.baker_id(), written.baker_id());
+        assert!(verify_keys_event(&keys_event(&written), &read));
+        assert!(!append_extension(&path, "tmp").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        assert_eq!(pending_path(&path), dir.0.join("credentials.json.pending"));
+        assert_eq!(backup_path(&path), dir.0.join("credentials.json.bak"));
+    }
+
+    #[test]
+    fn test_verify_keys_event() {
+        let creds = credentials(3);
+        assert!(verify_keys_event(&keys_event(&creds), &creds));
+        // Keys of another key pair.
+        assert!(!verify_keys_event(&keys_event(&credentials(3)), &creds));
+        // Same keys, but for another baker.
+        let mut event = keys_event(&creds);
+        event.baker_id = BakerId { id: 4 };
+        assert!(!verify_keys_event(&event, &creds));
+    }
+
+    #[test]
+    fn test_install_pending_credentials() {
+        let dir = TestDir::new("install");
+        let path = dir.0.join("credentials.json");
+        let pending = pending_path(&path);
+        let backup = backup_path(&path);
+        write_credentials(&path, &credentials(3)).unwrap();
+        let original = fs::read(&path).unwrap();
+        let new = credentials(3);
+        write_credentials(&pending, &new).unwrap();
+
+        // The chain recorded other keys: nothing is touched.
+        match install_pending_credentials(&path, &new, &keys_event(&credentials(3))) {
+            Err(BakerManagerError::KeysMismatch(p)) => assert_eq!(p, pending),
+            other => panic!("Expected a key mismatch, got {:?}.", other),
+        }
+        assert_eq!(fs::read(&path).unwrap(), original);
+        assert!(pending.exists());
+        assert!(!backup.exists());
+
+        // The chain recorded the new keys: the old file is backed up and
+        // replaced by the pending one.
+        install_pending_credentials(&path, &new, &keys_event(&new)).unwrap();
+        assert_eq!(fs::read(&backup).unwrap(), original);
+        assert!(!pending.exists());
+        assert!(verify_keys_event(
+            &keys_event(&new),
+            &read_credentials(&path).unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_check_add() {
+        let not_baker = state(None);
+        assert!(not_baker.check_add(Amount { microgtu: 100 }).is_ok());
+        assert!(not_baker.check_add(Amount { microgtu: 1000 }).is_ok());
+        assert!(matches!(
+            not_baker.check_add(Amount { microgtu: 99 }),
+            Err(BakerTransitionError::StakeUnderMinimumThresholdForBaking { .. })
+        ));
+        assert!(matches!(
+            not_baker.check_add(Amount { microgtu: 1001 }),
+            Err(BakerTransitionError::InsufficientBalanceForBakerStake { .. })
+        ));
+        assert!(matches!(
+            state(Some(baker(None))).check_add(Amount { microgtu: 500 }),
+            Err(BakerTransitionError::AlreadyABaker(BakerId { id: 3 }))
+        ));
+    }
+
+    #[test]
+    fn test_check_update_stake() {
+        assert!(matches!(
+            state(None).check_update_stake(Amount { microgtu: 500 }),
+            Err(BakerTransitionError::NotABaker)
+        ));
+        let active = state(Some(baker(None)));
+        assert!(active.check_update_stake(Amount { microgtu: 100 }).is_ok());
+        assert!(matches!(
+            active.check_update_stake(Amount { microgtu: 99 }),
+            Err(BakerTransitionError::StakeUnderMinimumThresholdForBaking { .. })
+        ));
+        assert!(matches!(
+            active.check_update_stake(Amount { microgtu: 1001 }),
+            Err(BakerTransitionError::InsufficientBalanceForBakerStake { .. })
+        ));
+        assert!(matches!(
+            state(Some(baker(cooldown()))).check_update_stake(Amount { microgtu: 500 }),
+            Err(BakerTransitionError::BakerInCooldown)
+        ));
+    }
+
+    #[test]
+    fn test_check_remove_and_update() {
+        assert!(matches!(
+            state(None).check_remove(),
+            Err(BakerTransitionError::NotABaker)
+        ));
+        assert!(matches!(
+            state(None).check_update(),
+            Err(BakerTransitionError::NotABaker)
+        ));
+        assert!(state(Some(baker(None))).check_remove().is_ok());
+        assert!(state(Some(baker(None))).check_update().is_ok());
+        // A baker in cooldown cannot be removed again, but can update its keys.
+        let in_cooldown = state(Some(baker(cooldown())));
+        assert!(matches!(
+            in_cooldown.check_remove(),
+            Err(BakerTransitionError::BakerInCooldown)
+        ));
+        assert!(in_cooldown.check_update().is_ok());
+    }
+
+    #[test]
+    fn test_reject_reason() {
+        let account = AccountAddress([1u8; 32]);
+        assert!(matches!(
+            BakerTransitionError::AlreadyABaker(BakerId { id: 3 }).reject_reason(account),
+            RejectReason::AlreadyABaker {
+                contents: BakerId { id: 3 },
+            }
+        ));
+        assert!(matches!(
+            BakerTransitionError::NotABaker.reject_reason(account),
+            RejectReason::NotABaker { contents } if contents == account
+        ));
+        assert!(matches!(
+            BakerTransitionError::BakerInCooldown.reject_reason(account),
+            RejectReason::BakerInCooldown
+        ));
+    }
+}
@@ -0,0 +1,109 @@
+//! Traversal of finalized blocks.
+//!
+//! Many analyses of the chain need to look at every finalized block in some
+//! range, together with the summary of what happened in the block. The
+//! [finalized_blocks] stream provides this, and optionally waits for new
+//! blocks to be finalized when it reaches the end of the chain.
+use crate::{
+    endpoints::{self, BlocksAtHeightInput, QueryError, QueryResult},
+    types::{hashes::BlockHash, queries::BlockInfo, AbsoluteBlockHeight, BlockSummary},
+};
+use futures::Stream;
+
+#[derive(Debug)]
+/// A finalized block together with its summary.
+pub struct FinalizedBlock {
+    /// Metadata about the block, such as its hash, height and slot time.
+    pub info:    BlockInfo,
+    /// Transactions and special outcomes of the block, as well as the chain
+    /// parameters in effect in the block.
+    pub summary: BlockSummary,
+}
+
+impl FinalizedBlock {
+    /// Get the block with the given height, which must be finalized. If it is
+    /// not [QueryError::NotFound] is returned.
+    pub async fn get(
+        client: &mut endpoints::Client,
+        height: AbsoluteBlockHeight,
+    ) -> QueryResult<Self> {
+        let bh = client.get_finalized_block_at_height(height).await?;
+        Self::get_by_hash(client, &bh).await
+    }
+
+    /// Get the block with the given hash. This does not check that the block is
+    /// finalized.
+    pub async fn get_by_hash(client: &mut endpoints::Client, bh: &BlockHash) -> QueryResult<Self> {
+        let info = client.get_block_info(bh).await?;
+        let summary = client.get_block_summary(bh).await?;
+        Ok(Self { info, summary })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Where a [finalized_blocks] stream ends.
+pub enum StreamEnd {
+    /// End after the block at the given height has been returned. If the block
+    /// is not yet finalized the stream waits for it.
+    AtHeight(AbsoluteBlockHeight),
+    /// End when reaching the last finalized block at the time the stream
+    /// reaches the end of the chain.
+    LastFinalized,
+    /// Never end. When the end of the chain is reached wait for new blocks to
+    /// be finalized.
+    Follow,
+}
+
+/// Return a stream of finalized blocks, starting at the given height, and
+/// ending as specified by `end`. Blocks are returned in order of increasing
+/// height. When waiting for new blocks to be finalized the stream checks for
+/// them every `poll_interval`.
+///
+/// The stream returns an error if any of the queries fail. It should not be
+/// polled after that, but a new stream can be started from the height
+/// following the last successfully returned block.
+pub fn finalized_blocks(
+    client: endpoints::Client,
+    start: AbsoluteBlockHeight,
+    end: StreamEnd,
+    poll_interval: std::time::Duration,
+) -> impl Stream<Item = QueryResult<FinalizedBlock>> {
+    futures::stream::try_unfold(
+        (client, start, None),
+        move |(mut client, height, mut last_finalized)| async move {
+            if let StreamEnd::AtHeight(stop_at) = end {
+                if height > stop_at {
+                    return Ok(None);
+                }
+            }
+            // Only ask for the consensus status when we reach the last known
+            // finalized block, to avoid an extra query for each block.
+            while last_finalized.map_or(true, |lf| height > lf) {
+                let lf = client
+                    .get_consensus_status()
+                    .await?
+                    .last_finalized_block_height;
+                last_finalized = Some(lf);
+                if height <= lf {
+                    break;
+                }
+                match end {
+                    StreamEnd::LastFinalized => return Ok(None),
+                    StreamEnd::AtHeight(_) | StreamEnd::Follow => {
+                        tokio::time::sleep(poll_interval).await
+                    }
+                }
+            }
+            // The block is known to be finalized, so there is exactly one block at
+            // this height.
+            let bh = client
+                .get_blocks_at_height(BlocksAtHeightInput::Absolute { height })
+                .await?
+                .into_iter()
+                .next()
+                .ok_or(QueryError::NotFound)?;
+            let block = FinalizedBlock::get_by_hash(&mut client, &bh).await?;
+            Ok::<_, QueryError>(Some((block, (client, height.next(), last_finalized))))
+        },
+    )
+}
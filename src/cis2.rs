@@ -0,0 +1,1048 @@
+//! Client for contracts implementing the CIS-2 token standard.
+//!
+//! The module provides construction of the parameters of the standard CIS-2
+//! entrypoints (`transfer`, `updateOperator`, `balanceOf` and
+//! `tokenMetadata`), parsing of the responses and of the standard events
+//! logged by CIS-2 contracts, and tracking of token balances by replaying
+//! these events from block summaries.
+//!
+//! All values are serialized as specified by the standard, which uses the
+//! little endian smart contract serialization, as opposed to the big endian
+//! serialization used for the rest of the chain data.
+use crate::{
+    blocks::{finalized_blocks, FinalizedBlock, StreamEnd},
+    constants::MAX_PARAMETER_LEN,
+    endpoints::{self, QueryError},
+    types::{
+        smart_contracts::{ContractEvent, Parameter, ReceiveName},
+        transactions::UpdateContractPayload,
+        AbsoluteBlockHeight, AccountTransactionEffects, Address, BlockItemSummary,
+        BlockItemSummaryDetails, ContractAddress, ContractIndex, ContractSubIndex,
+        ContractTraceElement,
+    },
+};
+use crypto_common::types::Amount;
+use futures::StreamExt;
+use id::types::AccountAddress;
+use num::{BigUint, ToPrimitive, Zero};
+use std::{
+    collections::BTreeMap,
+    convert::{TryFrom, TryInto},
+    fmt,
+    str::FromStr,
+};
+use thiserror::Error;
+
+/// Maximum number of bytes of a token amount in its LEB128 encoding. Token
+/// amounts are at most `2^256 - 1`.
+const MAX_TOKEN_AMOUNT_BYTES: usize = 37;
+
+/// Maximum length of an entrypoint name. Together with the contract name and
+/// the separating `.` it must fit into the 100 bytes of a receive name.
+const MAX_ENTRYPOINT_NAME_LEN: usize = 99;
+
+#[derive(Debug, Error)]
+/// Errors that can occur when building parameters or parsing CIS-2 data.
+pub enum Cis2Error {
+    #[error("Token ID is too long, it has {0} bytes, but at most 255 are allowed.")]
+    TokenIdTooLong(usize),
+    #[error("Token amount exceeds 2^256 - 1.")]
+    TokenAmountTooLarge,
+    #[error("The list has {0} elements, but at most 65535 are allowed.")]
+    ListTooLong(usize),
+    #[error("The data has {0} bytes, but at most 65535 are allowed.")]
+    DataTooLong(usize),
+    #[error(
+        "The parameter has {0} bytes, but at most {} are allowed.",
+        MAX_PARAMETER_LEN
+    )]
+    ParameterTooLarge(usize),
+    #[error("Invalid receive name: {0}")]
+    InvalidReceiveName(String),
+    #[error("Invalid entrypoint name: {0}")]
+    InvalidEntrypointName(String),
+    #[error("Unexpected end of input.")]
+    UnexpectedEnd,
+    #[error("Unexpected tag {0}.")]
+    InvalidTag(u8),
+    #[error("Invalid UTF8 in a string.")]
+    InvalidString,
+    #[error("{0} bytes of input remain after parsing.")]
+    TrailingBytes(usize),
+    #[error("Account {address:?} would have negative balance of token {token_id}.")]
+    InsufficientBalance {
+        token_id: TokenId,
+        address:  Address,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// Identifier of a token within a CIS-2 contract. This is a sequence of at
+/// most 255 bytes.
+pub struct TokenId {
+    bytes: Vec<u8>,
+}
+
+impl TryFrom<Vec<u8>> for TokenId {
+    type Error = Cis2Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
+        if bytes.len() > usize::from(u8::MAX) {
+            Err(Cis2Error::TokenIdTooLong(bytes.len()))
+        } else {
+            Ok(Self { bytes })
+        }
+    }
+}
+
+impl AsRef<[u8]> for TokenId {
+    fn as_ref(&self) -> &[u8] { &self.bytes }
+}
+
+/// Display the token ID as a hex string.
+impl fmt::Display for TokenId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(&self.bytes))
+    }
+}
+
+/// Parse the token ID from a hex string.
+impl FromStr for TokenId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> { Ok(Self::try_from(hex::decode(s)?)?) }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+/// An amount of a token. CIS-2 allows amounts up to `2^256 - 1`.
+pub struct TokenAmount(pub BigUint);
+
+impl From<u64> for TokenAmount {
+    fn from(n: u64) -> Self { Self(n.into()) }
+}
+
+impl fmt::Display for TokenAmount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}", self.0) }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Name of an entrypoint of a contract, without the contract name prefix,
+/// e.g., `onReceivingCIS2`. The name is at most 99 bytes of ASCII
+/// alphanumeric or punctuation characters.
+pub struct EntrypointName {
+    name: String,
+}
+
+impl TryFrom<String> for EntrypointName {
+    type Error = Cis2Error;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        if name.len() > MAX_ENTRYPOINT_NAME_LEN
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c.is_ascii_punctuation())
+        {
+            Err(Cis2Error::InvalidEntrypointName(name))
+        } else {
+            Ok(Self { name })
+        }
+    }
+}
+
+impl FromStr for EntrypointName {
+    type Err = Cis2Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> { Self::try_from(s.to_string()) }
+}
+
+impl EntrypointName {
+    /// The name as a string.
+    pub fn as_str(&self) -> &str { &self.name }
+}
+
+#[derive(Debug, Clone)]
+/// The receiver of a token transfer.
+pub enum Receiver {
+    /// An account receives the tokens.
+    Account(AccountAddress),
+    /// A contract receives the tokens. The given entrypoint of the contract is
+    /// invoked to notify it of the transfer.
+    Contract(ContractAddress, EntrypointName),
+}
+
+#[derive(Debug, Clone, Default)]
+/// Additional data that is passed along with a transfer to the receiver.
+pub struct AdditionalData {
+    bytes: Vec<u8>,
+}
+
+impl TryFrom<Vec<u8>> for AdditionalData {
+    type Error = Cis2Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
+        if bytes.len() > usize::from(u16::MAX) {
+            Err(Cis2Error::DataTooLong(bytes.len()))
+        } else {
+            Ok(Self { bytes })
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A single transfer of tokens.
+pub struct Transfer {
+    /// The token to transfer.
+    pub token_id: TokenId,
+    /// The amount of tokens to transfer.
+    pub amount:   TokenAmount,
+    /// The current owner of the tokens.
+    pub from:     Address,
+    /// The receiver of the tokens.
+    pub to:       Receiver,
+    /// Data passed to the receiver.
+    pub data:     AdditionalData,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Whether to add or remove an operator.
+pub enum OperatorUpdate {
+    Remove,
+    Add,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Update of an operator of the sender's tokens.
+pub struct UpdateOperator {
+    /// Whether to add or remove the operator.
+    pub update:   OperatorUpdate,
+    /// The address of the operator.
+    pub operator: Address,
+}
+
+#[derive(Debug, Clone)]
+/// A query for the balance of a token owned by an address.
+pub struct BalanceOfQuery {
+    /// The token to query.
+    pub token_id: TokenId,
+    /// The owner of the tokens.
+    pub address:  Address,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A URL for the metadata of a token, optionally with a SHA256 hash of the
+/// metadata.
+pub struct MetadataUrl {
+    /// The URL.
+    pub url:  String,
+    /// Hash of the content the URL points to.
+    pub hash: Option<[u8; 32]>,
+}
+
+#[derive(Debug, Clone)]
+/// The standard events logged by CIS-2 contracts.
+pub enum Event {
+    /// Tokens were transferred.
+    Transfer {
+        token_id: TokenId,
+        amount:   TokenAmount,
+        from:     Address,
+        to:       Address,
+    },
+    /// New tokens were created.
+    Mint {
+        token_id: TokenId,
+        amount:   TokenAmount,
+        owner:    Address,
+    },
+    /// Tokens were destroyed.
+    Burn {
+        token_id: TokenId,
+        amount:   TokenAmount,
+        owner:    Address,
+    },
+    /// An operator was added or removed.
+    UpdateOperator {
+        update:   OperatorUpdate,
+        owner:    Address,
+        operator: Address,
+    },
+    /// The metadata URL of a token was set.
+    TokenMetadata {
+        token_id:     TokenId,
+        metadata_url: MetadataUrl,
+    },
+    /// A custom event that is not defined by the standard. CIS-2 reserves
+    /// tags 251 to 255, all other tags can be used by contracts freely.
+    Unknown,
+}
+
+// Serialization helpers. These write in the little endian format used by smart
+// contracts.
+
+fn write_u16_len(out: &mut Vec<u8>, len: usize) -> Result<(), Cis2Error> {
+    let len = u16::try_from(len).map_err(|_| Cis2Error::ListTooLong(len))?;
+    out.extend_from_slice(&len.to_le_bytes());
+    Ok(())
+}
+
+fn write_token_id(out: &mut Vec<u8>, token_id: &TokenId) {
+    // the length is checked on construction of the token id.
+    out.push(token_id.bytes.len() as u8);
+    out.extend_from_slice(&token_id.bytes);
+}
+
+fn write_token_amount(out: &mut Vec<u8>, amount: &TokenAmount) -> Result<(), Cis2Error> {
+    if amount.0.bits() > 256 {
+        return Err(Cis2Error::TokenAmountTooLarge);
+    }
+    let mut value = amount.0.clone();
+    loop {
+        let byte = (&value % 128u32).to_u8().unwrap_or(0);
+        value >>= 7usize;
+        if value.is_zero() {
+            out.push(byte);
+            return Ok(());
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_contract_address(out: &mut Vec<u8>, addr: &ContractAddress) {
+    out.extend_from_slice(&u64::from(addr.index).to_le_bytes());
+    out.extend_from_slice(&u64::from(addr.subindex).to_le_bytes());
+}
+
+fn write_address(out: &mut Vec<u8>, addr: &Address) {
+    match addr {
+        Address::Account(acc) => {
+            out.push(0);
+            out.extend_from_slice(&acc.0);
+        }
+        Address::Contract(ca) => {
+            out.push(1);
+            write_contract_address(out, ca);
+        }
+    }
+}
+
+fn write_receiver(out: &mut Vec<u8>, receiver: &Receiver) -> Result<(), Cis2Error> {
+    match receiver {
+        Receiver::Account(acc) => {
+            out.push(0);
+            out.extend_from_slice(&acc.0);
+        }
+        Receiver::Contract(ca, hook) => {
+            out.push(1);
+            write_contract_address(out, ca);
+            write_u16_len(out, hook.name.len())?;
+            out.extend_from_slice(hook.name.as_bytes());
+        }
+    }
+    Ok(())
+}
+
+/// A helper for parsing data serialized in the smart contract format.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos:   usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self { Self { bytes, pos: 0 } }
+
+    fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], Cis2Error> {
+        let end = self.pos.checked_add(n).ok_or(Cis2Error::UnexpectedEnd)?;
+        let out = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or(Cis2Error::UnexpectedEnd)?;
+        self.pos = end;
+        Ok(out)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, Cis2Error> { Ok(self.read_bytes(1)?[0]) }
+
+    fn read_u16(&mut self) -> Result<u16, Cis2Error> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, Cis2Error> {
+        let bytes = self.read_bytes(8)?;
+        Ok(u64::from_le_bytes(
+            bytes.try_into().expect("Slice has length 8."),
+        ))
+    }
+
+    fn read_token_id(&mut self) -> Result<TokenId, Cis2Error> {
+        let len = self.read_u8()?;
+        let bytes = self.read_bytes(len.into())?.to_vec();
+        Ok(TokenId { bytes })
+    }
+
+    fn read_token_amount(&mut self) -> Result<TokenAmount, Cis2Error> {
+        let mut value = BigUint::zero();
+        for i in 0..MAX_TOKEN_AMOUNT_BYTES {
+            let byte = self.read_u8()?;
+            value |= BigUint::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                if value.bits() > 256 {
+                    return Err(Cis2Error::TokenAmountTooLarge);
+                }
+                return Ok(TokenAmount(value));
+            }
+        }
+        Err(Cis2Error::TokenAmountTooLarge)
+    }
+
+    fn read_contract_address(&mut self) -> Result<ContractAddress, Cis2Error> {
+        let index = self.read_u64()?;
+        let subindex = self.read_u64()?;
+        Ok(ContractAddress::new(
+            ContractIndex::from(index),
+            ContractSubIndex::from(subindex),
+        ))
+    }
+
+    fn read_address(&mut self) -> Result<Address, Cis2Error> {
+        match self.read_u8()? {
+            0 => {
+                let bytes: [u8; 32] = self
+                    .read_bytes(32)?
+                    .try_into()
+                    .expect("Slice has length 32.");
+                Ok(Address::Account(AccountAddress(bytes)))
+            }
+            1 => Ok(Address::Contract(self.read_contract_address()?)),
+            tag => Err(Cis2Error::InvalidTag(tag)),
+        }
+    }
+
+    fn read_string(&mut self) -> Result<String, Cis2Error> {
+        let len = self.read_u16()?;
+        let bytes = self.read_bytes(len.into())?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| Cis2Error::InvalidString)
+    }
+
+    fn read_metadata_url(&mut self) -> Result<MetadataUrl, Cis2Error> {
+        let url = self.read_string()?;
+        let hash = match self.read_u8()? {
+            0 => None,
+            1 => Some(
+                self.read_bytes(32)?
+                    .try_into()
+                    .expect("Slice has length 32."),
+            ),
+            tag => return Err(Cis2Error::InvalidTag(tag)),
+        };
+        Ok(MetadataUrl { url, hash })
+    }
+
+    fn finish(self) -> Result<(), Cis2Error> {
+        let remaining = self.bytes.len() - self.pos;
+        if remaining == 0 {
+            Ok(())
+        } else {
+            Err(Cis2Error::TrailingBytes(remaining))
+        }
+    }
+}
+
+impl Event {
+    /// Parse an event logged by a contract. Events with tags not defined by
+    /// the standard are returned as [Event::Unknown].
+    pub fn parse(event: &ContractEvent) -> Result<Self, Cis2Error> {
+        let bytes: &Vec<u8> = event.as_ref();
+        let mut reader = Reader::new(bytes);
+        let event = match reader.read_u8()? {
+            255 => Event::Transfer {
+                token_id: reader.read_token_id()?,
+                amount:   reader.read_token_amount()?,
+                from:     reader.read_address()?,
+                to:       reader.read_address()?,
+            },
+            254 => Event::Mint {
+                token_id: reader.read_token_id()?,
+                amount:   reader.read_token_amount()?,
+                owner:    reader.read_address()?,
+            },
+            253 => Event::Burn {
+                token_id: reader.read_token_id()?,
+                amount:   reader.read_token_amount()?,
+                owner:    reader.read_address()?,
+            },
+            252 => Event::UpdateOperator {
+                update:   match reader.read_u8()? {
+                    0 => OperatorUpdate::Remove,
+                    1 => OperatorUpdate::Add,
+                    tag => return Err(Cis2Error::InvalidTag(tag)),
+                },
+                owner:    reader.read_address()?,
+                operator: reader.read_address()?,
+            },
+            251 => Event::TokenMetadata {
+                token_id:     reader.read_token_id()?,
+                metadata_url: reader.read_metadata_url()?,
+            },
+            _ => return Ok(Event::Unknown),
+        };
+        reader.finish()?;
+        Ok(event)
+    }
+}
+
+/// Parse the response of the `balanceOf` entrypoint. The amounts are in the
+/// same order as the queries.
+pub fn parse_balance_of_response(bytes: &[u8]) -> Result<Vec<TokenAmount>, Cis2Error> {
+    let mut reader = Reader::new(bytes);
+    let len = reader.read_u16()?;
+    let amounts = (0..len)
+        .map(|_| reader.read_token_amount())
+        .collect::<Result<_, _>>()?;
+    reader.finish()?;
+    Ok(amounts)
+}
+
+/// Parse the response of the `tokenMetadata` entrypoint. The URLs are in the
+/// same order as the queried token IDs.
+pub fn parse_token_metadata_response(bytes: &[u8]) -> Result<Vec<MetadataUrl>, Cis2Error> {
+    let mut reader = Reader::new(bytes);
+    let len = reader.read_u16()?;
+    let urls = (0..len)
+        .map(|_| reader.read_metadata_url())
+        .collect::<Result<_, _>>()?;
+    reader.finish()?;
+    Ok(urls)
+}
+
+#[derive(Debug, Clone)]
+/// A CIS-2 contract instance. This is used to construct payloads for invoking
+/// the standard entrypoints.
+pub struct Cis2Contract {
+    /// Address of the contract instance.
+    pub address:       ContractAddress,
+    /// Name of the contract, i.e., the name of the init function without the
+    /// `init_` prefix.
+    pub contract_name: String,
+}
+
+impl Cis2Contract {
+    /// Construct a new client for the given contract instance.
+    pub fn new(address: ContractAddress, contract_name: impl Into<String>) -> Self {
+        Self {
+            address,
+            contract_name: contract_name.into(),
+        }
+    }
+
+    /// Construct the payload for invoking the given entrypoint with the given
+    /// parameter. The transaction sends no CCD to the contract.
+    fn make_payload(
+        &self,
+        entrypoint: &str,
+        parameter: Vec<u8>,
+    ) -> Result<UpdateContractPayload, Cis2Error> {
+        if parameter.len() > MAX_PARAMETER_LEN {
+            return Err(Cis2Error::ParameterTooLarge(parameter.len()));
+        }
+        let receive_name = ReceiveName::try_from(format!("{}.{}", self.contract_name, entrypoint))
+            .map_err(|e| Cis2Error::InvalidReceiveName(e.to_string()))?;
+        Ok(UpdateContractPayload {
+            amount: Amount { microgtu: 0 },
+            address: self.address,
+            receive_name,
+            message: Parameter::from(parameter),
+        })
+    }
+
+    /// Construct the payload for transferring tokens. The payload can be sent
+    /// using [send::update_contract](crate::types::transactions::send::update_contract).
+    pub fn transfer(&self, transfers: &[Transfer]) -> Result<UpdateContractPayload, Cis2Error> {
+        let mut parameter = Vec::new();
+        write_u16_len(&mut parameter, transfers.len())?;
+        for transfer in transfers {
+            write_token_id(&mut parameter, &transfer.token_id);
+            write_token_amount(&mut parameter, &transfer.amount)?;
+            write_address(&mut parameter, &transfer.from);
+            write_receiver(&mut parameter, &transfer.to)?;
+            write_u16_len(&mut parameter, transfer.data.bytes.len())?;
+            parameter.extend_from_slice(&transfer.data.bytes);
+        }
+        self.make_payload("transfer", parameter)
+    }
+
+    /// Construct the payload for adding or removing operators of the sender's
+    /// tokens.
+    pub fn update_operator(
+        &self,
+        updates: &[UpdateOperator],
+    ) -> Result<UpdateContractPayload, Cis2Error> {
+        let mut parameter = Vec::new();
+        write_u16_len(&mut parameter, updates.len())?;
+        for update in updates {
+            parameter.push(match update.update {
+                OperatorUpdate::Remove => 0,
+                OperatorUpdate::Add => 1,
+            });
+            write_address(&mut parameter, &update.operator);
+        }
+        self.make_payload("updateOperator", parameter)
+    }
+
+    /// Construct the payload for querying balances. The response can be parsed
+    /// with [parse_balance_of_response].
+    pub fn balance_of(
+        &self,
+        queries: &[BalanceOfQuery],
+    ) -> Result<UpdateContractPayload, Cis2Error> {
+        let mut parameter = Vec::new();
+        write_u16_len(&mut parameter, queries.len())?;
+        for query in queries {
+            write_token_id(&mut parameter, &query.token_id);
+            write_address(&mut parameter, &query.address);
+        }
+        self.make_payload("balanceOf", parameter)
+    }
+
+    /// Construct the payload for querying the metadata URLs of tokens. The
+    /// response can be parsed with [parse_token_metadata_response].
+    pub fn token_metadata(
+        &self,
+        token_ids: &[TokenId],
+    ) -> Result<UpdateContractPayload, Cis2Error> {
+        let mut parameter = Vec::new();
+        write_u16_len(&mut parameter, token_ids.len())?;
+        for token_id in token_ids {
+            write_token_id(&mut parameter, token_id);
+        }
+        self.make_payload("tokenMetadata", parameter)
+    }
+}
+
+/// Get the events logged by the given contract in the block item, in the
+/// order they were logged. Events that cannot be parsed are returned as
+/// errors.
+pub fn contract_events(
+    summary: &BlockItemSummary,
+    addr: ContractAddress,
+) -> Vec<Result<Event, Cis2Error>> {
+    let at = match &summary.details {
+        BlockItemSummaryDetails::AccountTransaction(at) => at,
+        _ => return Vec::new(),
+    };
+    match &at.effects {
+        AccountTransactionEffects::ContractInitialized { data } if data.address == addr => {
+            data.events.iter().map(Event::parse).collect()
+        }
+        AccountTransactionEffects::ContractUpdateIssued { effects } => effects
+            .iter()
+            .filter_map(|effect| match effect {
+                ContractTraceElement::Updated { data } if data.address == addr => {
+                    Some(&data.events)
+                }
+                _ => None,
+            })
+            .flatten()
+            .map(Event::parse)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[derive(Debug, Default)]
+/// Token balances of a CIS-2 contract, per token and holder, obtained by
+/// replaying the events logged by the contract. For the balances to be correct
+/// the events must be replayed from the creation of the contract.
+pub struct TokenBalances {
+    /// Balances of each holder, per token. Holders with zero balance are
+    /// removed.
+    pub balances: BTreeMap<TokenId, BTreeMap<Address, TokenAmount>>,
+    /// The latest metadata URL of each token.
+    pub metadata: BTreeMap<TokenId, MetadataUrl>,
+}
+
+impl TokenBalances {
+    /// Get the balance of the token held by the address.
+    pub fn balance_of(&self, token_id: &TokenId, address: &Address) -> TokenAmount {
+        self.balances
+            .get(token_id)
+            .and_then(|holders| holders.get(address))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Get the total supply of the token.
+    pub fn total_supply(&self, token_id: &TokenId) -> TokenAmount {
+        TokenAmount(
+            self.balances
+                .get(token_id)
+                .map(|holders| holders.values().map(|amount| &amount.0).sum())
+                .unwrap_or_default(),
+        )
+    }
+
+    fn credit(&mut self, token_id: &TokenId, address: Address, amount: &TokenAmount) {
+        // Zero amounts must not add holders with zero balance.
+        if amount.0.is_zero() {
+            return;
+        }
+        let balance = self
+            .balances
+            .entry(token_id.clone())
+            .or_default()
+            .entry(address)
+            .or_default();
+        balance.0 += &amount.0;
+    }
+
+    fn debit(
+        &mut self,
+        token_id: &TokenId,
+        address: Address,
+        amount: &TokenAmount,
+    ) -> Result<(), Cis2Error> {
+        // The standard allows transferring zero tokens from any address.
+        if amount.0.is_zero() {
+            return Ok(());
+        }
+        let insufficient = || Cis2Error::InsufficientBalance {
+            token_id: token_id.clone(),
+            address,
+        };
+        let holders = self.balances.get_mut(token_id).ok_or_else(insufficient)?;
+        let balance = holders.get_mut(&address).ok_or_else(insufficient)?;
+        if balance.0 < amount.0 {
+            return Err(insufficient());
+        }
+        balance.0 -= &amount.0;
+        if balance.0.is_zero() {
+            holders.remove(&address);
+        }
+        Ok(())
+    }
+
+    /// Update the balances according to the event. An error is returned if a
+    /// holder would end up with a negative balance, which indicates that not
+    /// all events were replayed.
+    pub fn apply_event(&mut self, event: &Event) -> Result<(), Cis2Error> {
+        match event {
+            Event::Transfer {
+                token_id,
+                amount,
+                from,
+                to,
+            } => {
+                self.debit(token_id, *from, amount)?;
+                self.credit(token_id, *to, amount);
+            }
+            Event::Mint {
+                token_id,
+                amount,
+                owner,
+            } => self.credit(token_id, *owner, amount),
+            Event::Burn {
+                token_id,
+                amount,
+                owner,
+            } => self.debit(token_id, *owner, amount)?,
+            Event::TokenMetadata {
+                token_id,
+                metadata_url,
+            } => {
+                self.metadata.insert(token_id.clone(), metadata_url.clone());
+            }
+            Event::UpdateOperator { .. } | Event::Unknown => (),
+        }
+        Ok(())
+    }
+
+    /// Apply all the events logged by the contract in the block item.
+    pub fn apply_summary(
+        &mut self,
+        summary: &BlockItemSummary,
+        addr: ContractAddress,
+    ) -> Result<(), Cis2Error> {
+        for event in contract_events(summary, addr) {
+            self.apply_event(&event?)?;
+        }
+        Ok(())
+    }
+
+    /// Apply all the events logged by the contract in the block.
+    pub fn apply_block(
+        &mut self,
+        block: &FinalizedBlock,
+        addr: ContractAddress,
+    ) -> Result<(), Cis2Error> {
+        for summary in &block.summary.transaction_summaries {
+            self.apply_summary(summary, addr)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+/// Errors that can occur when replaying the events of a contract.
+pub enum ReplayError {
+    #[error("Query error: {0}")]
+    Query(#[from] QueryError),
+    #[error("Error processing events: {0}")]
+    Cis2(#[from] Cis2Error),
+}
+
+/// Compute token balances by replaying the events of the contract in all
+/// finalized blocks in the given range of heights, both ends inclusive. To get
+/// correct balances `from` must be at most the height of the block in which
+/// the contract was created.
+pub async fn replay_balances(
+    client: endpoints::Client,
+    addr: ContractAddress,
+    from: AbsoluteBlockHeight,
+    to: AbsoluteBlockHeight,
+) -> Result<TokenBalances, ReplayError> {
+    let mut balances = TokenBalances::default();
+    let mut blocks = Box::pin(finalized_blocks(
+        client,
+        from,
+        StreamEnd::AtHeight(to),
+        std::time::Duration::from_secs(1),
+    ));
+    while let Some(block) = blocks.next().await {
+        balances.apply_block(&block?, addr)?;
+    }
+    Ok(balances)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(byte: u8) -> AccountAddress { AccountAddress([byte; 32]) }
+
+    fn contract_event(bytes: Vec<u8>) -> ContractEvent {
+        serde_json::from_value(serde_json::Value::String(hex::encode(bytes)))
+            .expect("Events are hex strings.")
+    }
+
+    fn token(byte: u8) -> TokenId { TokenId::try_from(vec![byte]).expect("Token ID is short.") }
+
+    #[test]
+    fn test_transfer_parameter() {
+        let contract = Cis2Contract::new(ContractAddress::new(7.into(), 0.into()), "token");
+        let transfer = Transfer {
+            token_id: token(0x2a),
+            amount:   TokenAmount::from(300),
+            from:     Address::Account(account(1)),
+            to:       Receiver::Contract(
+                ContractAddress::new(3.into(), 1.into()),
+                "onReceivingCIS2".parse().expect("Valid entrypoint name."),
+            ),
+            data:     AdditionalData::try_from(vec![0xab, 0xcd]).expect("Data is short."),
+        };
+        let payload = contract.transfer(&[transfer]).expect("Valid transfer.");
+        let name: &str = (&payload.receive_name).into();
+        assert_eq!(name, "token.transfer");
+
+        let mut expected = hex::decode("0100012aac0200").expect("Valid hex.");
+        expected.extend_from_slice(&[1; 32]);
+        expected.extend(
+            hex::decode(concat!(
+                "01",
+                "0300000000000000",
+                "0100000000000000",
+                "0f00",
+                "6f6e526563656976696e6743495332",
+                "0200abcd"
+            ))
+            .expect("Valid hex."),
+        );
+        let parameter: &Vec<u8> = payload.message.as_ref();
+        assert_eq!(parameter, &expected);
+    }
+
+    #[test]
+    fn test_entrypoint_name() {
+        assert!("onReceivingCIS2".parse::<EntrypointName>().is_ok());
+        assert!("with space".parse::<EntrypointName>().is_err());
+        assert!("a".repeat(100).parse::<EntrypointName>().is_err());
+    }
+
+    #[test]
+    fn test_token_amount_encoding() {
+        let mut out = Vec::new();
+        write_token_amount(&mut out, &TokenAmount::from(300)).expect("Amount is in range.");
+        assert_eq!(out, vec![0xac, 0x02]);
+
+        let max = TokenAmount((BigUint::from(1u8) << 256usize) - 1u8);
+        let mut out = Vec::new();
+        write_token_amount(&mut out, &max).expect("Amount is in range.");
+        assert_eq!(out.len(), MAX_TOKEN_AMOUNT_BYTES);
+        assert_eq!(
+            Reader::new(&out)
+                .read_token_amount()
+                .expect("Valid amount."),
+            max
+        );
+
+        let too_large = TokenAmount(BigUint::from(1u8) << 256usize);
+        assert!(write_token_amount(&mut Vec::new(), &too_large).is_err());
+        let mut out = vec![0xff; MAX_TOKEN_AMOUNT_BYTES - 1];
+        out.push(0x7f);
+        assert!(matches!(
+            Reader::new(&out).read_token_amount(),
+            Err(Cis2Error::TokenAmountTooLarge)
+        ));
+    }
+
+    #[test]
+    fn test_parse_events() {
+        let mut transfer = vec![255, 1, 5, 0xac, 0x02, 0];
+        transfer.extend_from_slice(&[1; 32]);
+        transfer.push(1);
+        transfer.extend_from_slice(&[3, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+        match Event::parse(&contract_event(transfer.clone())).expect("Valid event.") {
+            Event::Transfer {
+                token_id,
+                amount,
+                from,
+                to,
+            } => {
+                assert_eq!(token_id, token(5));
+                assert_eq!(amount, TokenAmount::from(300));
+                assert_eq!(from, Address::Account(account(1)));
+                assert_eq!(
+                    to,
+                    Address::Contract(ContractAddress::new(3.into(), 1.into()))
+                );
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+        transfer.push(0);
+        assert!(matches!(
+            Event::parse(&contract_event(transfer)),
+            Err(Cis2Error::TrailingBytes(1))
+        ));
+
+        let mut mint = vec![254, 1, 5, 0x80, 0x01, 0];
+        mint.extend_from_slice(&[2; 32]);
+        assert!(matches!(
+            Event::parse(&contract_event(mint)).expect("Valid event."),
+            Event::Mint { amount, owner, .. }
+                if amount == TokenAmount::from(128) && owner == Address::Account(account(2))
+        ));
+
+        let mut burn = vec![253, 0, 0, 0];
+        burn.extend_from_slice(&[2; 32]);
+        assert!(matches!(
+            Event::parse(&contract_event(burn)).expect("Valid event."),
+            Event::Burn { token_id, amount, .. }
+                if token_id.as_ref().is_empty() && amount == TokenAmount::from(0)
+        ));
+
+        let mut update_operator = vec![252, 1, 0];
+        update_operator.extend_from_slice(&[2; 32]);
+        update_operator.push(0);
+        update_operator.extend_from_slice(&[3; 32]);
+        assert!(matches!(
+            Event::parse(&contract_event(update_operator)).expect("Valid event."),
+            Event::UpdateOperator { update: OperatorUpdate::Add, operator, .. }
+                if operator == Address::Account(account(3))
+        ));
+
+        let mut metadata = vec![251, 1, 5, 3, 0];
+        metadata.extend_from_slice(b"url");
+        metadata.push(1);
+        metadata.extend_from_slice(&[9; 32]);
+        match Event::parse(&contract_event(metadata)).expect("Valid event.") {
+            Event::TokenMetadata {
+                token_id,
+                metadata_url,
+            } => {
+                assert_eq!(token_id, token(5));
+                assert_eq!(metadata_url, MetadataUrl {
+                    url:  "url".into(),
+                    hash: Some([9; 32]),
+                });
+            }
+            event => panic!("Unexpected event {:?}", event),
+        }
+
+        assert!(matches!(
+            Event::parse(&contract_event(vec![0, 1, 2])).expect("Custom events are allowed."),
+            Event::Unknown
+        ));
+        assert!(matches!(
+            Event::parse(&contract_event(vec![252, 2])),
+            Err(Cis2Error::InvalidTag(2))
+        ));
+        assert!(matches!(
+            Event::parse(&contract_event(vec![255, 1])),
+            Err(Cis2Error::UnexpectedEnd)
+        ));
+    }
+
+    #[test]
+    fn test_balance_replay() {
+        let id = token(1);
+        let a = Address::Account(account(1));
+        let b = Address::Account(account(2));
+        let c = Address::Contract(ContractAddress::new(3.into(), 0.into()));
+        let mut balances = TokenBalances::default();
+        let events = vec![
+            Event::Mint {
+                token_id: id.clone(),
+                amount:   TokenAmount::from(100),
+                owner:    a,
+            },
+            Event::Transfer {
+                token_id: id.clone(),
+                amount:   TokenAmount::from(30),
+                from:     a,
+                to:       b,
+            },
+            // Zero amounts are allowed from and to addresses without tokens.
+            Event::Transfer {
+                token_id: id.clone(),
+                amount:   TokenAmount::from(0),
+                from:     c,
+                to:       c,
+            },
+            Event::Mint {
+                token_id: token(2),
+                amount:   TokenAmount::from(0),
+                owner:    c,
+            },
+            Event::Burn {
+                token_id: token(2),
+                amount:   TokenAmount::from(0),
+                owner:    c,
+            },
+            Event::Burn {
+                token_id: id.clone(),
+                amount:   TokenAmount::from(30),
+                owner:    b,
+            },
+        ];
+        for event in &events {
+            balances.apply_event(event).expect("Balances suffice.");
+        }
+        assert_eq!(balances.balance_of(&id, &a), TokenAmount::from(70));
+        assert_eq!(balances.balance_of(&id, &b), TokenAmount::from(0));
+        assert_eq!(balances.total_supply(&id), TokenAmount::from(70));
+        // Holders with zero balance are not kept.
+        assert_eq!(balances.balances[&id].len(), 1);
+        assert!(!balances.balances.contains_key(&token(2)));
+
+        let overdraft = Event::Transfer {
+            token_id: id.clone(),
+            amount:   TokenAmount::from(71),
+            from:     a,
+            to:       b,
+        };
+        assert!(matches!(
+            balances.apply_event(&overdraft),
+            Err(Cis2Error::InsufficientBalance { address, .. }) if address == a
+        ));
+        assert_eq!(balances.balance_of(&id, &a), TokenAmount::from(70));
+    }
+}
@@ -0,0 +1,342 @@
+//! Conversion between slots, epochs and wall-clock time.
+//!
+//! The chain is divided into eras, one for each protocol update. Slots and
+//! epochs are numbered from the start of each era, starting at the slot time
+//! of the era's genesis block. Converting between slots and time thus requires
+//! knowing the era. The [ChainClock] records the start of every era and
+//! performs the conversions.
+//!
+//! The slot and epoch durations are assumed to be the same in all eras, which
+//! is the case for all protocol versions so far.
+use crate::{
+    endpoints::{self, BlocksAtHeightInput, QueryError},
+    types::{BlockHeight, Epoch, GenesisIndex, Slot, SlotDuration},
+};
+use chrono::{DateTime, TimeZone, Utc};
+use crypto_common::types::TransactionTime;
+use std::convert::TryFrom;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+/// Reasons why a [ChainClock] cannot be constructed.
+pub enum ClockError {
+    #[error("There must be at least one era.")]
+    /// No era start times were given.
+    NoEras,
+    #[error("The slot duration must be positive.")]
+    /// The slot duration is zero.
+    ZeroSlotDuration,
+    #[error("The epoch duration must be at least one slot.")]
+    /// The epoch is shorter than a slot.
+    InvalidEpochDuration,
+    #[error("Error querying the node: {0}")]
+    /// Querying the node for the parameters of the chain failed.
+    Query(#[from] QueryError),
+}
+
+#[derive(Debug, Clone)]
+/// Converts between slots, epochs and time in every era of the chain.
+pub struct ChainClock {
+    /// Duration of a slot in milliseconds.
+    slot_millis:  u64,
+    /// Number of slots in an epoch.
+    epoch_length: u64,
+    /// Slot time of the genesis block of each era, indexed by genesis index.
+    era_starts:   Vec<DateTime<Utc>>,
+}
+
+impl ChainClock {
+    /// Construct a clock from the slot and epoch durations and the start
+    /// times of all the eras, in order of genesis index. There must be at least
+    /// one era, and the epoch duration must be a positive multiple of the slot
+    /// duration.
+    pub fn new(
+        slot_duration: SlotDuration,
+        epoch_duration: chrono::Duration,
+        era_starts: Vec<DateTime<Utc>>,
+    ) -> Result<Self, ClockError> {
+        if era_starts.is_empty() {
+            return Err(ClockError::NoEras);
+        }
+        if slot_duration.millis == 0 {
+            return Err(ClockError::ZeroSlotDuration);
+        }
+        let epoch_millis = u64::try_from(epoch_duration.num_milliseconds())
+            .map_err(|_| ClockError::InvalidEpochDuration)?;
+        let epoch_length = epoch_millis / slot_duration.millis;
+        if epoch_length == 0 {
+            return Err(ClockError::InvalidEpochDuration);
+        }
+        Ok(Self {
+            slot_millis: slot_duration.millis,
+            epoch_length,
+            era_starts,
+        })
+    }
+
+    /// Construct a clock with the parameters of the chain the node is on. The
+    /// start of each era is the slot time of its genesis block.
+    pub async fn from_client(client: &mut endpoints::Client) -> Result<Self, ClockError> {
+        let consensus_info = client.get_consensus_status().await?;
+        let mut era_starts = vec![consensus_info.genesis_time];
+        for index in 1..consensus_info.genesis_index.height {
+            let bh = client
+                .get_blocks_at_height(BlocksAtHeightInput::Relative {
+                    genesis_index: GenesisIndex { height: index },
+                    height:        BlockHeight { height: 0 },
+                    restrict:      true,
+                })
+                .await?
+                .into_iter()
+                .next()
+                .ok_or(QueryError::NotFound)?;
+            era_starts.push(client.get_block_info(&bh).await?.block_slot_time);
+        }
+        if consensus_info.genesis_index.height > 0 {
+            era_starts.push(consensus_info.current_era_genesis_time);
+        }
+        Self::new(
+            consensus_info.slot_duration,
+            consensus_info.epoch_duration,
+            era_starts,
+        )
+    }
+
+    /// Number of slots in an epoch.
+    pub fn epoch_length(&self) -> u64 { self.epoch_length }
+
+    /// Duration of a slot.
+    pub fn slot_duration(&self) -> chrono::Duration {
+        chrono::Duration::milliseconds(self.slot_millis as i64)
+    }
+
+    /// The latest era known to the clock.
+    pub fn current_era(&self) -> GenesisIndex {
+        GenesisIndex {
+            height: (self.era_starts.len() - 1) as u32,
+        }
+    }
+
+    /// The time the era started, if the era is known.
+    pub fn era_start(&self, era: GenesisIndex) -> Option<DateTime<Utc>> {
+        self.era_starts.get(era.height as usize).copied()
+    }
+
+    /// The era that the given time belongs to. `None` if the time is before
+    /// the start of the chain.
+    pub fn era_at(&self, time: DateTime<Utc>) -> Option<GenesisIndex> {
+        let index = self.era_starts.iter().rposition(|start| *start <= time)?;
+        Some(GenesisIndex {
+            height: index as u32,
+        })
+    }
+
+    /// The time at which the slot of the given era starts.
+    pub fn slot_time(&self, era: GenesisIndex, slot: Slot) -> Option<DateTime<Utc>> {
+        let offset = chrono::Duration::milliseconds((slot.slot * self.slot_millis) as i64);
+        Some(self.era_start(era)? + offset)
+    }
+
+    /// The slot of the given era that contains the time. `None` if the era is
+    /// not known or the time is before the start of the era.
+    pub fn slot_in_era(&self, era: GenesisIndex, time: DateTime<Utc>) -> Option<Slot> {
+        let since_start = (time - self.era_start(era)?).num_milliseconds();
+        if since_start < 0 {
+            return None;
+        }
+        Some(Slot {
+            slot: since_start as u64 / self.slot_millis,
+        })
+    }
+
+    /// The era and the slot in it that contain the time.
+    pub fn slot_at(&self, time: DateTime<Utc>) -> Option<(GenesisIndex, Slot)> {
+        let era = self.era_at(time)?;
+        Some((era, self.slot_in_era(era, time)?))
+    }
+
+    /// The epoch the slot belongs to.
+    pub fn epoch_of_slot(&self, slot: Slot) -> Epoch {
+        Epoch {
+            epoch: slot.slot / self.epoch_length,
+        }
+    }
+
+    /// The first slot of the epoch.
+    pub fn first_slot(&self, epoch: Epoch) -> Slot {
+        Slot {
+            slot: epoch.epoch * self.epoch_length,
+        }
+    }
+
+    /// The last slot of the epoch.
+    pub fn last_slot(&self, epoch: Epoch) -> Slot {
+        Slot {
+            slot: (epoch.epoch + 1) * self.epoch_length - 1,
+        }
+    }
+
+    /// The time at which the epoch of the given era starts.
+    pub fn epoch_start(&self, era: GenesisIndex, epoch: Epoch) -> Option<DateTime<Utc>> {
+        self.slot_time(era, self.first_slot(epoch))
+    }
+
+    /// The era and the epoch in it that contain the time.
+    pub fn epoch_at(&self, time: DateTime<Utc>) -> Option<(GenesisIndex, Epoch)> {
+        let (era, slot) = self.slot_at(time)?;
+        Some((era, self.epoch_of_slot(slot)))
+    }
+
+    /// The first epoch that starts strictly after the given time, together
+    /// with its start time. Since future protocol updates are not known to the
+    /// clock the epoch is assumed to be in the era containing the time.
+    pub fn next_epoch_boundary(
+        &self,
+        time: DateTime<Utc>,
+    ) -> Option<(GenesisIndex, Epoch, DateTime<Utc>)> {
+        let (era, epoch) = self.epoch_at(time)?;
+        let next = Epoch {
+            epoch: epoch.epoch + 1,
+        };
+        Some((era, next, self.epoch_start(era, next)?))
+    }
+
+    /// Convert a transaction time, which has a resolution of seconds, to a
+    /// date. `None` if the time is out of the range of representable dates.
+    pub fn transaction_time_to_date(&self, time: TransactionTime) -> Option<DateTime<Utc>> {
+        Utc.timestamp_opt(i64::try_from(time.seconds).ok()?, 0)
+            .single()
+    }
+
+    /// Convert a date to a transaction time, rounding down to whole seconds.
+    /// Dates before 1970 are mapped to time 0.
+    pub fn date_to_transaction_time(&self, time: DateTime<Utc>) -> TransactionTime {
+        TransactionTime::from_seconds(time.timestamp().max(0) as u64)
+    }
+
+    /// The slot of the given era that contains the transaction time.
+    pub fn transaction_time_to_slot(
+        &self,
+        era: GenesisIndex,
+        time: TransactionTime,
+    ) -> Option<Slot> {
+        self.slot_in_era(era, self.transaction_time_to_date(time)?)
+    }
+
+    /// The transaction time at which the slot of the given era starts, rounded
+    /// down to whole seconds.
+    pub fn slot_to_transaction_time(
+        &self,
+        era: GenesisIndex,
+        slot: Slot,
+    ) -> Option<TransactionTime> {
+        Some(self.date_to_transaction_time(self.slot_time(era, slot)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> DateTime<Utc> { s.parse().expect("Valid date.") }
+
+    /// A clock with 250ms slots, 1 hour epochs, and two eras.
+    fn test_clock() -> ChainClock {
+        ChainClock::new(
+            SlotDuration { millis: 250 },
+            chrono::Duration::hours(1),
+            vec![date("2021-06-09T00:00:00Z"), date("2021-12-01T12:00:00Z")],
+        )
+        .expect("Valid parameters.")
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        let slot = SlotDuration { millis: 250 };
+        let hour = chrono::Duration::hours(1);
+        let eras = vec![date("2021-06-09T00:00:00Z")];
+        assert!(matches!(
+            ChainClock::new(slot, hour, Vec::new()),
+            Err(ClockError::NoEras)
+        ));
+        assert!(matches!(
+            ChainClock::new(SlotDuration { millis: 0 }, hour, eras.clone()),
+            Err(ClockError::ZeroSlotDuration)
+        ));
+        assert!(matches!(
+            ChainClock::new(slot, chrono::Duration::milliseconds(249), eras.clone()),
+            Err(ClockError::InvalidEpochDuration)
+        ));
+        assert!(matches!(
+            ChainClock::new(slot, chrono::Duration::hours(-1), eras.clone()),
+            Err(ClockError::InvalidEpochDuration)
+        ));
+        assert!(ChainClock::new(slot, chrono::Duration::milliseconds(250), eras).is_ok());
+    }
+
+    #[test]
+    fn test_slot_conversions() {
+        let clock = test_clock();
+        assert_eq!(clock.epoch_length(), 14400);
+        let era0 = GenesisIndex { height: 0 };
+        let era1 = GenesisIndex { height: 1 };
+
+        let time = date("2021-06-09T01:00:00.500Z");
+        assert_eq!(clock.slot_at(time), Some((era0, Slot { slot: 14402 })));
+        assert_eq!(clock.epoch_at(time), Some((era0, Epoch { epoch: 1 })));
+        assert_eq!(
+            clock.slot_time(era0, Slot { slot: 14402 }),
+            Some(date("2021-06-09T01:00:00.500Z"))
+        );
+
+        // Slots are numbered from the start of each era.
+        let time = date("2021-12-01T12:00:01.999Z");
+        assert_eq!(clock.slot_at(time), Some((era1, Slot { slot: 7 })));
+        assert!(clock.slot_in_era(era0, time).is_some());
+        assert_eq!(clock.slot_in_era(era1, date("2021-11-01T00:00:00Z")), None);
+        assert_eq!(clock.slot_at(date("2021-01-01T00:00:00Z")), None);
+        assert_eq!(clock.era_start(GenesisIndex { height: 2 }), None);
+    }
+
+    #[test]
+    fn test_epoch_boundaries() {
+        let clock = test_clock();
+        let era1 = GenesisIndex { height: 1 };
+        assert_eq!(clock.first_slot(Epoch { epoch: 2 }), Slot { slot: 28800 });
+        assert_eq!(clock.last_slot(Epoch { epoch: 2 }), Slot { slot: 43199 });
+
+        let time = date("2021-12-01T14:30:00Z");
+        assert_eq!(
+            clock.next_epoch_boundary(time),
+            Some((era1, Epoch { epoch: 3 }, date("2021-12-01T15:00:00Z")))
+        );
+        // A time exactly on a boundary belongs to the epoch starting there.
+        let time = date("2021-12-01T15:00:00Z");
+        assert_eq!(clock.epoch_at(time), Some((era1, Epoch { epoch: 3 })));
+        assert_eq!(
+            clock.next_epoch_boundary(time).map(|(_, epoch, _)| epoch),
+            Some(Epoch { epoch: 4 })
+        );
+    }
+
+    #[test]
+    fn test_transaction_time() {
+        let clock = test_clock();
+        let era0 = GenesisIndex { height: 0 };
+        let time = date("2021-06-09T00:00:10.750Z");
+        let tt = clock.date_to_transaction_time(time);
+        assert_eq!(tt.seconds, time.timestamp() as u64);
+        assert_eq!(
+            clock.transaction_time_to_date(tt),
+            Some(date("2021-06-09T00:00:10Z"))
+        );
+        assert_eq!(
+            clock.transaction_time_to_slot(era0, tt),
+            Some(Slot { slot: 40 })
+        );
+        assert_eq!(
+            clock.slot_to_transaction_time(era0, Slot { slot: 43 }),
+            Some(tt)
+        );
+    }
+}
@@ -0,0 +1,500 @@
+//! Following the activity of a single smart contract instance directly from
+//! the node, without the need for the transaction index in Postgres.
+//!
+//! The [contract_activity] stream walks finalized blocks and returns every
+//! transaction that touched the given contract instance, i.e., initialized
+//! it, updated it, made it transfer CCD, or tried to invoke it and was
+//! rejected.
+use crate::{
+    blocks::{finalized_blocks, FinalizedBlock, StreamEnd},
+    endpoints::{self, QueryResult},
+    types::{
+        hashes::{BlockHash, TransactionHash},
+        smart_contracts::{ContractEvent, InitName, ModuleRef, Parameter, ReceiveName},
+        AbsoluteBlockHeight, AccountTransactionEffects, Address, BlockItemSummary,
+        BlockItemSummaryDetails, ContractAddress, ContractTraceElement, RejectReason,
+        TransactionIndex,
+    },
+};
+use concordium_contracts_common::schema;
+use crypto_common::types::Amount;
+use futures::{Stream, StreamExt, TryStreamExt};
+use id::types::AccountAddress;
+
+#[derive(Debug, Clone, Copy)]
+/// Position in the chain from which a [contract_activity] stream starts. All
+/// transactions in the block at `height` with index at least `index` are
+/// included, followed by all transactions in subsequent blocks.
+pub struct ActivityCursor {
+    /// Height of the block to start at.
+    pub height: AbsoluteBlockHeight,
+    /// Index of the first transaction in the block to consider.
+    pub index:  u64,
+}
+
+impl From<AbsoluteBlockHeight> for ActivityCursor {
+    fn from(height: AbsoluteBlockHeight) -> Self { Self { height, index: 0 } }
+}
+
+#[derive(Debug, Clone)]
+/// A transaction that touched the contract instance.
+pub struct ContractActivity {
+    /// Hash of the block the transaction is in.
+    pub block_hash:      BlockHash,
+    /// Height of the block the transaction is in.
+    pub block_height:    AbsoluteBlockHeight,
+    /// Slot time of the block the transaction is in.
+    pub block_slot_time: chrono::DateTime<chrono::Utc>,
+    /// Hash of the transaction.
+    pub hash:            TransactionHash,
+    /// Index of the transaction in the block.
+    pub index:           TransactionIndex,
+    /// Sender of the transaction.
+    pub sender:          AccountAddress,
+    /// The list of effects on the contract, in the order they happened. This is
+    /// always non-empty.
+    pub events:          Vec<ContractActivityKind>,
+}
+
+impl ContractActivity {
+    /// The cursor pointing just after this transaction. Starting a new stream
+    /// from this cursor continues with the next transaction.
+    pub fn cursor(&self) -> ActivityCursor {
+        ActivityCursor {
+            height: self.block_height,
+            index:  self.index.index + 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// An individual effect on the contract instance.
+pub enum ContractActivityKind {
+    /// The instance was created.
+    Initialized {
+        /// Module the instance was created from.
+        origin_ref: ModuleRef,
+        /// Name of the init function.
+        init_name:  InitName,
+        /// The amount the instance was initialized with.
+        amount:     Amount,
+        /// Events logged by the init function.
+        events:     Vec<ContractEvent>,
+    },
+    /// An entrypoint of the instance was invoked.
+    Updated {
+        /// Who invoked the entrypoint. Either an account or another contract.
+        instigator:        Address,
+        /// The full name of the receive function, `<contract>.<entrypoint>`.
+        receive_name:      ReceiveName,
+        /// The amount the entrypoint was invoked with.
+        amount:            Amount,
+        /// The parameter the entrypoint was invoked with.
+        parameter:         Parameter,
+        /// The parameter decoded with the contract schema, if a schema was
+        /// supplied and it describes the parameter of the entrypoint.
+        decoded_parameter: Option<serde_json::Value>,
+        /// Events logged by the entrypoint.
+        events:            Vec<ContractEvent>,
+    },
+    /// The instance transferred CCD to an account.
+    Transferred {
+        /// The amount that was transferred.
+        amount: Amount,
+        /// The receiving account.
+        to:     AccountAddress,
+    },
+    /// A transaction that tried to invoke the instance was rejected.
+    Rejected {
+        /// The receive function that rejected, if known.
+        receive_name:      Option<ReceiveName>,
+        /// The parameter the contract was invoked with, if known.
+        parameter:         Option<Parameter>,
+        /// The parameter decoded with the contract schema, if known and a
+        /// schema was supplied.
+        decoded_parameter: Option<serde_json::Value>,
+        /// The reason the transaction was rejected.
+        reject_reason:     RejectReason,
+    },
+}
+
+/// Decode the parameter of the given receive function, if the schema describes
+/// it.
+fn decode_parameter(
+    contract_schema: Option<&schema::Contract>,
+    receive_name: &ReceiveName,
+    parameter: &Parameter,
+) -> Option<serde_json::Value> {
+    let ty = contract_schema?
+        .receive
+        .get(receive_name.entrypoint_name())?;
+    let bytes: &Vec<u8> = parameter.as_ref();
+    ty.to_json(&mut std::io::Cursor::new(bytes)).ok()
+}
+
+/// Extract the effects on the given contract from the summary of a block
+/// item. Returns `None` if the block item did not touch the contract.
+///
+/// This does not use [BlockItemSummary::affected_contracts] since that only
+/// lists the addresses of initialized and updated instances. It omits rejected
+/// transactions, and transfers made by an instance, and it does not give the
+/// details of each effect.
+///
+/// Rejected transactions are only attributed to the contract if the rejection
+/// reason mentions the contract address. In particular transactions that ran
+/// out of energy, or failed at runtime, are not included since the rejection
+/// reason does not record which contract was invoked.
+pub fn contract_effects(
+    summary: &BlockItemSummary,
+    addr: ContractAddress,
+    contract_schema: Option<&schema::Contract>,
+) -> Option<(AccountAddress, Vec<ContractActivityKind>)> {
+    let at = match &summary.details {
+        BlockItemSummaryDetails::AccountTransaction(at) => at,
+        BlockItemSummaryDetails::AccountCreation(_) | BlockItemSummaryDetails::Update(_) => {
+            return None
+        }
+    };
+    let events = match &at.effects {
+        AccountTransactionEffects::ContractInitialized { data } if data.address == addr => {
+            vec![ContractActivityKind::Initialized {
+                origin_ref: data.origin_ref,
+                init_name:  data.init_name.clone(),
+                amount:     data.amount,
+                events:     data.events.clone(),
+            }]
+        }
+        AccountTransactionEffects::ContractUpdateIssued { effects } => effects
+            .iter()
+            .filter_map(|effect| match effect {
+                ContractTraceElement::Updated { data } if data.address == addr => {
+                    Some(ContractActivityKind::Updated {
+                        instigator:        data.instigator,
+                        receive_name:      data.receive_name.clone(),
+                        amount:            data.amount,
+                        parameter:         data.message.clone(),
+                        decoded_parameter: decode_parameter(
+                            contract_schema,
+                            &data.receive_name,
+                            &data.message,
+                        ),
+                        events:            data.events.clone(),
+                    })
+                }
+                ContractTraceElement::Transferred { from, amount, to } if *from == addr => {
+                    Some(ContractActivityKind::Transferred {
+                        amount: *amount,
+                        to:     *to,
+                    })
+                }
+                _ => None,
+            })
+            .collect(),
+        AccountTransactionEffects::None { reject_reason, .. } => {
+            let (receive_name, parameter) = match reject_reason {
+                RejectReason::RejectedReceive {
+                    contract_address,
+                    receive_name,
+                    parameter,
+                    ..
+                } if *contract_address == addr => {
+                    (Some(receive_name.clone()), Some(parameter.clone()))
+                }
+                RejectReason::InvalidContractAddress { contents } if *contents == addr => {
+                    (None, None)
+                }
+                RejectReason::AmountTooLarge {
+                    contents: (Address::Contract(from), _),
+                } if *from == addr => (None, None),
+                _ => return None,
+            };
+            let decoded_parameter = match (&receive_name, &parameter) {
+                (Some(rn), Some(param)) => decode_parameter(contract_schema, rn, param),
+                _ => None,
+            };
+            vec![ContractActivityKind::Rejected {
+                receive_name,
+                parameter,
+                decoded_parameter,
+                reject_reason: reject_reason.clone(),
+            }]
+        }
+        _ => return None,
+    };
+    if events.is_empty() {
+        None
+    } else {
+        Some((at.sender, events))
+    }
+}
+
+/// Extract all the transactions in the block that touched the contract,
+/// skipping those with index smaller than `min_index`.
+fn block_activities(
+    block: FinalizedBlock,
+    addr: ContractAddress,
+    contract_schema: Option<&schema::Contract>,
+    min_index: u64,
+) -> Vec<ContractActivity> {
+    let info = block.info;
+    block
+        .summary
+        .transaction_summaries
+        .iter()
+        .filter(|summary| summary.index.index >= min_index)
+        .filter_map(|summary| {
+            let (sender, events) = contract_effects(summary, addr, contract_schema)?;
+            Some(ContractActivity {
+                block_hash: info.block_hash,
+                block_height: info.block_height,
+                block_slot_time: info.block_slot_time,
+                hash: summary.hash,
+                index: summary.index,
+                sender,
+                events,
+            })
+        })
+        .collect()
+}
+
+/// Return a stream of all the transactions that touched the given contract
+/// instance, starting at the given cursor. The stream follows the chain,
+/// waiting for new finalized blocks every `poll_interval` once it reaches the
+/// last finalized block.
+///
+/// If `contract_schema` is given it is used to decode parameters of the
+/// entrypoints it describes.
+///
+/// To resume after an interruption start a new stream from the
+/// [cursor](ContractActivity::cursor) of the last processed activity.
+pub fn contract_activity(
+    client: endpoints::Client,
+    addr: ContractAddress,
+    start: ActivityCursor,
+    contract_schema: Option<schema::Contract>,
+    poll_interval: std::time::Duration,
+) -> impl Stream<Item = QueryResult<ContractActivity>> {
+    finalized_blocks(client, start.height, StreamEnd::Follow, poll_interval)
+        .map_ok(move |block| {
+            let min_index = if block.info.block_height == start.height {
+                start.index
+            } else {
+                0
+            };
+            let activities = block_activities(block, addr, contract_schema.as_ref(), min_index);
+            futures::stream::iter(activities.into_iter().map(Ok))
+        })
+        .try_flatten()
+}
+
+/// Get all the transactions in the given range of blocks (both ends inclusive)
+/// that touched the contract.
+pub async fn contract_activity_in_range(
+    client: endpoints::Client,
+    addr: ContractAddress,
+    from: AbsoluteBlockHeight,
+    to: AbsoluteBlockHeight,
+    contract_schema: Option<&schema::Contract>,
+) -> QueryResult<Vec<ContractActivity>> {
+    let mut blocks = Box::pin(finalized_blocks(
+        client,
+        from,
+        StreamEnd::AtHeight(to),
+        std::time::Duration::from_secs(1),
+    ));
+    let mut out = Vec::new();
+    while let Some(block) = blocks.next().await {
+        out.extend(block_activities(block?, addr, contract_schema, 0));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        AccountTransactionDetails, ContractInitializedEvent, Energy, InstanceUpdatedEvent,
+    };
+    use std::{collections::BTreeMap, convert::TryFrom};
+
+    fn account(byte: u8) -> AccountAddress { AccountAddress([byte; 32]) }
+
+    fn contract(index: u64) -> ContractAddress { ContractAddress::new(index.into(), 0.into()) }
+
+    fn ccd(microgtu: u64) -> Amount { Amount { microgtu } }
+
+    fn receive_name(name: &str) -> ReceiveName { ReceiveName { name: name.into() } }
+
+    fn summary(effects: AccountTransactionEffects) -> BlockItemSummary {
+        BlockItemSummary {
+            index:       TransactionIndex { index: 0 },
+            energy_cost: Energy { energy: 500 },
+            hash:        TransactionHash::new([1; 32]),
+            details:     BlockItemSummaryDetails::AccountTransaction(AccountTransactionDetails {
+                cost: ccd(5),
+                sender: account(1),
+                effects,
+            }),
+        }
+    }
+
+    fn updated(address: ContractAddress, instigator: Address, name: &str) -> ContractTraceElement {
+        ContractTraceElement::Updated {
+            data: InstanceUpdatedEvent {
+                address,
+                instigator,
+                amount: ccd(10),
+                message: Parameter::from(vec![1, 0, 0, 0]),
+                receive_name: receive_name(name),
+                events: Vec::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_initialized() {
+        let init = summary(AccountTransactionEffects::ContractInitialized {
+            data: ContractInitializedEvent {
+                origin_ref: ModuleRef::new([2; 32]),
+                address:    contract(3),
+                amount:     ccd(7),
+                init_name:  InitName::try_from(String::from("init_counter"))
+                    .expect("Valid init name."),
+                events:     Vec::new(),
+            },
+        });
+        let (sender, events) = contract_effects(&init, contract(3), None).expect("Touches 3.");
+        assert_eq!(sender, account(1));
+        assert!(matches!(
+            events.as_slice(),
+            [ContractActivityKind::Initialized { amount, .. }] if *amount == ccd(7)
+        ));
+        assert!(contract_effects(&init, contract(4), None).is_none());
+    }
+
+    #[test]
+    fn test_updated() {
+        let update = summary(AccountTransactionEffects::ContractUpdateIssued {
+            effects: vec![updated(
+                contract(3),
+                Address::Account(account(1)),
+                "counter.inc",
+            )],
+        });
+        let mut receive = BTreeMap::new();
+        receive.insert(String::from("inc"), schema::Type::U32);
+        let contract_schema = schema::Contract {
+            state: None,
+            init: None,
+            receive,
+        };
+        let (_, events) =
+            contract_effects(&update, contract(3), Some(&contract_schema)).expect("Touches 3.");
+        match events.as_slice() {
+            [ContractActivityKind::Updated {
+                instigator,
+                receive_name,
+                decoded_parameter,
+                ..
+            }] => {
+                assert_eq!(*instigator, Address::Account(account(1)));
+                assert_eq!(receive_name.entrypoint_name(), "inc");
+                assert_eq!(*decoded_parameter, Some(serde_json::json!(1)));
+            }
+            other => panic!("Unexpected events: {:?}", other),
+        }
+        // Without a schema the parameter is not decoded.
+        let (_, events) = contract_effects(&update, contract(3), None).expect("Touches 3.");
+        assert!(matches!(events.as_slice(), [
+            ContractActivityKind::Updated {
+                decoded_parameter: None,
+                ..
+            }
+        ]));
+    }
+
+    #[test]
+    fn test_nested_calls() {
+        // Contract 3 calls contract 4, which pays an account and calls back
+        // into contract 3, which then pays another account.
+        let update = summary(AccountTransactionEffects::ContractUpdateIssued {
+            effects: vec![
+                updated(contract(4), Address::Contract(contract(3)), "other.pay"),
+                ContractTraceElement::Transferred {
+                    from:   contract(4),
+                    amount: ccd(1),
+                    to:     account(2),
+                },
+                updated(
+                    contract(3),
+                    Address::Contract(contract(4)),
+                    "counter.callback",
+                ),
+                ContractTraceElement::Transferred {
+                    from:   contract(3),
+                    amount: ccd(2),
+                    to:     account(5),
+                },
+                updated(contract(3), Address::Account(account(1)), "counter.inc"),
+            ],
+        });
+        let (_, events) = contract_effects(&update, contract(3), None).expect("Touches 3.");
+        assert!(matches!(events.as_slice(), [
+            ContractActivityKind::Updated {
+                instigator: Address::Contract(_),
+                ..
+            },
+            ContractActivityKind::Transferred { to, .. },
+            ContractActivityKind::Updated {
+                instigator: Address::Account(_),
+                ..
+            }
+        ] if *to == account(5)));
+        let (_, events) = contract_effects(&update, contract(4), None).expect("Touches 4.");
+        assert!(matches!(events.as_slice(), [
+            ContractActivityKind::Updated { .. },
+            ContractActivityKind::Transferred { amount, .. }
+        ] if *amount == ccd(1)));
+        assert!(contract_effects(&update, contract(5), None).is_none());
+    }
+
+    #[test]
+    fn test_rejected() {
+        let rejected = |reject_reason| {
+            summary(AccountTransactionEffects::None {
+                transaction_type: None,
+                reject_reason,
+            })
+        };
+        let receive = rejected(RejectReason::RejectedReceive {
+            reject_reason:    -1,
+            contract_address: contract(3),
+            receive_name:     receive_name("counter.inc"),
+            parameter:        Parameter::from(Vec::new()),
+        });
+        let (_, events) = contract_effects(&receive, contract(3), None).expect("Touches 3.");
+        assert!(matches!(
+            events.as_slice(),
+            [ContractActivityKind::Rejected {
+                receive_name: Some(name),
+                ..
+            }] if name.entrypoint_name() == "inc"
+        ));
+        assert!(contract_effects(&receive, contract(4), None).is_none());
+
+        let invalid = rejected(RejectReason::InvalidContractAddress {
+            contents: contract(3),
+        });
+        assert!(matches!(
+            contract_effects(&invalid, contract(3), None),
+            Some((_, events)) if matches!(events.as_slice(), [ContractActivityKind::Rejected {
+                receive_name: None,
+                ..
+            }])
+        ));
+
+        // The reason does not say which contract ran out of energy.
+        let out_of_energy = rejected(RejectReason::OutOfEnergy);
+        assert!(contract_effects(&out_of_energy, contract(3), None).is_none());
+    }
+}
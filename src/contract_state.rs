@@ -0,0 +1,471 @@
+//! Decoding of the state of smart contract instances.
+//!
+//! The state of a V0 smart contract instance is returned by
+//! [Client::get_instance_info](crate::endpoints::Client::get_instance_info) as
+//! raw bytes in [InstanceInfo::model]. Given a schema for the state, either
+//! embedded in the module the instance was created from or supplied by the
+//! user, the functionality in this module decodes the state into JSON, and
+//! compares the decoded state of an instance at two different blocks.
+use crate::{
+    endpoints::{self, QueryError},
+    types::{hashes::BlockHash, smart_contracts::InstanceInfo, ContractAddress},
+};
+use concordium_contracts_common::{from_bytes, schema};
+use std::{collections::BTreeSet, io::Cursor};
+use thiserror::Error;
+
+/// Name of the Wasm custom section in which the module schema is embedded.
+pub const SCHEMA_SECTION_NAME: &str = "concordium-schema-v1";
+
+/// The magic bytes every Wasm module starts with.
+const WASM_MAGIC: [u8; 4] = [0x00, 0x61, 0x73, 0x6D];
+
+#[derive(Debug, Error)]
+/// Errors that can occur when obtaining a schema or decoding the state of an
+/// instance.
+pub enum StateDecodeError {
+    #[error("Malformed Wasm module: {0}")]
+    /// The module source is not a well-formed Wasm module.
+    MalformedModule(&'static str),
+    #[error("The module does not contain an embedded schema.")]
+    /// The module does not have the schema custom section.
+    NoEmbeddedSchema,
+    #[error("The embedded schema could not be parsed.")]
+    /// The schema custom section is present, but its contents are malformed.
+    InvalidSchema,
+    #[error("The schema does not contain the contract '{0}'.")]
+    /// The schema does not have an entry for the requested contract.
+    UnknownContract(String),
+    #[error("The schema of contract '{0}' does not describe its state.")]
+    /// The schema has an entry for the contract, but without a state schema.
+    NoStateSchema(String),
+    #[error("The state does not match the schema.")]
+    /// The state bytes could not be parsed according to the schema.
+    StateMismatch,
+    #[error("{0} bytes of state remain after parsing according to the schema.")]
+    /// The state was parsed, but not all of the bytes were consumed.
+    TrailingBytes(u64),
+    #[error("Query error: {0}")]
+    /// Querying the node failed.
+    Query(#[from] QueryError),
+}
+
+#[derive(Debug, Clone)]
+/// Schema describing the state of a specific contract.
+pub struct StateSchema {
+    ty: schema::Type,
+}
+
+impl From<schema::Type> for StateSchema {
+    fn from(ty: schema::Type) -> Self { Self { ty } }
+}
+
+impl StateSchema {
+    /// Construct the state schema of the given contract from a module schema.
+    pub fn from_module_schema(
+        module_schema: &schema::Module,
+        contract_name: &str,
+    ) -> Result<Self, StateDecodeError> {
+        let contract = module_schema
+            .contracts
+            .get(contract_name)
+            .ok_or_else(|| StateDecodeError::UnknownContract(contract_name.into()))?;
+        let ty = contract
+            .state
+            .clone()
+            .ok_or_else(|| StateDecodeError::NoStateSchema(contract_name.into()))?;
+        Ok(Self { ty })
+    }
+
+    /// Construct the state schema of the given contract from the schema
+    /// embedded in the module source, as returned by
+    /// [Client::get_module_source](endpoints::Client::get_module_source).
+    pub fn from_module_source(
+        source: &[u8],
+        contract_name: &str,
+    ) -> Result<Self, StateDecodeError> {
+        let module_schema = get_embedded_schema(source)?;
+        Self::from_module_schema(&module_schema, contract_name)
+    }
+
+    /// Decode the given state bytes into JSON. All of the bytes must be
+    /// consumed by the schema.
+    pub fn decode(&self, state: &[u8]) -> Result<serde_json::Value, StateDecodeError> {
+        let mut cursor = Cursor::new(state);
+        let value = self
+            .ty
+            .to_json(&mut cursor)
+            .map_err(|_| StateDecodeError::StateMismatch)?;
+        let remaining = state.len() as u64 - cursor.position();
+        if remaining != 0 {
+            return Err(StateDecodeError::TrailingBytes(remaining));
+        }
+        Ok(value)
+    }
+
+    /// Decode the state of the given instance into JSON.
+    pub fn decode_instance(
+        &self,
+        instance: &InstanceInfo,
+    ) -> Result<serde_json::Value, StateDecodeError> {
+        self.decode(&instance.model)
+    }
+}
+
+/// Extract and parse the schema embedded in the module source. The source is
+/// either a bare Wasm module, or a module prefixed by its version and length,
+/// which is how the node returns it.
+pub fn get_embedded_schema(source: &[u8]) -> Result<schema::Module, StateDecodeError> {
+    let wasm = strip_module_version(source)?;
+    let section = find_custom_section(wasm, SCHEMA_SECTION_NAME)?
+        .ok_or(StateDecodeError::NoEmbeddedSchema)?;
+    from_bytes::<schema::Module>(section).map_err(|_| StateDecodeError::InvalidSchema)
+}
+
+/// Return the Wasm module bytes, removing the version and length prefix if
+/// present.
+fn strip_module_version(source: &[u8]) -> Result<&[u8], StateDecodeError> {
+    if source.starts_with(&WASM_MAGIC) {
+        return Ok(source);
+    }
+    // 4 bytes of version followed by 4 bytes of length, both big endian.
+    if source.len() < 8 {
+        return Err(StateDecodeError::MalformedModule("Module is too short."));
+    }
+    let mut len_bytes = [0u8; 4];
+    len_bytes.copy_from_slice(&source[4..8]);
+    let len = u32::from_be_bytes(len_bytes) as usize;
+    let wasm = &source[8..];
+    if wasm.len() != len || !wasm.starts_with(&WASM_MAGIC) {
+        return Err(StateDecodeError::MalformedModule(
+            "Module is neither a Wasm module nor a versioned Wasm module.",
+        ));
+    }
+    Ok(wasm)
+}
+
+/// Read an unsigned LEB128 encoded 32-bit integer, advancing the position.
+fn read_leb128_u32(bytes: &[u8], pos: &mut usize) -> Result<u32, StateDecodeError> {
+    let mut result: u32 = 0;
+    for i in 0..5 {
+        let byte = *bytes.get(*pos).ok_or(StateDecodeError::MalformedModule(
+            "Unexpected end of module.",
+        ))?;
+        *pos += 1;
+        result |= u32::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+    }
+    Err(StateDecodeError::MalformedModule("Invalid LEB128 integer."))
+}
+
+/// Find the contents of the first custom section with the given name.
+fn find_custom_section<'a>(
+    wasm: &'a [u8],
+    name: &str,
+) -> Result<Option<&'a [u8]>, StateDecodeError> {
+    // skip the magic and the version
+    let mut pos = 8;
+    if wasm.len() < pos {
+        return Err(StateDecodeError::MalformedModule("Module is too short."));
+    }
+    while pos < wasm.len() {
+        let section_id = wasm[pos];
+        pos += 1;
+        let size = read_leb128_u32(wasm, &mut pos)? as usize;
+        let end = pos
+            .checked_add(size)
+            .filter(|&end| end <= wasm.len())
+            .ok_or(StateDecodeError::MalformedModule("Section exceeds module."))?;
+        if section_id == 0 {
+            let mut name_pos = pos;
+            let name_len = read_leb128_u32(wasm, &mut name_pos)? as usize;
+            let name_end = name_pos
+                .checked_add(name_len)
+                .filter(|&name_end| name_end <= end)
+                .ok_or(StateDecodeError::MalformedModule(
+                    "Custom section name exceeds section.",
+                ))?;
+            if &wasm[name_pos..name_end] == name.as_bytes() {
+                return Ok(Some(&wasm[name_end..end]));
+            }
+        }
+        pos = end;
+    }
+    Ok(None)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A single difference between two decoded states. The path is a JSON pointer
+/// (RFC 6901) to the value that changed.
+pub enum StateChange {
+    /// A value was added at the given path.
+    Added {
+        path:  String,
+        value: serde_json::Value,
+    },
+    /// A value was removed from the given path.
+    Removed {
+        path:  String,
+        value: serde_json::Value,
+    },
+    /// The value at the given path changed.
+    Modified {
+        path: String,
+        old:  serde_json::Value,
+        new:  serde_json::Value,
+    },
+}
+
+/// Compute the list of changes from the `old` to the `new` value. Objects are
+/// compared key by key and arrays element by element, all other values are
+/// compared for equality. The changes are listed in the order of the paths.
+pub fn diff_states(old: &serde_json::Value, new: &serde_json::Value) -> Vec<StateChange> {
+    let mut changes = Vec::new();
+    diff_worker(String::new(), old, new, &mut changes);
+    changes
+}
+
+/// Escape a key for use in a JSON pointer.
+fn escape_pointer_key(key: &str) -> String { key.replace('~', "~0").replace('/', "~1") }
+
+fn diff_worker(
+    path: String,
+    old: &serde_json::Value,
+    new: &serde_json::Value,
+    changes: &mut Vec<StateChange>,
+) {
+    use serde_json::Value;
+    match (old, new) {
+        (Value::Object(old_map), Value::Object(new_map)) => {
+            let keys = old_map
+                .keys()
+                .chain(new_map.keys())
+                .collect::<BTreeSet<_>>();
+            for key in keys {
+                let path = format!("{}/{}", path, escape_pointer_key(key));
+                match (old_map.get(key), new_map.get(key)) {
+                    (Some(o), Some(n)) => diff_worker(path, o, n, changes),
+                    (Some(o), None) => changes.push(StateChange::Removed {
+                        path,
+                        value: o.clone(),
+                    }),
+                    (None, Some(n)) => changes.push(StateChange::Added {
+                        path,
+                        value: n.clone(),
+                    }),
+                    (None, None) => unreachable!("The key comes from one of the maps."),
+                }
+            }
+        }
+        (Value::Array(old_arr), Value::Array(new_arr)) => {
+            for i in 0..std::cmp::max(old_arr.len(), new_arr.len()) {
+                let path = format!("{}/{}", path, i);
+                match (old_arr.get(i), new_arr.get(i)) {
+                    (Some(o), Some(n)) => diff_worker(path, o, n, changes),
+                    (Some(o), None) => changes.push(StateChange::Removed {
+                        path,
+                        value: o.clone(),
+                    }),
+                    (None, Some(n)) => changes.push(StateChange::Added {
+                        path,
+                        value: n.clone(),
+                    }),
+                    (None, None) => unreachable!("The index is in range of one of the arrays."),
+                }
+            }
+        }
+        (o, n) => {
+            if o != n {
+                changes.push(StateChange::Modified {
+                    path,
+                    old: o.clone(),
+                    new: n.clone(),
+                })
+            }
+        }
+    }
+}
+
+/// Get the state schema of the given instance from the schema embedded in the
+/// module the instance was created from.
+pub async fn get_embedded_state_schema(
+    client: &mut endpoints::Client,
+    addr: ContractAddress,
+    bh: &BlockHash,
+) -> Result<StateSchema, StateDecodeError> {
+    let info = client.get_instance_info(addr, bh).await?;
+    let source = client.get_module_source(&info.source_module, bh).await?;
+    StateSchema::from_module_source(&source, info.name.contract_name())
+}
+
+/// Get the decoded state of the instance in the given block. If `schema` is
+/// `None` the schema embedded in the instance's module is used.
+pub async fn get_instance_state(
+    client: &mut endpoints::Client,
+    addr: ContractAddress,
+    bh: &BlockHash,
+    schema: Option<&StateSchema>,
+) -> Result<serde_json::Value, StateDecodeError> {
+    let info = client.get_instance_info(addr, bh).await?;
+    match schema {
+        Some(schema) => schema.decode_instance(&info),
+        None => {
+            let source = client.get_module_source(&info.source_module, bh).await?;
+            StateSchema::from_module_source(&source, info.name.contract_name())?
+                .decode_instance(&info)
+        }
+    }
+}
+
+/// Compare the decoded state of the instance in the `before` block with the
+/// decoded state in the `after` block. If `schema` is `None` the state in each
+/// block is decoded with the schema embedded in the module the instance uses
+/// in that block, so that the diff is correct across an upgrade of the
+/// instance. A given `schema` is used for both blocks.
+pub async fn diff_instance_state(
+    client: &mut endpoints::Client,
+    addr: ContractAddress,
+    before: &BlockHash,
+    after: &BlockHash,
+    schema: Option<&StateSchema>,
+) -> Result<Vec<StateChange>, StateDecodeError> {
+    let old = get_instance_state(client, addr, before, schema).await?;
+    let new = get_instance_state(client, addr, after, schema).await?;
+    Ok(diff_states(&old, &new))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use concordium_contracts_common::to_bytes;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    fn leb128(mut value: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte);
+                return out;
+            }
+            out.push(byte | 0x80);
+        }
+    }
+
+    fn custom_section(name: &str, contents: &[u8]) -> Vec<u8> {
+        let mut body = leb128(name.len());
+        body.extend_from_slice(name.as_bytes());
+        body.extend_from_slice(contents);
+        let mut section = vec![0u8];
+        section.extend(leb128(body.len()));
+        section.extend(body);
+        section
+    }
+
+    /// A module with a type section, a large custom section whose size needs
+    /// several LEB128 bytes, and the given sections.
+    fn module(sections: &[Vec<u8>]) -> Vec<u8> {
+        let mut wasm = WASM_MAGIC.to_vec();
+        wasm.extend_from_slice(&[1, 0, 0, 0]);
+        wasm.extend_from_slice(&[1, 1, 0]);
+        wasm.extend(custom_section("padding", &[0xff; 300]));
+        for section in sections {
+            wasm.extend_from_slice(section);
+        }
+        wasm
+    }
+
+    fn test_schema() -> schema::Module {
+        let mut contracts = BTreeMap::new();
+        contracts.insert("counter".to_string(), schema::Contract {
+            state:   Some(schema::Type::U32),
+            init:    None,
+            receive: BTreeMap::new(),
+        });
+        schema::Module { contracts }
+    }
+
+    #[test]
+    fn test_embedded_schema() {
+        let wasm = module(&[custom_section(
+            SCHEMA_SECTION_NAME,
+            &to_bytes(&test_schema()),
+        )]);
+        let schema =
+            StateSchema::from_module_source(&wasm, "counter").expect("Schema is embedded.");
+        assert_eq!(
+            schema.decode(&[17, 0, 0, 0]).expect("State matches."),
+            json!(17)
+        );
+        assert!(matches!(
+            schema.decode(&[17, 0, 0, 0, 0]),
+            Err(StateDecodeError::TrailingBytes(1))
+        ));
+        assert!(matches!(
+            StateSchema::from_module_source(&wasm, "other"),
+            Err(StateDecodeError::UnknownContract(_))
+        ));
+
+        // The node returns the module prefixed by its version and length.
+        let mut versioned = vec![0, 0, 0, 0];
+        versioned.extend_from_slice(&(wasm.len() as u32).to_be_bytes());
+        versioned.extend_from_slice(&wasm);
+        let module_schema = get_embedded_schema(&versioned).expect("Schema is embedded.");
+        assert!(module_schema.contracts.contains_key("counter"));
+    }
+
+    #[test]
+    fn test_missing_or_malformed_schema() {
+        let wasm = module(&[]);
+        assert!(matches!(
+            get_embedded_schema(&wasm),
+            Err(StateDecodeError::NoEmbeddedSchema)
+        ));
+        let wasm = module(&[custom_section(SCHEMA_SECTION_NAME, &[1, 2, 3])]);
+        assert!(matches!(
+            get_embedded_schema(&wasm),
+            Err(StateDecodeError::InvalidSchema)
+        ));
+        // A section that claims to be longer than the module.
+        let mut wasm = module(&[]);
+        wasm.extend_from_slice(&[0, 0x80, 0x01]);
+        assert!(matches!(
+            get_embedded_schema(&wasm),
+            Err(StateDecodeError::MalformedModule(_))
+        ));
+        assert!(matches!(
+            get_embedded_schema(&[1, 2, 3]),
+            Err(StateDecodeError::MalformedModule(_))
+        ));
+    }
+
+    #[test]
+    fn test_diff_states() {
+        let old = json!({"count": 1, "owner": "a", "list": [1, 2], "a/b": {"x": true}});
+        let new = json!({"count": 2, "list": [1, 2, 3], "a/b": {"x": true}, "new": null});
+        assert_eq!(diff_states(&old, &new), vec![
+            StateChange::Modified {
+                path: "/count".into(),
+                old:  json!(1),
+                new:  json!(2),
+            },
+            StateChange::Added {
+                path:  "/list/2".into(),
+                value: json!(3),
+            },
+            StateChange::Added {
+                path:  "/new".into(),
+                value: json!(null),
+            },
+            StateChange::Removed {
+                path:  "/owner".into(),
+                value: json!("a"),
+            },
+        ]);
+        assert!(diff_states(&new, &new).is_empty());
+    }
+}
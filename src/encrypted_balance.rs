@@ -0,0 +1,707 @@
+//! Decryption and management of the shielded (encrypted) balance of an
+//! account.
+//!
+//! The shielded balance of an account is recorded on the chain as a list of
+//! encrypted amounts, see [AccountEncryptedAmount]. Only the owner of the
+//! account, who has the decryption key, can find out the actual amounts.
+//! Decryption requires solving a discrete logarithm, which is done using a
+//! precomputed lookup table, [DecryptionTable]. Computing the table is
+//! expensive, so it should be computed once and stored, e.g., in a file.
+//!
+//! The history of the shielded balance, i.e., the decrypted amounts of all
+//! the encrypted transfers to and from the account, can be obtained with the
+//! [ShieldedHistoryScanner].
+use crate::{
+    blocks::{finalized_blocks, FinalizedBlock, StreamEnd},
+    constants::EncryptedAmountsCurve,
+    endpoints::{self, QueryResult},
+    postgres::{DatabaseClient, DatabaseRow, DatabaseSummaryEntry, RowError},
+    types::{
+        hashes::{BlockHash, TransactionHash},
+        transactions::{send, AccountTransaction, EncodedPayload, ExactSizeTransactionSigner},
+        AbsoluteBlockHeight, AccountEncryptedAmount, AccountTransactionEffects, BlockItemSummary,
+        BlockItemSummaryDetails, EncryptedAmountRemovedEvent, Memo, NewEncryptedAmountEvent, Nonce,
+    },
+};
+use crypto_common::{
+    types::{Amount, Timestamp, TransactionTime},
+    Buffer, Deserial, ParseResult, ReadBytesExt, Serial,
+};
+use encrypted_transfers::types::{
+    AggregatedDecryptedAmount, EncryptedAmount, EncryptedAmountIndex, EncryptedAmountTransferData,
+    SecToPubAmountTransferData,
+};
+use futures::StreamExt;
+use id::{
+    constants::ArCurve,
+    elgamal::{BabyStepGiantStep, PublicKey, SecretKey},
+    types::{AccountAddress, GlobalContext},
+};
+use thiserror::Error;
+
+/// The default size of the baby step table. With this size decryption of any
+/// amount takes at most `2^16` giant steps.
+pub const DEFAULT_TABLE_SIZE: u64 = 1 << 16;
+
+#[derive(Debug, Error)]
+/// Errors that can occur when constructing transfers from the shielded balance.
+pub enum EncryptedBalanceError {
+    #[error("Insufficient shielded balance: {available} is available, but {needed} is needed.")]
+    InsufficientBalance {
+        available: Amount,
+        needed:    Amount,
+    },
+    #[error("Could not construct the proofs for the transfer.")]
+    ProofGeneration,
+    #[error("The decrypted amounts of the shielded balance sum to more than the maximum amount.")]
+    Overflow,
+}
+
+/// Lookup table for decrypting encrypted amounts. The table only depends on
+/// the cryptographic parameters of the chain, so the same table can be used
+/// for all accounts. The table can be serialized with
+/// [to_bytes](crypto_common::to_bytes) and read back with
+/// [from_bytes](crypto_common::from_bytes).
+pub struct DecryptionTable {
+    table: BabyStepGiantStep<EncryptedAmountsCurve>,
+}
+
+impl DecryptionTable {
+    /// Compute the table of the [default size](DEFAULT_TABLE_SIZE) for the
+    /// given cryptographic parameters.
+    pub fn new(global_context: &GlobalContext<ArCurve>) -> Self {
+        Self::with_size(global_context, DEFAULT_TABLE_SIZE)
+    }
+
+    /// Compute a table with `m` baby steps. Larger tables take more time to
+    /// compute and more space to store, but make decryption faster.
+    pub fn with_size(global_context: &GlobalContext<ArCurve>, m: u64) -> Self {
+        Self {
+            table: BabyStepGiantStep::new(global_context.encryption_in_exponent_generator(), m),
+        }
+    }
+
+    /// Decrypt a single amount with the given decryption key.
+    pub fn decrypt(
+        &self,
+        secret_key: &SecretKey<EncryptedAmountsCurve>,
+        amount: &EncryptedAmount<EncryptedAmountsCurve>,
+    ) -> Amount {
+        encrypted_transfers::decrypt_amount(&self.table, secret_key, amount)
+    }
+}
+
+impl Serial for DecryptionTable {
+    fn serial<B: Buffer>(&self, out: &mut B) { self.table.serial(out) }
+}
+
+impl Deserial for DecryptionTable {
+    fn deserial<R: ReadBytesExt>(source: &mut R) -> ParseResult<Self> {
+        let table = BabyStepGiantStep::deserial(source)?;
+        Ok(Self { table })
+    }
+}
+
+/// The decrypted shielded balance of an account.
+pub struct DecryptedBalance {
+    /// The decrypted self amount.
+    pub self_amount:       Amount,
+    /// Index of the first incoming amount, or of the aggregated amount if it
+    /// is present.
+    pub start_index:       u64,
+    /// The decrypted aggregated amount, and the number of incoming amounts
+    /// that were aggregated into it.
+    pub aggregated_amount: Option<(Amount, u32)>,
+    /// The decrypted incoming amounts, in order of their indices.
+    pub incoming_amounts:  Vec<Amount>,
+    /// The combination of all the encrypted amounts, together with their
+    /// total. This is the input to a transfer from the shielded balance.
+    input:                 AggregatedDecryptedAmount<EncryptedAmountsCurve>,
+}
+
+impl DecryptedBalance {
+    /// Decrypt the shielded balance of an account with the account's
+    /// decryption key. Fails if the decrypted amounts do not fit in an
+    /// [Amount], which means that the key is not the account's key.
+    pub fn decrypt(
+        table: &DecryptionTable,
+        secret_key: &SecretKey<EncryptedAmountsCurve>,
+        encrypted: &AccountEncryptedAmount,
+    ) -> Result<Self, EncryptedBalanceError> {
+        let self_amount = table.decrypt(secret_key, &encrypted.self_amount);
+        let aggregated_amount = encrypted
+            .aggregated_amount
+            .as_ref()
+            .map(|(amount, n)| (table.decrypt(secret_key, amount), *n));
+        let incoming_amounts = encrypted
+            .incoming_amounts
+            .iter()
+            .map(|amount| table.decrypt(secret_key, amount))
+            .collect::<Vec<_>>();
+
+        let mut agg_encrypted_amount = encrypted.self_amount.clone();
+        let mut agg_amount = self_amount.microgtu;
+        for (enc, dec) in encrypted
+            .aggregated_amount
+            .iter()
+            .map(|(enc, _)| enc)
+            .zip(aggregated_amount.iter().map(|(dec, _)| dec))
+            .chain(
+                encrypted
+                    .incoming_amounts
+                    .iter()
+                    .zip(incoming_amounts.iter()),
+            )
+        {
+            agg_encrypted_amount = encrypted_transfers::aggregate(&agg_encrypted_amount, enc);
+            agg_amount = agg_amount
+                .checked_add(dec.microgtu)
+                .ok_or(EncryptedBalanceError::Overflow)?;
+        }
+        // The index up to which (but not including) the incoming amounts were
+        // used. The aggregated amount, if present, occupies the start index.
+        let agg_index = encrypted.start_index
+            + u64::from(encrypted.aggregated_amount.is_some())
+            + encrypted.incoming_amounts.len() as u64;
+        Ok(Self {
+            self_amount,
+            start_index: encrypted.start_index,
+            aggregated_amount,
+            incoming_amounts,
+            input: AggregatedDecryptedAmount {
+                agg_encrypted_amount,
+                agg_amount: Amount {
+                    microgtu: agg_amount,
+                },
+                agg_index: agg_index.into(),
+            },
+        })
+    }
+
+    /// The total shielded balance that can be spent, i.e., the sum of the self
+    /// amount and all the incoming amounts.
+    pub fn spendable(&self) -> Amount { self.input.agg_amount }
+
+    /// The aggregate of all the encrypted amounts, which is used as the input
+    /// amount of transfers from the shielded balance.
+    pub fn input_amount(&self) -> &AggregatedDecryptedAmount<EncryptedAmountsCurve> { &self.input }
+
+    fn ensure_spendable(&self, needed: Amount) -> Result<(), EncryptedBalanceError> {
+        let available = self.spendable();
+        if available.microgtu < needed.microgtu {
+            Err(EncryptedBalanceError::InsufficientBalance { available, needed })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Construct the data of a transfer of the given amount from the shielded
+    /// balance to the shielded balance of the receiver.
+    pub fn make_transfer_data<R: rand::Rng>(
+        &self,
+        global_context: &GlobalContext<ArCurve>,
+        secret_key: &SecretKey<EncryptedAmountsCurve>,
+        receiver_key: &PublicKey<EncryptedAmountsCurve>,
+        amount: Amount,
+        csprng: &mut R,
+    ) -> Result<EncryptedAmountTransferData<EncryptedAmountsCurve>, EncryptedBalanceError> {
+        self.ensure_spendable(amount)?;
+        encrypted_transfers::make_transfer_data(
+            global_context,
+            receiver_key,
+            secret_key,
+            &self.input,
+            amount,
+            csprng,
+        )
+        .ok_or(EncryptedBalanceError::ProofGeneration)
+    }
+
+    /// Construct the data of a transfer of the given amount from the shielded
+    /// balance to the public balance of the account.
+    pub fn make_transfer_to_public_data<R: rand::Rng>(
+        &self,
+        global_context: &GlobalContext<ArCurve>,
+        secret_key: &SecretKey<EncryptedAmountsCurve>,
+        amount: Amount,
+        csprng: &mut R,
+    ) -> Result<SecToPubAmountTransferData<EncryptedAmountsCurve>, EncryptedBalanceError> {
+        self.ensure_spendable(amount)?;
+        encrypted_transfers::make_sec_to_pub_transfer_data(
+            global_context,
+            secret_key,
+            &self.input,
+            amount,
+            csprng,
+        )
+        .ok_or(EncryptedBalanceError::ProofGeneration)
+    }
+
+    /// Construct and sign a transfer of the given amount from the shielded
+    /// balance to the shielded balance of the receiver. The decrypted balance
+    /// must be of the sender's account in the state in which the transaction
+    /// will be executed, i.e., no other transfers from the shielded balance may
+    /// happen before this one.
+    #[allow(clippy::too_many_arguments)]
+    pub fn encrypted_transfer<R: rand::Rng>(
+        &self,
+        signer: &impl ExactSizeTransactionSigner,
+        global_context: &GlobalContext<ArCurve>,
+        secret_key: &SecretKey<EncryptedAmountsCurve>,
+        sender: AccountAddress,
+        nonce: Nonce,
+        expiry: TransactionTime,
+        receiver: AccountAddress,
+        receiver_key: &PublicKey<EncryptedAmountsCurve>,
+        amount: Amount,
+        csprng: &mut R,
+    ) -> Result<AccountTransaction<EncodedPayload>, EncryptedBalanceError> {
+        let data =
+            self.make_transfer_data(global_context, secret_key, receiver_key, amount, csprng)?;
+        Ok(send::encrypted_transfer(
+            signer, sender, nonce, expiry, receiver, data,
+        ))
+    }
+
+    /// Construct and sign a transfer of the given amount from the shielded
+    /// balance to the public balance of the account. The same caveats as for
+    /// [encrypted_transfer](Self::encrypted_transfer) apply.
+    #[allow(clippy::too_many_arguments)]
+    pub fn transfer_to_public<R: rand::Rng>(
+        &self,
+        signer: &impl ExactSizeTransactionSigner,
+        global_context: &GlobalContext<ArCurve>,
+        secret_key: &SecretKey<EncryptedAmountsCurve>,
+        sender: AccountAddress,
+        nonce: Nonce,
+        expiry: TransactionTime,
+        amount: Amount,
+        csprng: &mut R,
+    ) -> Result<AccountTransaction<EncodedPayload>, EncryptedBalanceError> {
+        let data = self.make_transfer_to_public_data(global_context, secret_key, amount, csprng)?;
+        Ok(send::transfer_to_public(
+            signer, sender, nonce, expiry, data,
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+/// The kind of a change of the shielded balance of an account.
+pub enum ShieldedTransferKind {
+    /// An encrypted amount was received from another account.
+    Received {
+        /// The sender of the amount.
+        from:  AccountAddress,
+        /// The index the amount was assigned on the account.
+        index: EncryptedAmountIndex,
+    },
+    /// An encrypted amount was sent to another account.
+    Sent {
+        /// The receiver of the amount.
+        to: AccountAddress,
+    },
+    /// An amount was transferred from the public balance of the account.
+    FromPublic,
+    /// An amount was transferred to the public balance of the account.
+    ToPublic,
+}
+
+impl ShieldedTransferKind {
+    /// Whether the transfer increases the shielded balance of the account.
+    pub fn is_inflow(&self) -> bool {
+        matches!(
+            self,
+            ShieldedTransferKind::Received { .. } | ShieldedTransferKind::FromPublic
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+/// An entry in the history of the shielded balance of an account.
+pub struct ShieldedTransfer {
+    /// Hash of the block the transaction is in.
+    pub block_hash:       BlockHash,
+    /// Slot time of the block the transaction is in.
+    pub block_time:       Timestamp,
+    /// Hash of the transaction that made the transfer.
+    pub transaction_hash: TransactionHash,
+    /// The direction and counterparty of the transfer.
+    pub kind:             ShieldedTransferKind,
+    /// The decrypted amount that was transferred.
+    pub amount:           Amount,
+    /// The memo of the transfer, if any.
+    pub memo:             Option<Memo>,
+}
+
+/// Scanner for the history of the shielded balance of a single account. The
+/// scanner decrypts the amounts of encrypted transfers to and from the account
+/// using the account's decryption key.
+pub struct ShieldedHistoryScanner<'a> {
+    table:      &'a DecryptionTable,
+    secret_key: &'a SecretKey<EncryptedAmountsCurve>,
+    account:    AccountAddress,
+}
+
+impl<'a> ShieldedHistoryScanner<'a> {
+    /// Construct a scanner for the given account. The secret key must be the
+    /// decryption key of the account.
+    pub fn new(
+        table: &'a DecryptionTable,
+        secret_key: &'a SecretKey<EncryptedAmountsCurve>,
+        account: AccountAddress,
+    ) -> Self {
+        Self {
+            table,
+            secret_key,
+            account,
+        }
+    }
+
+    /// The amount removed from the shielded balance. This is the difference
+    /// between the amounts that were used as input and the remaining amount.
+    fn removed_amount(&self, removed: &EncryptedAmountRemovedEvent) -> Amount {
+        let input = self.table.decrypt(self.secret_key, &removed.input_amount);
+        let remaining = self.table.decrypt(self.secret_key, &removed.new_amount);
+        Amount {
+            microgtu: input.microgtu.saturating_sub(remaining.microgtu),
+        }
+    }
+
+    /// Extract the changes of the account's shielded balance made by the
+    /// transaction, together with their memos. The returned list is empty if
+    /// the transaction did not affect the shielded balance of the account.
+    pub fn scan_summary(
+        &self,
+        summary: &BlockItemSummary,
+    ) -> Vec<(ShieldedTransferKind, Amount, Option<Memo>)> {
+        let at = match &summary.details {
+            BlockItemSummaryDetails::AccountTransaction(at) => at,
+            _ => return Vec::new(),
+        };
+        let mut out = Vec::new();
+        let mut transferred = |removed: &EncryptedAmountRemovedEvent,
+                               added: &NewEncryptedAmountEvent,
+                               memo: Option<&Memo>| {
+            if added.receiver == self.account {
+                out.push((
+                    ShieldedTransferKind::Received {
+                        from:  at.sender,
+                        index: added.new_index,
+                    },
+                    self.table.decrypt(self.secret_key, &added.encrypted_amount),
+                    memo.cloned(),
+                ));
+            }
+            if removed.account == self.account {
+                out.push((
+                    ShieldedTransferKind::Sent { to: added.receiver },
+                    self.removed_amount(removed),
+                    memo.cloned(),
+                ));
+            }
+        };
+        match &at.effects {
+            AccountTransactionEffects::EncryptedAmountTransferred { removed, added } => {
+                transferred(removed, added, None)
+            }
+            AccountTransactionEffects::EncryptedAmountTransferredWithMemo {
+                removed,
+                added,
+                memo,
+            } => transferred(removed, added, Some(memo)),
+            AccountTransactionEffects::TransferredToEncrypted { data }
+                if data.account == self.account =>
+            {
+                out.push((ShieldedTransferKind::FromPublic, data.amount, None))
+            }
+            AccountTransactionEffects::TransferredToPublic { removed, amount }
+                if removed.account == self.account =>
+            {
+                out.push((ShieldedTransferKind::ToPublic, *amount, None))
+            }
+            _ => (),
+        }
+        out
+    }
+
+    fn make_entries(
+        &self,
+        block_hash: BlockHash,
+        block_time: Timestamp,
+        summary: &BlockItemSummary,
+    ) -> impl Iterator<Item = ShieldedTransfer> {
+        let transaction_hash = summary.hash;
+        self.scan_summary(summary)
+            .into_iter()
+            .map(move |(kind, amount, memo)| ShieldedTransfer {
+                block_hash,
+                block_time,
+                transaction_hash,
+                kind,
+                amount,
+                memo,
+            })
+    }
+
+    /// Extract the changes of the account's shielded balance in the block.
+    pub fn scan_block(&self, block: &FinalizedBlock) -> Vec<ShieldedTransfer> {
+        let block_time = Timestamp::from(block.info.block_slot_time.timestamp_millis() as u64);
+        block
+            .summary
+            .transaction_summaries
+            .iter()
+            .flat_map(|summary| self.make_entries(block.info.block_hash, block_time, summary))
+            .collect()
+    }
+
+    /// Extract the changes of the account's shielded balance recorded in the
+    /// row of the transaction index.
+    pub fn scan_row(&self, row: &DatabaseRow) -> Vec<ShieldedTransfer> {
+        match &row.summary {
+            DatabaseSummaryEntry::BlockItem(summary) => self
+                .make_entries(row.block_hash, row.block_time, summary)
+                .collect(),
+            DatabaseSummaryEntry::ProtocolEvent(_) => Vec::new(),
+        }
+    }
+
+    /// Get the history of the shielded balance of the account from the
+    /// transaction index, starting at the given row id. Rows that cannot be
+    /// parsed are not skipped, since the history would be incomplete without
+    /// them. Instead an error is returned.
+    pub async fn history_from_database(
+        &self,
+        db: &DatabaseClient,
+        start: Option<i64>,
+    ) -> Result<Vec<ShieldedTransfer>, RowError> {
+        let rows = db.iterate_account_strict(&self.account, start).await?;
+        futures::pin_mut!(rows);
+        let mut out = Vec::new();
+        while let Some(row) = rows.next().await {
+            out.extend(self.scan_row(&row?));
+        }
+        Ok(out)
+    }
+
+    /// Get the history of the shielded balance of the account in the given
+    /// range of blocks, both ends inclusive.
+    pub async fn history_in_range(
+        &self,
+        client: endpoints::Client,
+        from: AbsoluteBlockHeight,
+        to: AbsoluteBlockHeight,
+    ) -> QueryResult<Vec<ShieldedTransfer>> {
+        let mut blocks = Box::pin(finalized_blocks(
+            client,
+            from,
+            StreamEnd::AtHeight(to),
+            std::time::Duration::from_secs(1),
+        ));
+        let mut out = Vec::new();
+        while let Some(block) = blocks.next().await {
+            out.extend(self.scan_block(&block?));
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{
+        AccountTransactionDetails, EncryptedSelfAmountAddedEvent, Energy, TransactionIndex,
+    };
+
+    /// Table size for tests. Small amounts are decrypted quickly with it.
+    const TEST_TABLE_SIZE: u64 = 1 << 8;
+
+    struct TestKeys {
+        global_context: GlobalContext<ArCurve>,
+        secret_key:     SecretKey<EncryptedAmountsCurve>,
+        public_key:     PublicKey<EncryptedAmountsCurve>,
+        table:          DecryptionTable,
+    }
+
+    impl TestKeys {
+        fn new() -> Self {
+            let global_context = GlobalContext::generate(String::from("test"));
+            let secret_key = SecretKey::generate_all(&mut rand::thread_rng());
+            let public_key = PublicKey::from(&secret_key);
+            let table = DecryptionTable::with_size(&global_context, TEST_TABLE_SIZE);
+            Self {
+                global_context,
+                secret_key,
+                public_key,
+                table,
+            }
+        }
+
+        fn encrypt(&self, microgtu: u64) -> EncryptedAmount<EncryptedAmountsCurve> {
+            let (encrypted, _) = encrypted_transfers::encrypt_amount(
+                &self.global_context,
+                &self.public_key,
+                Amount { microgtu },
+                &mut rand::thread_rng(),
+            );
+            encrypted
+        }
+    }
+
+    fn ccd(microgtu: u64) -> Amount { Amount { microgtu } }
+
+    #[test]
+    fn test_table_serialization() {
+        let keys = TestKeys::new();
+        let bytes = crypto_common::to_bytes(&keys.table);
+        let table: DecryptionTable = crypto_common::from_bytes(&mut std::io::Cursor::new(bytes))
+            .expect("The table can be read back.");
+        let encrypted = keys.encrypt(1234);
+        assert_eq!(table.decrypt(&keys.secret_key, &encrypted), ccd(1234));
+        assert_eq!(keys.table.decrypt(&keys.secret_key, &encrypted), ccd(1234));
+    }
+
+    #[test]
+    fn test_decrypted_balance() {
+        let keys = TestKeys::new();
+        let encrypted = AccountEncryptedAmount {
+            self_amount:       keys.encrypt(100),
+            start_index:       3,
+            aggregated_amount: None,
+            incoming_amounts:  vec![keys.encrypt(20), keys.encrypt(3)],
+        };
+        let balance = DecryptedBalance::decrypt(&keys.table, &keys.secret_key, &encrypted)
+            .expect("The balance is small.");
+        assert_eq!(balance.self_amount, ccd(100));
+        assert_eq!(balance.incoming_amounts, vec![ccd(20), ccd(3)]);
+        assert_eq!(balance.spendable(), ccd(123));
+        // The incoming amounts have indices 3 and 4.
+        assert_eq!(balance.input_amount().agg_index.index, 5);
+
+        // The aggregated amount takes the start index, and the incoming amounts
+        // follow it.
+        let encrypted = AccountEncryptedAmount {
+            aggregated_amount: Some((keys.encrypt(50), 2)),
+            ..encrypted
+        };
+        let balance = DecryptedBalance::decrypt(&keys.table, &keys.secret_key, &encrypted)
+            .expect("The balance is small.");
+        assert_eq!(balance.aggregated_amount, Some((ccd(50), 2)));
+        assert_eq!(balance.spendable(), ccd(173));
+        assert_eq!(balance.input_amount().agg_index.index, 6);
+        assert!(matches!(
+            balance.ensure_spendable(ccd(174)),
+            Err(EncryptedBalanceError::InsufficientBalance { .. })
+        ));
+        assert!(balance.ensure_spendable(ccd(173)).is_ok());
+    }
+
+    fn account(byte: u8) -> AccountAddress { AccountAddress([byte; 32]) }
+
+    fn summary(sender: AccountAddress, effects: AccountTransactionEffects) -> BlockItemSummary {
+        BlockItemSummary {
+            index:       TransactionIndex { index: 0 },
+            energy_cost: Energy { energy: 500 },
+            hash:        TransactionHash::new([1; 32]),
+            details:     BlockItemSummaryDetails::AccountTransaction(AccountTransactionDetails {
+                cost: ccd(5),
+                sender,
+                effects,
+            }),
+        }
+    }
+
+    /// Removal of `input` from the shielded balance of the account, leaving
+    /// `remaining`.
+    fn removed(
+        keys: &TestKeys,
+        account: AccountAddress,
+        input: u64,
+        remaining: u64,
+    ) -> Box<EncryptedAmountRemovedEvent> {
+        Box::new(EncryptedAmountRemovedEvent {
+            account,
+            new_amount: keys.encrypt(remaining),
+            input_amount: keys.encrypt(input),
+            up_to_index: 0u64.into(),
+        })
+    }
+
+    #[test]
+    fn test_scan_encrypted_transfer() {
+        let keys = TestKeys::new();
+        let added = Box::new(NewEncryptedAmountEvent {
+            receiver:         account(2),
+            new_index:        7u64.into(),
+            encrypted_amount: keys.encrypt(30),
+        });
+        let transfer = summary(
+            account(1),
+            AccountTransactionEffects::EncryptedAmountTransferred {
+                removed: removed(&keys, account(1), 100, 70),
+                added,
+            },
+        );
+
+        // The sender sees the difference between the input and the remaining
+        // amount.
+        let sender = ShieldedHistoryScanner::new(&keys.table, &keys.secret_key, account(1));
+        let entries = sender.scan_summary(&transfer);
+        assert_eq!(entries.len(), 1);
+        assert!(
+            matches!(&entries[0], (ShieldedTransferKind::Sent { to }, amount, None)
+            if *to == account(2) && *amount == ccd(30))
+        );
+        assert!(!entries[0].0.is_inflow());
+
+        // The receiver decrypts the added amount with its own key, which in
+        // this test is the same one.
+        let receiver = ShieldedHistoryScanner::new(&keys.table, &keys.secret_key, account(2));
+        let entries = receiver.scan_summary(&transfer);
+        assert_eq!(entries.len(), 1);
+        assert!(
+            matches!(&entries[0], (ShieldedTransferKind::Received { from, .. }, amount, None)
+            if *from == account(1) && *amount == ccd(30))
+        );
+        assert!(entries[0].0.is_inflow());
+
+        let other = ShieldedHistoryScanner::new(&keys.table, &keys.secret_key, account(3));
+        assert!(other.scan_summary(&transfer).is_empty());
+    }
+
+    #[test]
+    fn test_scan_public_transfers() {
+        let keys = TestKeys::new();
+        let scanner = ShieldedHistoryScanner::new(&keys.table, &keys.secret_key, account(1));
+
+        let to_encrypted = summary(
+            account(1),
+            AccountTransactionEffects::TransferredToEncrypted {
+                data: Box::new(EncryptedSelfAmountAddedEvent {
+                    account:    account(1),
+                    new_amount: keys.encrypt(40),
+                    amount:     ccd(40),
+                }),
+            },
+        );
+        let entries = scanner.scan_summary(&to_encrypted);
+        assert!(
+            matches!(entries.as_slice(), [(ShieldedTransferKind::FromPublic, amount, None)]
+            if *amount == ccd(40))
+        );
+
+        let to_public = summary(account(1), AccountTransactionEffects::TransferredToPublic {
+            removed: removed(&keys, account(1), 40, 15),
+            amount:  ccd(25),
+        });
+        let entries = scanner.scan_summary(&to_public);
+        assert!(
+            matches!(entries.as_slice(), [(ShieldedTransferKind::ToPublic, amount, None)]
+            if *amount == ccd(25))
+        );
+
+        // Transfers of other accounts are ignored.
+        let other = ShieldedHistoryScanner::new(&keys.table, &keys.secret_key, account(2));
+        assert!(other.scan_summary(&to_encrypted).is_empty());
+        assert!(other.scan_summary(&to_public).is_empty());
+    }
+}
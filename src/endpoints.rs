@@ -19,7 +19,14 @@ use id::{
     types::{ArInfo, GlobalContext, IpInfo},
 };
 use sha2::Digest;
-use std::{borrow::Borrow, convert::TryInto, net::IpAddr, sync::Arc, time::UNIX_EPOCH};
+use std::{
+    borrow::Borrow,
+    collections::BTreeMap,
+    convert::TryInto,
+    net::IpAddr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::UNIX_EPOCH,
+};
 use thiserror::Error;
 pub use tonic::transport::Endpoint;
 use tonic::{
@@ -104,6 +111,22 @@ pub enum BlocksAtHeightInput {
     },
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A finalized block together with its height and slot time, as returned by
+/// [Client::find_finalized_block_at_time].
+pub struct BlockAtTime {
+    /// Hash of the block.
+    pub block_hash: types::hashes::BlockHash,
+    /// Absolute height of the block.
+    pub height:     types::AbsoluteBlockHeight,
+    /// Slot time of the block.
+    pub slot_time:  chrono::DateTime<chrono::Utc>,
+}
+
+/// Maximum number of finalized blocks whose slot times are cached by the
+/// client. The cache is cleared when it grows beyond this.
+const BLOCK_TIME_CACHE_SIZE: usize = 100_000;
+
 #[derive(Clone)]
 /// Client that can perform queries.
 /// All endpoints take a &mut self as an argument which means that a single
@@ -111,8 +134,10 @@ pub enum BlocksAtHeightInput {
 /// behind a Mutex, the intended way to use it is to clone it. Cloning is very
 /// cheap and will reuse the underlying connection.
 pub struct Client {
-    client: p2p_client::P2pClient<Channel>,
-    token:  Arc<String>,
+    client:           p2p_client::P2pClient<Channel>,
+    token:            Arc<String>,
+    /// Slot times of finalized blocks, shared between clones of the client.
+    block_time_cache: Arc<Mutex<BTreeMap<types::AbsoluteBlockHeight, BlockAtTime>>>,
 }
 
 impl Client {
@@ -136,6 +161,7 @@ impl Client {
         Ok(Client {
             client,
             token: Arc::new(token),
+            block_time_cache: Arc::new(Mutex::new(BTreeMap::new())),
         })
     }
 
@@ -411,6 +437,113 @@ impl Client {
         Ok(blocks)
     }
 
+    /// Get the hash of the finalized block at the given absolute height. If
+    /// there is no finalized block at the given height, i.e., the height is
+    /// above the last finalized block, [QueryError::NotFound] is returned.
+    pub async fn get_finalized_block_at_height(
+        &mut self,
+        height: types::AbsoluteBlockHeight,
+    ) -> QueryResult<types::hashes::BlockHash> {
+        let consensus_info = self.get_consensus_status().await?;
+        if height > consensus_info.last_finalized_block_height {
+            return Err(QueryError::NotFound);
+        }
+        // Below the last finalized block there is exactly one block at each
+        // height, the finalized one.
+        let blocks = self
+            .get_blocks_at_height(BlocksAtHeightInput::Absolute { height })
+            .await?;
+        blocks.into_iter().next().ok_or(QueryError::NotFound)
+    }
+
+    /// Find the last finalized block whose slot time is at or before the given
+    /// time. If the time is after the slot time of the last finalized block,
+    /// that block is returned. If the time is before the genesis block of the
+    /// chain [QueryError::NotFound] is returned.
+    ///
+    /// The block is found by binary search over absolute heights, which span
+    /// all eras of the chain since slot times never decrease along the chain.
+    /// The slot times of the blocks visited by the search are cached, so
+    /// repeated lookups of nearby times need few queries.
+    pub async fn find_finalized_block_at_time(
+        &mut self,
+        time: chrono::DateTime<chrono::Utc>,
+    ) -> QueryResult<BlockAtTime> {
+        let consensus_info = self.get_consensus_status().await?;
+        let last = self
+            .finalized_block_time(consensus_info.last_finalized_block_height)
+            .await?;
+        if last.slot_time <= time {
+            return Ok(last);
+        }
+        // Invariant: `lo.slot_time <= time < hi.slot_time`. Start from the
+        // tightest bounds known from the cache.
+        let (lo, mut hi) = {
+            let cache = self.block_time_cache();
+            let lo = cache.values().rev().find(|b| b.slot_time <= time).copied();
+            let hi = cache.values().find(|b| b.slot_time > time).copied();
+            (lo, hi.unwrap_or(last))
+        };
+        let mut lo = match lo {
+            Some(lo) => lo,
+            None => {
+                let genesis = self.finalized_block_time(0.into()).await?;
+                if genesis.slot_time > time {
+                    return Err(QueryError::NotFound);
+                }
+                genesis
+            }
+        };
+        while hi.height.height - lo.height.height > 1 {
+            let mid = lo.height.height + (hi.height.height - lo.height.height) / 2;
+            let block = self.finalized_block_time(mid.into()).await?;
+            if block.slot_time <= time {
+                lo = block;
+            } else {
+                hi = block;
+            }
+        }
+        Ok(lo)
+    }
+
+    /// Get the hash and slot time of the finalized block at the given height,
+    /// which must be at most the height of the last finalized block.
+    async fn finalized_block_time(
+        &mut self,
+        height: types::AbsoluteBlockHeight,
+    ) -> QueryResult<BlockAtTime> {
+        let cached = self.block_time_cache().get(&height).copied();
+        if let Some(block) = cached {
+            return Ok(block);
+        }
+        let block_hash = self
+            .get_blocks_at_height(BlocksAtHeightInput::Absolute { height })
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(QueryError::NotFound)?;
+        let info = self.get_block_info(&block_hash).await?;
+        let block = BlockAtTime {
+            block_hash,
+            height,
+            slot_time: info.block_slot_time,
+        };
+        let mut cache = self.block_time_cache();
+        if cache.len() >= BLOCK_TIME_CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(height, block);
+        Ok(block)
+    }
+
+    /// Lock the cache of block slot times. The cache is only ever updated by
+    /// single insertions, so it is consistent even if the lock is poisoned.
+    fn block_time_cache(&self) -> MutexGuard<BTreeMap<types::AbsoluteBlockHeight, BlockAtTime>> {
+        self.block_time_cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
     /// FIXME: This currently does nothing on the node, hence it is private.
     async fn _start_baker(&mut self) -> RPCResult<bool> {
         let request = self.construct_request(Empty {})?;
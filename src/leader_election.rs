@@ -0,0 +1,168 @@
+//! Evaluation of the leadership election for a baker.
+//!
+//! In each slot every baker evaluates a verifiable random function (VRF) on a
+//! message consisting of the leadership election nonce of the epoch and the
+//! slot number. The baker wins the slot, and may produce a block in it, if the
+//! output of the VRF, interpreted as a number between 0 and 1, is below the
+//! [win probability](crate::luck::win_probability) determined by the election
+//! difficulty and the baker's lottery power. Since the nonce is fixed for the
+//! whole epoch, a baker can compute in advance which slots of the current
+//! epoch it wins.
+use crate::{
+    endpoints::{self, QueryError, QueryResult},
+    luck::win_probability,
+    types::{hashes::LeadershipElectionNonce, BakerElectionSignKey, BakerId, Slot},
+};
+use std::{convert::TryInto, ops::RangeInclusive};
+
+/// Construct the message on which the VRF is evaluated to determine whether
+/// a baker wins the given slot.
+pub fn election_message(nonce: &LeadershipElectionNonce, slot: Slot) -> Vec<u8> {
+    let mut message = Vec::with_capacity(2 + 32 + 8);
+    message.extend_from_slice(b"LE");
+    message.extend_from_slice(nonce.as_ref());
+    message.extend_from_slice(&slot.slot.to_be_bytes());
+    message
+}
+
+/// Interpret the output of the VRF as a number in the interval `[0, 1)`. The
+/// first 8 bytes of the hash are taken as a big endian integer and divided by
+/// `2^64`.
+pub fn election_luck(proof: &ecvrf::Proof) -> f64 {
+    let hash = proof.to_hash();
+    let prefix: [u8; 8] = hash[..8].try_into().expect("The hash has 64 bytes.");
+    u64::from_be_bytes(prefix) as f64 / 2f64.powi(64)
+}
+
+/// Determine whether the given [election luck](election_luck) wins a slot for
+/// a baker with the given lottery power. The luck must be strictly below the
+/// win probability.
+pub fn is_winner(luck: f64, election_difficulty: f64, lottery_power: f64) -> bool {
+    luck < win_probability(election_difficulty, lottery_power)
+}
+
+/// Determine whether the baker with the given election key and lottery power
+/// wins the slot.
+pub fn wins_slot(
+    key: &BakerElectionSignKey,
+    nonce: &LeadershipElectionNonce,
+    election_difficulty: f64,
+    lottery_power: f64,
+    slot: Slot,
+) -> bool {
+    let proof = key.prove(&election_message(nonce, slot));
+    is_winner(election_luck(&proof), election_difficulty, lottery_power)
+}
+
+/// List the slots in the given range that the baker wins. All of the slots
+/// must be in the epoch the nonce belongs to.
+pub fn winning_slots(
+    key: &BakerElectionSignKey,
+    nonce: &LeadershipElectionNonce,
+    election_difficulty: f64,
+    lottery_power: f64,
+    slots: RangeInclusive<u64>,
+) -> Vec<Slot> {
+    slots
+        .map(|slot| Slot { slot })
+        .filter(|slot| wins_slot(key, nonce, election_difficulty, lottery_power, *slot))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy)]
+/// A slot won by the baker.
+pub struct WinningSlot {
+    /// The slot.
+    pub slot: Slot,
+    /// The time at which the slot starts.
+    pub time: chrono::DateTime<chrono::Utc>,
+}
+
+/// List the slots in the rest of the current epoch that the baker wins,
+/// starting from the current time. The nonce of the next epoch is only known
+/// once it has started, so later slots cannot be determined. If the baker is
+/// not among the bakers of the current epoch [QueryError::NotFound] is
+/// returned.
+pub async fn upcoming_winning_slots(
+    client: &mut endpoints::Client,
+    baker_id: BakerId,
+    key: &BakerElectionSignKey,
+) -> QueryResult<Vec<WinningSlot>> {
+    let consensus_info = client.get_consensus_status().await?;
+    let best_block = consensus_info.best_block;
+    let birk = client.get_birk_parameters(&best_block).await?;
+    let lottery_power = birk
+        .bakers
+        .iter()
+        .find(|baker| baker.baker_id == baker_id)
+        .ok_or(QueryError::NotFound)?
+        .baker_lottery_power;
+    let best_slot = client.get_block_info(&best_block).await?.block_slot.slot;
+
+    let slot_millis = consensus_info.slot_duration.millis;
+    let era_start = consensus_info.current_era_genesis_time;
+    let epoch_length = consensus_info.epoch_duration.num_milliseconds() as u64 / slot_millis;
+    let now_slot = (chrono::Utc::now() - era_start).num_milliseconds().max(0) as u64 / slot_millis;
+    let last_slot = (best_slot / epoch_length + 1) * epoch_length - 1;
+    let slots = std::cmp::max(now_slot, best_slot)..=last_slot;
+
+    let difficulty = birk.election_difficulty.as_f64();
+    let won = winning_slots(key, &birk.election_nonce, difficulty, lottery_power, slots);
+    Ok(won
+        .into_iter()
+        .map(|slot| WinningSlot {
+            slot,
+            time: era_start + chrono::Duration::milliseconds((slot.slot * slot_millis) as i64),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_election_message() {
+        let mut nonce = [0u8; 32];
+        for (i, b) in nonce.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let message = election_message(&LeadershipElectionNonce::new(nonce), Slot {
+            slot: 0x0102_0304_0506_0708,
+        });
+        let mut expected = vec![b'L', b'E'];
+        expected.extend(0u8..32);
+        expected.extend(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(message, expected);
+        assert_eq!(message.len(), 2 + 32 + 8);
+    }
+
+    #[test]
+    fn test_threshold() {
+        let threshold = win_probability(0.5, 1.0);
+        assert_eq!(threshold, 0.5);
+        // The threshold itself does not win.
+        assert!(!is_winner(threshold, 0.5, 1.0));
+        assert!(is_winner(threshold - f64::EPSILON, 0.5, 1.0));
+        assert!(is_winner(0.0, 0.5, 1.0));
+        // Without lottery power nothing wins, not even the lowest luck.
+        assert!(!is_winner(0.0, 0.5, 0.0));
+    }
+
+    #[test]
+    fn test_wins_slot() {
+        let key = BakerElectionSignKey::generate(&mut rand::thread_rng());
+        let nonce = LeadershipElectionNonce::new([7u8; 32]);
+        for slot in 0..20 {
+            let slot = Slot { slot };
+            let luck = election_luck(&key.prove(&election_message(&nonce, slot)));
+            assert!((0.0..1.0).contains(&luck));
+            assert_eq!(
+                wins_slot(&key, &nonce, 0.5, 0.3, slot),
+                is_winner(luck, 0.5, 0.3)
+            );
+        }
+        // With difficulty 1 a baker with any lottery power wins every slot.
+        assert_eq!(winning_slots(&key, &nonce, 1.0, 0.1, 0..=9).len(), 10);
+    }
+}
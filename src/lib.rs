@@ -1,13 +1,41 @@
+/// Account statements with running balances computed from the transaction
+/// index.
+pub mod account_statement;
+/// Management of the baker of an account.
+pub mod baker;
+/// Traversal of finalized blocks and their summaries.
+pub mod blocks;
+/// Client for tokens in contracts following the CIS-2 token standard.
+pub mod cis2;
+/// Conversion between slots, epochs and time in every era of the chain.
+pub mod clock;
 /// Various type and value parameters that apply to the chain.
 pub mod constants;
+/// Following the transactions that affect a smart contract instance.
+pub mod contract_activity;
+/// Decoding of smart contract instance state via contract schemas.
+pub mod contract_state;
+/// Decryption of the shielded balance of an account, its history, and
+/// transfers from it.
+pub mod encrypted_balance;
 /// Wrapper for the node's GRPC API. The return values are parsed and wrapped in
 /// structured values.
 pub mod endpoints;
 mod generated_types;
 mod internal;
+/// Evaluation of the leadership election to find the slots a baker wins.
+pub mod leader_election;
+/// Analysis of block production of bakers compared to their lottery power.
+pub mod luck;
 /// Interface to the (optional) postgres database that the node logs finalized
 /// transactions in.
 pub mod postgres;
+/// Simulation of the distribution of transaction fees and minted CCD.
+pub mod reward_simulation;
+/// Accounting of baker rewards over ranges of blocks.
+pub mod rewards;
+/// Tracking of the total CCD supply and the reward accounts.
+pub mod supply;
 /// Type definitions used throughout the rest of the SDK.
 pub mod types;
 
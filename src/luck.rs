@@ -0,0 +1,359 @@
+//! Analysis of block production of bakers.
+//!
+//! In each slot a baker with lottery power `α` wins with probability
+//! `1 - (1 - d)^α`, where `d` is the election difficulty. Summing these
+//! probabilities over the slots in a range gives the number of blocks the
+//! baker is expected to produce. Comparing it with the number of blocks the
+//! baker actually produced in the finalized chain shows whether the baker has
+//! been lucky or unlucky, or whether it has stopped producing blocks.
+//!
+//! Note that since forks are resolved by finalization, and a slot can have
+//! several winners, the number of blocks in the finalized chain is generally
+//! lower than the number of winning slots. The luck factor of all bakers is
+//! thus typically somewhat below 1.
+use crate::{
+    blocks::{finalized_blocks, FinalizedBlock, StreamEnd},
+    endpoints::{self, QueryResult},
+    rewards::EraEpoch,
+    types::{AbsoluteBlockHeight, BakerId, BirkBaker, Epoch},
+};
+use futures::StreamExt;
+use id::types::AccountAddress;
+use std::collections::BTreeMap;
+
+/// The probability that a baker with the given lottery power wins a slot with
+/// the given election difficulty.
+pub fn win_probability(election_difficulty: f64, lottery_power: f64) -> f64 {
+    1f64 - (1f64 - election_difficulty).powf(lottery_power)
+}
+
+#[derive(Debug, Clone)]
+/// Block production statistics of a baker over a range of slots.
+pub struct BakerLuck {
+    /// Account of the baker.
+    pub account:  AccountAddress,
+    /// Number of blocks the baker produced in the finalized chain.
+    pub actual:   u64,
+    /// Expected number of winning slots.
+    pub expected: f64,
+    /// Variance of the number of winning slots.
+    pub variance: f64,
+}
+
+impl BakerLuck {
+    /// Ratio of the actual and the expected number of blocks. `None` if no
+    /// blocks were expected.
+    pub fn luck(&self) -> Option<f64> {
+        if self.expected > 0f64 {
+            Some(self.actual as f64 / self.expected)
+        } else {
+            None
+        }
+    }
+
+    /// Interval of the number of blocks that contains the actual number with
+    /// the confidence given by the standard score `z`, e.g., `1.96` for 95%,
+    /// using the normal approximation.
+    pub fn confidence_interval(&self, z: f64) -> (f64, f64) {
+        let deviation = z * self.variance.sqrt();
+        (
+            (self.expected - deviation).max(0f64),
+            self.expected + deviation,
+        )
+    }
+
+    /// The [confidence interval](Self::confidence_interval) relative to the
+    /// expected number of blocks, i.e., the interval of luck factors that
+    /// are consistent with the baker working correctly.
+    pub fn luck_interval(&self, z: f64) -> Option<(f64, f64)> {
+        if self.expected > 0f64 {
+            let (lower, upper) = self.confidence_interval(z);
+            Some((lower / self.expected, upper / self.expected))
+        } else {
+            None
+        }
+    }
+
+    /// Whether the actual number of blocks is within the confidence interval.
+    pub fn is_within(&self, z: f64) -> bool {
+        let (lower, upper) = self.confidence_interval(z);
+        lower <= self.actual as f64 && self.actual as f64 <= upper
+    }
+}
+
+#[derive(Debug, Clone)]
+/// A baker that produced no blocks in an epoch.
+pub struct IdleBaker {
+    /// The epoch.
+    pub epoch:                 EraEpoch,
+    /// The baker.
+    pub baker_id:              BakerId,
+    /// Expected number of winning slots of the baker in the epoch.
+    pub expected:              f64,
+    /// Probability that the baker won no slots in the epoch by chance. A low
+    /// value means that the baker most likely was not working.
+    pub probability_by_chance: f64,
+}
+
+#[derive(Debug, Default)]
+/// Block production statistics of all bakers over a range of blocks.
+pub struct LuckReport {
+    /// Statistics of each baker.
+    pub bakers: BTreeMap<BakerId, BakerLuck>,
+    /// Bakers that produced no blocks in an epoch, in order of epochs.
+    pub idle:   Vec<IdleBaker>,
+    /// Number of slots analyzed.
+    pub slots:  u64,
+    /// Number of blocks in the analyzed range.
+    pub blocks: u64,
+}
+
+/// Slots of a single epoch that are part of the analysis.
+struct EpochSegment {
+    epoch:      EraEpoch,
+    first_slot: u64,
+    last_slot:  u64,
+    difficulty: f64,
+    bakers:     Vec<BirkBaker>,
+    produced:   BTreeMap<BakerId, u64>,
+}
+
+impl LuckReport {
+    fn close(&mut self, segment: &EpochSegment) {
+        let slots = (segment.last_slot + 1).saturating_sub(segment.first_slot);
+        self.slots += slots;
+        for baker in segment.bakers.iter() {
+            let p = win_probability(segment.difficulty, baker.baker_lottery_power);
+            let expected = slots as f64 * p;
+            let actual = segment.produced.get(&baker.baker_id).copied().unwrap_or(0);
+            let entry = self.bakers.entry(baker.baker_id).or_insert(BakerLuck {
+                account:  baker.baker_account,
+                actual:   0,
+                expected: 0f64,
+                variance: 0f64,
+            });
+            entry.actual += actual;
+            entry.expected += expected;
+            entry.variance += slots as f64 * p * (1f64 - p);
+            if actual == 0 {
+                self.idle.push(IdleBaker {
+                    epoch: segment.epoch,
+                    baker_id: baker.baker_id,
+                    expected,
+                    probability_by_chance: (1f64 - p).powf(slots as f64),
+                });
+            }
+        }
+    }
+}
+
+/// Incrementally builds a [LuckReport] from consecutive finalized blocks.
+pub struct LuckAnalyzer {
+    client:       endpoints::Client,
+    epoch_length: u64,
+    current:      Option<EpochSegment>,
+    report:       LuckReport,
+}
+
+impl LuckAnalyzer {
+    /// Construct a new analyzer.
+    pub async fn new(mut client: endpoints::Client) -> QueryResult<Self> {
+        let consensus_info = client.get_consensus_status().await?;
+        let epoch_length = consensus_info.epoch_duration.num_milliseconds() as u64
+            / consensus_info.slot_duration.millis;
+        Ok(Self {
+            client,
+            epoch_length,
+            current: None,
+            report: LuckReport::default(),
+        })
+    }
+
+    /// Add the next block. Blocks must be added in order of increasing height
+    /// without gaps.
+    pub async fn add_block(&mut self, block: &FinalizedBlock) -> QueryResult<()> {
+        let slot = block.info.block_slot.slot;
+        let epoch = EraEpoch {
+            genesis_index: block.info.genesis_index,
+            epoch:         Epoch {
+                epoch: slot / self.epoch_length,
+            },
+        };
+        let first_slot = match self.current.take() {
+            Some(mut segment) if segment.epoch == epoch => {
+                segment.last_slot = slot;
+                self.current = Some(segment);
+                None
+            }
+            Some(mut segment) if segment.epoch.genesis_index == epoch.genesis_index => {
+                // The segment ends with the last slot of its epoch, and the
+                // new one starts with the first slot of the block's epoch.
+                segment.last_slot = (segment.epoch.epoch.epoch + 1) * self.epoch_length - 1;
+                self.report.close(&segment);
+                // Epochs without any finalized block still count, since the
+                // bakers had lottery power in them but produced no blocks. The
+                // bakers of the last epoch with a block are used for them.
+                segment.produced.clear();
+                for skipped in segment.epoch.epoch.epoch + 1..epoch.epoch.epoch {
+                    segment.epoch.epoch = Epoch { epoch: skipped };
+                    segment.first_slot = skipped * self.epoch_length;
+                    segment.last_slot = (skipped + 1) * self.epoch_length - 1;
+                    self.report.close(&segment);
+                }
+                Some(epoch.epoch.epoch * self.epoch_length)
+            }
+            Some(segment) => {
+                self.report.close(&segment);
+                Some(slot)
+            }
+            None => Some(slot),
+        };
+        if let Some(first_slot) = first_slot {
+            let birk = self
+                .client
+                .get_birk_parameters(&block.info.block_hash)
+                .await?;
+            self.current = Some(EpochSegment {
+                epoch,
+                first_slot,
+                last_slot: slot,
+                difficulty: birk.election_difficulty.as_f64(),
+                bakers: birk.bakers,
+                produced: BTreeMap::new(),
+            });
+        }
+        self.report.blocks += 1;
+        if let (Some(baker_id), Some(segment)) = (block.info.block_baker, self.current.as_mut()) {
+            *segment.produced.entry(baker_id).or_insert(0) += 1;
+        }
+        Ok(())
+    }
+
+    /// Finish the analysis and return the report.
+    pub fn finish(mut self) -> LuckReport {
+        if let Some(segment) = self.current.take() {
+            self.report.close(&segment);
+        }
+        self.report
+    }
+}
+
+/// Analyze block production in the finalized blocks in the given range of
+/// heights, both ends inclusive.
+pub async fn analyze_luck(
+    client: endpoints::Client,
+    from: AbsoluteBlockHeight,
+    to: AbsoluteBlockHeight,
+) -> QueryResult<LuckReport> {
+    let mut analyzer = LuckAnalyzer::new(client.clone()).await?;
+    let mut blocks = Box::pin(finalized_blocks(
+        client,
+        from,
+        StreamEnd::AtHeight(to),
+        std::time::Duration::from_secs(1),
+    ));
+    while let Some(block) = blocks.next().await {
+        analyzer.add_block(&block?).await?;
+    }
+    Ok(analyzer.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not close to {}",
+            actual,
+            expected
+        );
+    }
+
+    fn birk_baker(id: u64, baker_lottery_power: f64) -> BirkBaker {
+        BirkBaker {
+            baker_id: BakerId { id },
+            baker_lottery_power,
+            baker_account: AccountAddress([id as u8; 32]),
+        }
+    }
+
+    fn segment(epoch: u64, produced: &[(u64, u64)]) -> EpochSegment {
+        EpochSegment {
+            epoch:      EraEpoch {
+                genesis_index: 0.into(),
+                epoch:         Epoch { epoch },
+            },
+            first_slot: epoch * 100,
+            last_slot:  epoch * 100 + 99,
+            difficulty: 0.5,
+            bakers:     vec![birk_baker(1, 1.0), birk_baker(2, 0.5)],
+            produced:   produced
+                .iter()
+                .map(|&(id, n)| (BakerId { id }, n))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_win_probability() {
+        assert_close(win_probability(0.025, 1.0), 0.025);
+        assert_close(win_probability(0.025, 0.0), 0.0);
+        assert_close(win_probability(0.5, 0.5), 1.0 - 0.5f64.sqrt());
+        assert_close(win_probability(0.5, 2.0), 0.75);
+    }
+
+    #[test]
+    fn test_confidence_interval() {
+        let luck = BakerLuck {
+            account:  AccountAddress([0u8; 32]),
+            actual:   12,
+            expected: 10.0,
+            variance: 4.0,
+        };
+        assert_eq!(luck.confidence_interval(2.0), (6.0, 14.0));
+        assert_eq!(luck.luck_interval(2.0), Some((0.6, 1.4)));
+        assert_eq!(luck.luck(), Some(1.2));
+        assert!(luck.is_within(2.0));
+        assert!(!luck.is_within(0.5));
+        // The lower end is never negative.
+        let unlikely = BakerLuck {
+            expected: 1.0,
+            ..luck
+        };
+        assert_eq!(unlikely.confidence_interval(2.0), (0.0, 5.0));
+        let none = BakerLuck {
+            expected: 0.0,
+            ..unlikely
+        };
+        assert_eq!(none.luck(), None);
+    }
+
+    #[test]
+    fn test_close() {
+        let mut report = LuckReport::default();
+        report.close(&segment(3, &[(1, 40)]));
+        report.close(&segment(4, &[(1, 60), (2, 30)]));
+        assert_eq!(report.slots, 200);
+
+        // Baker 1 wins each slot with probability 0.5.
+        let first = &report.bakers[&BakerId { id: 1 }];
+        assert_eq!(first.actual, 100);
+        assert_close(first.expected, 100.0);
+        assert_close(first.variance, 50.0);
+
+        // Baker 2 produced nothing in epoch 3, which makes it idle there.
+        let p = 1.0 - 0.5f64.sqrt();
+        let second = &report.bakers[&BakerId { id: 2 }];
+        assert_eq!(second.actual, 30);
+        assert_close(second.expected, 200.0 * p);
+        assert_close(second.variance, 200.0 * p * (1.0 - p));
+        assert_eq!(report.idle.len(), 1);
+        let idle = &report.idle[0];
+        assert_eq!(idle.baker_id, BakerId { id: 2 });
+        assert_eq!(idle.epoch.epoch, Epoch { epoch: 3 });
+        assert_close(idle.expected, 100.0 * p);
+        assert_close(idle.probability_by_chance, 0.5f64.sqrt().powf(100.0));
+    }
+}
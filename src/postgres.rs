@@ -1,18 +1,36 @@
 use crate::types::{
     hashes::BlockHash, AbsoluteBlockHeight, BlockItemSummary, ContractAddress,
-    SpecialTransactionOutcome,
+    SpecialTransactionOutcome, TransactionType,
 };
 use crypto_common::{types::Timestamp, SerdeDeserialize, SerdeSerialize};
 use futures::StreamExt;
 use id::types::AccountAddress;
-use std::convert::TryInto;
-use tokio::task::{JoinError, JoinHandle};
+use std::{
+    collections::VecDeque,
+    convert::TryInto,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::broadcast,
+    task::{JoinError, JoinHandle},
+};
 use tokio_postgres::{
     types::{BorrowToSql, ToSql},
-    RowStream,
+    AsyncMessage, Connection, Notification, RowStream,
 };
 pub use tokio_postgres::{Config, Error, NoTls};
 
+pub mod aggregates;
+pub mod cursor;
+pub mod indexer;
+pub mod pool;
+pub mod storage;
+
 #[derive(SerdeSerialize, SerdeDeserialize, Debug, Clone)]
 pub enum DatabaseSummaryEntry {
     #[serde(rename = "Left")]
@@ -70,18 +88,73 @@ enum QueryStatement {
 struct QueryStatements {
     /// Prepared statement that is used to query accounts in ascending order.
     /// It has 3 placeholders, for account address, `id` start and limit.
-    query_account_statement_asc:   QueryStatement,
+    query_account_statement_asc:     QueryStatement,
     /// Prepared statement that is used to query contracts in ascending order.
     /// It has 4 placeholders, for contract index and subindex, `id` start and
     /// limit.
-    query_contract_statement_asc:  QueryStatement,
+    query_contract_statement_asc:    QueryStatement,
     /// Prepared statement that is used to query contracts in descending order.
     /// It has 3 placeholders, for account address, `id` start and limit.
-    query_account_statement_desc:  QueryStatement,
+    query_account_statement_desc:    QueryStatement,
     /// Prepared statement that is used to query contracts in descending order.
     /// It has 4 placeholders, for contract index and subindex, `id` start and
     /// limit.
-    query_contract_statement_desc: QueryStatement,
+    query_contract_statement_desc:   QueryStatement,
+    /// Statements that query accounts in a range of block heights or block
+    /// times. They have 5 placeholders, for account address, the start and
+    /// end of the range, `id` start and limit.
+    query_account_range_statements:  RangeStatements,
+    /// Statements that query contracts in a range of block heights or block
+    /// times. They have 6 placeholders, for contract index and subindex, the
+    /// start and end of the range, `id` start and limit.
+    query_contract_range_statements: RangeStatements,
+}
+
+/// Statements for querying rows in a range of blocks, for each kind of range
+/// and order.
+struct RangeStatements {
+    height_asc:  QueryStatement,
+    height_desc: QueryStatement,
+    time_asc:    QueryStatement,
+    time_desc:   QueryStatement,
+}
+
+impl QueryStatement {
+    async fn create(
+        client: &tokio_postgres::Client,
+        statement: &'static str,
+        prepared: bool,
+    ) -> Result<Self, tokio_postgres::Error> {
+        if prepared {
+            Ok(QueryStatement::Prepared(client.prepare(statement).await?))
+        } else {
+            Ok(QueryStatement::Raw(statement))
+        }
+    }
+}
+
+impl RangeStatements {
+    async fn create(
+        client: &tokio_postgres::Client,
+        [height_asc, height_desc, time_asc, time_desc]: [&'static str; 4],
+        prepared: bool,
+    ) -> Result<Self, tokio_postgres::Error> {
+        Ok(Self {
+            height_asc:  QueryStatement::create(client, height_asc, prepared).await?,
+            height_desc: QueryStatement::create(client, height_desc, prepared).await?,
+            time_asc:    QueryStatement::create(client, time_asc, prepared).await?,
+            time_desc:   QueryStatement::create(client, time_desc, prepared).await?,
+        })
+    }
+
+    fn get(&self, range: BlockRange, order: QueryOrder) -> &QueryStatement {
+        match (range, order) {
+            (BlockRange::Height { .. }, QueryOrder::Ascending { .. }) => &self.height_asc,
+            (BlockRange::Height { .. }, QueryOrder::Descending { .. }) => &self.height_desc,
+            (BlockRange::Time { .. }, QueryOrder::Ascending { .. }) => &self.time_asc,
+            (BlockRange::Time { .. }, QueryOrder::Descending { .. }) => &self.time_desc,
+        }
+    }
 }
 
 impl QueryStatements {
@@ -146,11 +219,74 @@ impl QueryStatements {
                 QueryStatement::Raw(statement)
             }
         };
+
+        let query_account_range_statements = RangeStatements::create(
+            client,
+            [
+                "SELECT ati.id, summaries.block, summaries.timestamp, summaries.height, \
+                 summaries.summary
+ FROM ati JOIN summaries ON ati.summary = summaries.id
+ WHERE ati.account = $1 AND summaries.height BETWEEN $2 AND $3 AND ati.id >= $4
+ ORDER BY ati.id ASC, summaries.id ASC LIMIT $5",
+                "SELECT ati.id, summaries.block, summaries.timestamp, summaries.height, \
+                 summaries.summary
+ FROM ati JOIN summaries ON ati.summary = summaries.id
+ WHERE ati.account = $1 AND summaries.height BETWEEN $2 AND $3 AND ati.id <= $4
+ ORDER BY ati.id DESC, summaries.id DESC LIMIT $5",
+                "SELECT ati.id, summaries.block, summaries.timestamp, summaries.height, \
+                 summaries.summary
+ FROM ati JOIN summaries ON ati.summary = summaries.id
+ WHERE ati.account = $1 AND summaries.timestamp BETWEEN $2 AND $3 AND ati.id >= $4
+ ORDER BY ati.id ASC, summaries.id ASC LIMIT $5",
+                "SELECT ati.id, summaries.block, summaries.timestamp, summaries.height, \
+                 summaries.summary
+ FROM ati JOIN summaries ON ati.summary = summaries.id
+ WHERE ati.account = $1 AND summaries.timestamp BETWEEN $2 AND $3 AND ati.id <= $4
+ ORDER BY ati.id DESC, summaries.id DESC LIMIT $5",
+            ],
+            prepared,
+        )
+        .await?;
+
+        let query_contract_range_statements = RangeStatements::create(
+            client,
+            [
+                "SELECT cti.id, summaries.block, summaries.timestamp, summaries.height, \
+                 summaries.summary
+ FROM cti JOIN summaries ON cti.summary = summaries.id
+ WHERE cti.index = $1 AND cti.subindex = $2 AND summaries.height BETWEEN $3 AND $4
+ AND cti.id >= $5
+ ORDER BY cti.id ASC, summaries.id ASC LIMIT $6",
+                "SELECT cti.id, summaries.block, summaries.timestamp, summaries.height, \
+                 summaries.summary
+ FROM cti JOIN summaries ON cti.summary = summaries.id
+ WHERE cti.index = $1 AND cti.subindex = $2 AND summaries.height BETWEEN $3 AND $4
+ AND cti.id <= $5
+ ORDER BY cti.id DESC, summaries.id DESC LIMIT $6",
+                "SELECT cti.id, summaries.block, summaries.timestamp, summaries.height, \
+                 summaries.summary
+ FROM cti JOIN summaries ON cti.summary = summaries.id
+ WHERE cti.index = $1 AND cti.subindex = $2 AND summaries.timestamp BETWEEN $3 AND $4
+ AND cti.id >= $5
+ ORDER BY cti.id ASC, summaries.id ASC LIMIT $6",
+                "SELECT cti.id, summaries.block, summaries.timestamp, summaries.height, \
+                 summaries.summary
+ FROM cti JOIN summaries ON cti.summary = summaries.id
+ WHERE cti.index = $1 AND cti.subindex = $2 AND summaries.timestamp BETWEEN $3 AND $4
+ AND cti.id <= $5
+ ORDER BY cti.id DESC, summaries.id DESC LIMIT $6",
+            ],
+            prepared,
+        )
+        .await?;
+
         Ok(Self {
             query_account_statement_asc,
             query_contract_statement_asc,
             query_account_statement_desc,
             query_contract_statement_desc,
+            query_account_range_statements,
+            query_contract_range_statements,
         })
     }
 }
@@ -170,6 +306,14 @@ pub struct DatabaseClient {
     connection_handle: JoinHandle<Result<(), tokio_postgres::Error>>,
     database_client:   tokio_postgres::Client,
     statements:        QueryStatements,
+    /// Notifications received on the connection, for channels the client
+    /// listens on.
+    notifications:     broadcast::Sender<Notification>,
+    /// Number of rows skipped by the lenient queries because they could not
+    /// be parsed.
+    skipped_rows:      Arc<AtomicU64>,
+    /// Statements of [DatabaseClient::insert_block], prepared on first use.
+    insert_statements: Option<indexer::InsertStatements>,
 }
 
 impl DatabaseClient {
@@ -178,6 +322,18 @@ impl DatabaseClient {
         self.connection_handle.abort();
         self.connection_handle.await
     }
+
+    /// Whether the connection to the database has been lost. A client whose
+    /// connection is lost cannot be used anymore, and a new one must be
+    /// created.
+    pub fn is_closed(&self) -> bool { self.database_client.is_closed() }
+
+    /// Number of rows that the queries returning [DatabaseRow]s have skipped
+    /// because they could not be parsed, since the client was created. Use the
+    /// `_strict` variants of the queries to get the reason for each. Clients of
+    /// a [Pool](pool::Pool) share the count, see
+    /// [Pool::skipped_rows](pool::Pool::skipped_rows).
+    pub fn skipped_rows(&self) -> u64 { self.skipped_rows.load(Ordering::Relaxed) }
 }
 
 /// This implementation enables direct queries on the underlying database
@@ -193,27 +349,63 @@ impl AsMut<tokio_postgres::Client> for DatabaseClient {
     fn as_mut(&mut self) -> &mut tokio_postgres::Client { &mut self.database_client }
 }
 
+/// Number of notifications that are buffered for each subscription.
+const NOTIFICATION_BUFFER_SIZE: usize = 1024;
+
+/// Drive the connection and forward the notifications received on it.
+async fn forward_notifications<S, T>(
+    mut connection: Connection<S, T>,
+    sender: broadcast::Sender<Notification>,
+) -> Result<(), tokio_postgres::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    T: AsyncRead + AsyncWrite + Unpin, {
+    let mut messages = futures::stream::poll_fn(move |cx| connection.poll_message(cx));
+    while let Some(message) = messages.next().await {
+        if let AsyncMessage::Notification(notification) = message? {
+            // There being no subscribers is not an error.
+            let _ = sender.send(notification);
+        }
+    }
+    Ok(())
+}
+
 impl DatabaseClient {
-    /// Create a connection to the database. This does not create any prepared
-    /// statements. If the database and its tables already exist prefer
-    /// [DatabaseClient::create_prepared], however if the database tables do not
-    /// yet exist then use this method to build it.
-    pub async fn create<T: tokio_postgres::tls::MakeTlsConnect<tokio_postgres::Socket>>(
+    async fn connect<T: tokio_postgres::tls::MakeTlsConnect<tokio_postgres::Socket>>(
         config: tokio_postgres::Config,
         tls: T,
+        prepared: bool,
     ) -> Result<DatabaseClient, tokio_postgres::Error>
     where
         T::Stream: Send + 'static, {
         let (database_client, connection) = config.connect(tls).await?;
-        let connection_handle = tokio::spawn(connection);
-        let statements = QueryStatements::create(&database_client, false).await?;
+        let (notifications, _) = broadcast::channel(NOTIFICATION_BUFFER_SIZE);
+        let connection_handle =
+            tokio::spawn(forward_notifications(connection, notifications.clone()));
+        let statements = QueryStatements::create(&database_client, prepared).await?;
         Ok(DatabaseClient {
             connection_handle,
             database_client,
             statements,
+            notifications,
+            skipped_rows: Arc::new(AtomicU64::new(0)),
+            insert_statements: None,
         })
     }
 
+    /// Create a connection to the database. This does not create any prepared
+    /// statements. If the database and its tables already exist prefer
+    /// [DatabaseClient::create_prepared], however if the database tables do not
+    /// yet exist then use this method to build it.
+    pub async fn create<T: tokio_postgres::tls::MakeTlsConnect<tokio_postgres::Socket>>(
+        config: tokio_postgres::Config,
+        tls: T,
+    ) -> Result<DatabaseClient, tokio_postgres::Error>
+    where
+        T::Stream: Send + 'static, {
+        Self::connect(config, tls, false).await
+    }
+
     /// Like [DatabaseClient::create] but creates prepared statements and thus
     /// requires all the necessary database tables to already exist. Use this
     /// when using the database in read-only mode.
@@ -223,14 +415,7 @@ impl DatabaseClient {
     ) -> Result<DatabaseClient, tokio_postgres::Error>
     where
         T::Stream: Send + 'static, {
-        let (database_client, connection) = config.connect(tls).await?;
-        let connection_handle = tokio::spawn(connection);
-        let statements = QueryStatements::create(&database_client, true).await?;
-        Ok(DatabaseClient {
-            connection_handle,
-            database_client,
-            statements,
-        })
+        Self::connect(config, tls, true).await
     }
 }
 
@@ -249,6 +434,146 @@ pub enum QueryOrder {
     },
 }
 
+#[derive(SerdeSerialize, SerdeDeserialize, Debug, Clone, Copy)]
+/// A range of blocks to restrict results to. Both ends of the range are
+/// inclusive.
+pub enum BlockRange {
+    /// Blocks with height in the given range.
+    Height {
+        from: AbsoluteBlockHeight,
+        to:   AbsoluteBlockHeight,
+    },
+    /// Blocks with slot time in the given range.
+    Time { from: Timestamp, to: Timestamp },
+}
+
+impl BlockRange {
+    /// The ends of the range as they are stored in the database.
+    fn bounds(self) -> (i64, i64) {
+        match self {
+            BlockRange::Height { from, to } => (from.height as i64, to.height as i64),
+            BlockRange::Time { from, to } => (from.millis as i64, to.millis as i64),
+        }
+    }
+}
+
+impl QueryOrder {
+    /// The `id` to start from, defaulting to the first or last row.
+    fn start(self) -> i64 {
+        match self {
+            QueryOrder::Ascending { start } => start.unwrap_or(i64::MIN),
+            QueryOrder::Descending { start } => start.unwrap_or(i64::MAX),
+        }
+    }
+}
+
+#[derive(SerdeSerialize, SerdeDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+/// The kind of a [DatabaseSummaryEntry].
+pub enum SummaryKind {
+    /// [DatabaseSummaryEntry::BlockItem].
+    BlockItem,
+    /// [DatabaseSummaryEntry::ProtocolEvent].
+    ProtocolEvent,
+}
+
+#[derive(SerdeSerialize, SerdeDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+/// Outcome of a block item.
+pub enum ItemOutcome {
+    /// The block item had its intended effect.
+    Success,
+    /// The block item was rejected. Only the sender was charged.
+    Reject,
+}
+
+#[derive(SerdeSerialize, SerdeDeserialize, Debug, Clone, Default)]
+/// Conditions on the rows returned by [DatabaseClient::query_account_filtered]
+/// and [DatabaseClient::query_contract_filtered]. The conditions are checked
+/// by the database, so a query with a limit returns that many matching rows if
+/// they exist. A row is returned if it satisfies all the given conditions.
+pub struct QueryFilter {
+    /// Only return entries of the given kind.
+    pub kind:              Option<SummaryKind>,
+    /// Only return account transactions of one of the given types. If empty
+    /// transactions of all types are returned, as well as other entries.
+    pub transaction_types: Vec<TransactionType>,
+    /// Only return block items with the given outcome.
+    pub outcome:           Option<ItemOutcome>,
+    /// Only return entries in the given range of blocks.
+    pub range:             Option<BlockRange>,
+}
+
+/// Builder of a query with a dynamic number of parameters.
+struct DynamicQuery {
+    sql:    String,
+    params: Vec<Box<dyn ToSql + Sync + Send>>,
+}
+
+impl DynamicQuery {
+    fn new() -> Self {
+        Self {
+            sql:    String::new(),
+            params: Vec::new(),
+        }
+    }
+
+    /// Add a parameter and return its placeholder.
+    fn param(&mut self, value: impl ToSql + Sync + Send + 'static) -> String {
+        self.params.push(Box::new(value));
+        format!("${}", self.params.len())
+    }
+
+    /// Add the conditions of the filter to the `WHERE` clause.
+    fn add_filter(&mut self, filter: &QueryFilter) {
+        match filter.kind {
+            Some(SummaryKind::BlockItem) => self
+                .sql
+                .push_str(" AND summaries.summary->'Left' IS NOT NULL"),
+            Some(SummaryKind::ProtocolEvent) => self
+                .sql
+                .push_str(" AND summaries.summary->'Right' IS NOT NULL"),
+            None => (),
+        }
+        if !filter.transaction_types.is_empty() {
+            let types = filter
+                .transaction_types
+                .iter()
+                .filter_map(|tt| match serde_json::to_value(tt) {
+                    Ok(serde_json::Value::String(s)) => Some(s),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            let placeholder = self.param(types);
+            self.sql.push_str(&format!(
+                " AND summaries.summary->'Left'->'type'->>'type' = 'accountTransaction' AND \
+                 summaries.summary->'Left'->'type'->>'contents' = ANY({})",
+                placeholder
+            ));
+        }
+        if let Some(outcome) = filter.outcome {
+            let outcome = match outcome {
+                ItemOutcome::Success => "success",
+                ItemOutcome::Reject => "reject",
+            };
+            let placeholder = self.param(outcome);
+            self.sql.push_str(&format!(
+                " AND summaries.summary->'Left'->'result'->>'outcome' = {}",
+                placeholder
+            ));
+        }
+        if let Some(range) = filter.range {
+            let column = match range {
+                BlockRange::Height { .. } => "summaries.height",
+                BlockRange::Time { .. } => "summaries.timestamp",
+            };
+            let (from, to) = range.bounds();
+            let from = self.param(from);
+            let to = self.param(to);
+            self.sql
+                .push_str(&format!(" AND {} BETWEEN {} AND {}", column, from, to));
+        }
+    }
+}
+
 impl DatabaseClient {
     async fn query<P, I>(&self, st: &QueryStatement, params: I) -> Result<RowStream, Error>
     where
@@ -261,16 +586,24 @@ impl DatabaseClient {
         }
     }
 
-    /// Get the list of transactions affecting the given account.
-    /// The return value is a stream of rows that have been parsed.
-    ///
-    /// The `limit` value limits the number of rows that will be returned.
-    pub async fn query_account<'a>(
-        &'a self,
-        acc: &'a AccountAddress,
+    /// Parse the rows, skipping the ones that cannot be parsed. Skipped rows
+    /// are counted and logged. Database errors are returned.
+    fn parse_lenient(
+        &self,
+        rows: RowStream,
+    ) -> impl futures::stream::Stream<Item = Result<DatabaseRow, tokio_postgres::Error>> {
+        let skipped = self.skipped_rows.clone();
+        rows.filter_map(move |row_or_err| {
+            futures::future::ready(construct_row(row_or_err, &skipped))
+        })
+    }
+
+    async fn query_account_rows(
+        &self,
+        acc: &AccountAddress,
         limit: i64,
         order: QueryOrder,
-    ) -> Result<impl futures::stream::Stream<Item = DatabaseRow>, tokio_postgres::Error> {
+    ) -> Result<RowStream, tokio_postgres::Error> {
         let (statement, start) = match order {
             QueryOrder::Ascending { start } => (
                 &self.statements.query_account_statement_asc,
@@ -288,20 +621,50 @@ impl DatabaseClient {
             &limit as &(dyn ToSql + Sync),
         ];
 
-        let rows = self.query(statement, params).await?;
-        Ok(rows.filter_map(|row_or_err| async move { construct_row(row_or_err) }))
+        self.query(statement, params).await
     }
 
-    /// Get the list of transactions affecting the given contract.
-    /// The return value is a stream of rows that have been parsed.
+    /// Get the list of transactions affecting the given account.
+    /// The return value is a stream of rows that have been parsed. Rows that
+    /// cannot be parsed are skipped, see [DatabaseClient::skipped_rows].
+    /// Database errors, e.g., because the connection was lost, are returned in
+    /// the stream.
     ///
     /// The `limit` value limits the number of rows that will be returned.
-    pub async fn query_contract(
+    pub async fn query_account<'a>(
+        &'a self,
+        acc: &'a AccountAddress,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, tokio_postgres::Error>>,
+        tokio_postgres::Error,
+    > {
+        let rows = self.query_account_rows(acc, limit, order).await?;
+        Ok(self.parse_lenient(rows))
+    }
+
+    /// Like [DatabaseClient::query_account], but rows that cannot be parsed
+    /// are returned as errors instead of being skipped.
+    pub async fn query_account_strict(
+        &self,
+        acc: &AccountAddress,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, RowError>>,
+        tokio_postgres::Error,
+    > {
+        let rows = self.query_account_rows(acc, limit, order).await?;
+        Ok(rows.map(construct_row_strict))
+    }
+
+    async fn query_contract_rows(
         &self,
         c: ContractAddress,
         limit: i64,
         order: QueryOrder,
-    ) -> Result<impl futures::stream::Stream<Item = DatabaseRow>, tokio_postgres::Error> {
+    ) -> Result<RowStream, tokio_postgres::Error> {
         let (statement, start) = match order {
             QueryOrder::Ascending { start } => (
                 &self.statements.query_contract_statement_asc,
@@ -320,8 +683,315 @@ impl DatabaseClient {
             limit,
         ];
 
-        let rows = self.query(statement, &params).await?;
-        Ok(rows.filter_map(|row_or_err| async move { construct_row(row_or_err) }))
+        self.query(statement, &params).await
+    }
+
+    /// Get the list of transactions affecting the given contract.
+    /// The return value is a stream of rows that have been parsed. Rows that
+    /// cannot be parsed are skipped, see [DatabaseClient::skipped_rows].
+    /// Database errors, e.g., because the connection was lost, are returned in
+    /// the stream.
+    ///
+    /// The `limit` value limits the number of rows that will be returned.
+    pub async fn query_contract(
+        &self,
+        c: ContractAddress,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, tokio_postgres::Error>>,
+        tokio_postgres::Error,
+    > {
+        let rows = self.query_contract_rows(c, limit, order).await?;
+        Ok(self.parse_lenient(rows))
+    }
+
+    /// Like [DatabaseClient::query_contract], but rows that cannot be parsed
+    /// are returned as errors instead of being skipped.
+    pub async fn query_contract_strict(
+        &self,
+        c: ContractAddress,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, RowError>>,
+        tokio_postgres::Error,
+    > {
+        let rows = self.query_contract_rows(c, limit, order).await?;
+        Ok(rows.map(construct_row_strict))
+    }
+
+    async fn query_account_range_rows(
+        &self,
+        acc: &AccountAddress,
+        range: BlockRange,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<RowStream, tokio_postgres::Error> {
+        let statement = self
+            .statements
+            .query_account_range_statements
+            .get(range, order);
+        let (from, to) = range.bounds();
+        let start = order.start();
+        let acc_raw: &[u8] = acc.as_ref();
+        let params = [
+            &acc_raw as &(dyn ToSql + Sync),
+            &from as &(dyn ToSql + Sync),
+            &to as &(dyn ToSql + Sync),
+            &start as &(dyn ToSql + Sync),
+            &limit as &(dyn ToSql + Sync),
+        ];
+
+        self.query(statement, params).await
+    }
+
+    /// Like [DatabaseClient::query_account], but only returns transactions in
+    /// blocks in the given range.
+    pub async fn query_account_range<'a>(
+        &'a self,
+        acc: &'a AccountAddress,
+        range: BlockRange,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, tokio_postgres::Error>>,
+        tokio_postgres::Error,
+    > {
+        let rows = self
+            .query_account_range_rows(acc, range, limit, order)
+            .await?;
+        Ok(self.parse_lenient(rows))
+    }
+
+    /// Like [DatabaseClient::query_account_range], but rows that cannot be
+    /// parsed are returned as errors instead of being skipped.
+    pub async fn query_account_range_strict(
+        &self,
+        acc: &AccountAddress,
+        range: BlockRange,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, RowError>>,
+        tokio_postgres::Error,
+    > {
+        let rows = self
+            .query_account_range_rows(acc, range, limit, order)
+            .await?;
+        Ok(rows.map(construct_row_strict))
+    }
+
+    async fn query_contract_range_rows(
+        &self,
+        c: ContractAddress,
+        range: BlockRange,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<RowStream, tokio_postgres::Error> {
+        let statement = self
+            .statements
+            .query_contract_range_statements
+            .get(range, order);
+        let (from, to) = range.bounds();
+
+        let params: [i64; 6] = [
+            u64::from(c.index) as i64,
+            u64::from(c.subindex) as i64,
+            from,
+            to,
+            order.start(),
+            limit,
+        ];
+
+        self.query(statement, &params).await
+    }
+
+    /// Like [DatabaseClient::query_contract], but only returns transactions in
+    /// blocks in the given range.
+    pub async fn query_contract_range(
+        &self,
+        c: ContractAddress,
+        range: BlockRange,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, tokio_postgres::Error>>,
+        tokio_postgres::Error,
+    > {
+        let rows = self
+            .query_contract_range_rows(c, range, limit, order)
+            .await?;
+        Ok(self.parse_lenient(rows))
+    }
+
+    /// Like [DatabaseClient::query_contract_range], but rows that cannot be
+    /// parsed are returned as errors instead of being skipped.
+    pub async fn query_contract_range_strict(
+        &self,
+        c: ContractAddress,
+        range: BlockRange,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, RowError>>,
+        tokio_postgres::Error,
+    > {
+        let rows = self
+            .query_contract_range_rows(c, range, limit, order)
+            .await?;
+        Ok(rows.map(construct_row_strict))
+    }
+
+    /// Complete the query of the rows of the given index table, which already
+    /// selects the account or contract, with the conditions of the filter and
+    /// the order, and run it.
+    async fn query_filtered(
+        &self,
+        mut query: DynamicQuery,
+        table: &str,
+        filter: &QueryFilter,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<RowStream, tokio_postgres::Error> {
+        let (comparison, direction) = match order {
+            QueryOrder::Ascending { .. } => (">=", "ASC"),
+            QueryOrder::Descending { .. } => ("<=", "DESC"),
+        };
+        let start = query.param(order.start());
+        query
+            .sql
+            .push_str(&format!(" AND {}.id {} {}", table, comparison, start));
+        query.add_filter(filter);
+        let limit = query.param(limit);
+        query.sql.push_str(&format!(
+            " ORDER BY {table}.id {direction}, summaries.id {direction} LIMIT {limit}",
+            table = table,
+            direction = direction,
+            limit = limit
+        ));
+        let params = query
+            .params
+            .iter()
+            .map(|param| param.as_ref() as &(dyn ToSql + Sync));
+        self.as_ref().query_raw(query.sql.as_str(), params).await
+    }
+
+    async fn query_account_filtered_rows(
+        &self,
+        acc: &AccountAddress,
+        filter: &QueryFilter,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<RowStream, tokio_postgres::Error> {
+        let mut query = DynamicQuery {
+            sql:    String::from(
+                "SELECT ati.id, summaries.block, summaries.timestamp, summaries.height, \
+                 summaries.summary FROM ati JOIN summaries ON ati.summary = summaries.id WHERE \
+                 ati.account = ",
+            ),
+            params: Vec::new(),
+        };
+        let account = query.param(AsRef::<[u8]>::as_ref(acc).to_vec());
+        query.sql.push_str(&account);
+        self.query_filtered(query, "ati", filter, limit, order)
+            .await
+    }
+
+    /// Like [DatabaseClient::query_account], but only returns the rows that
+    /// satisfy the filter.
+    pub async fn query_account_filtered(
+        &self,
+        acc: &AccountAddress,
+        filter: &QueryFilter,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, tokio_postgres::Error>>,
+        tokio_postgres::Error,
+    > {
+        let rows = self
+            .query_account_filtered_rows(acc, filter, limit, order)
+            .await?;
+        Ok(self.parse_lenient(rows))
+    }
+
+    /// Like [DatabaseClient::query_account_filtered], but rows that cannot be
+    /// parsed are returned as errors instead of being skipped.
+    pub async fn query_account_filtered_strict(
+        &self,
+        acc: &AccountAddress,
+        filter: &QueryFilter,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, RowError>>,
+        tokio_postgres::Error,
+    > {
+        let rows = self
+            .query_account_filtered_rows(acc, filter, limit, order)
+            .await?;
+        Ok(rows.map(construct_row_strict))
+    }
+
+    async fn query_contract_filtered_rows(
+        &self,
+        c: ContractAddress,
+        filter: &QueryFilter,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<RowStream, tokio_postgres::Error> {
+        let mut query = DynamicQuery {
+            sql:    String::from(
+                "SELECT cti.id, summaries.block, summaries.timestamp, summaries.height, \
+                 summaries.summary FROM cti JOIN summaries ON cti.summary = summaries.id WHERE ",
+            ),
+            params: Vec::new(),
+        };
+        let index = query.param(u64::from(c.index) as i64);
+        let subindex = query.param(u64::from(c.subindex) as i64);
+        query.sql.push_str(&format!(
+            "cti.index = {} AND cti.subindex = {}",
+            index, subindex
+        ));
+        self.query_filtered(query, "cti", filter, limit, order)
+            .await
+    }
+
+    /// Like [DatabaseClient::query_contract], but only returns the rows that
+    /// satisfy the filter.
+    pub async fn query_contract_filtered(
+        &self,
+        c: ContractAddress,
+        filter: &QueryFilter,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, tokio_postgres::Error>>,
+        tokio_postgres::Error,
+    > {
+        let rows = self
+            .query_contract_filtered_rows(c, filter, limit, order)
+            .await?;
+        Ok(self.parse_lenient(rows))
+    }
+
+    /// Like [DatabaseClient::query_contract_filtered], but rows that cannot be
+    /// parsed are returned as errors instead of being skipped.
+    pub async fn query_contract_filtered_strict(
+        &self,
+        c: ContractAddress,
+        filter: &QueryFilter,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, RowError>>,
+        tokio_postgres::Error,
+    > {
+        let rows = self
+            .query_contract_filtered_rows(c, filter, limit, order)
+            .await?;
+        Ok(rows.map(construct_row_strict))
     }
 
     /// Return all transactions affecting the account, starting with the given
@@ -330,7 +1000,10 @@ impl DatabaseClient {
         &self,
         acc: &AccountAddress,
         start: Option<i64>,
-    ) -> Result<impl futures::stream::Stream<Item = DatabaseRow>, tokio_postgres::Error> {
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, tokio_postgres::Error>>,
+        tokio_postgres::Error,
+    > {
         self.query_account(acc, i64::MAX, QueryOrder::Ascending { start })
             .await
     }
@@ -341,26 +1014,419 @@ impl DatabaseClient {
         &self,
         addr: ContractAddress,
         start: Option<i64>,
-    ) -> Result<impl futures::stream::Stream<Item = DatabaseRow>, tokio_postgres::Error> {
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, tokio_postgres::Error>>,
+        tokio_postgres::Error,
+    > {
         self.query_contract(addr, i64::MAX, QueryOrder::Ascending { start })
             .await
     }
+
+    /// Like [DatabaseClient::iterate_account], but rows that cannot be parsed
+    /// are returned as errors instead of being skipped.
+    pub async fn iterate_account_strict(
+        &self,
+        acc: &AccountAddress,
+        start: Option<i64>,
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, RowError>>,
+        tokio_postgres::Error,
+    > {
+        self.query_account_strict(acc, i64::MAX, QueryOrder::Ascending { start })
+            .await
+    }
+
+    /// Like [DatabaseClient::iterate_contract], but rows that cannot be parsed
+    /// are returned as errors instead of being skipped.
+    pub async fn iterate_contract_strict(
+        &self,
+        addr: ContractAddress,
+        start: Option<i64>,
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, RowError>>,
+        tokio_postgres::Error,
+    > {
+        self.query_contract_strict(addr, i64::MAX, QueryOrder::Ascending { start })
+            .await
+    }
 }
 
-/// Try to parse a row returned from the database.
-/// This deliberately turns all errors into `None` since in the context it is
-/// used errors should not occur.
-fn construct_row(
+/// Channel on which the triggers installed by
+/// [DatabaseClient::install_notification_triggers] announce new rows of the
+/// account transaction index. The payload is the account address in hex.
+pub const ACCOUNT_NOTIFICATION_CHANNEL: &str = "concordium_ati";
+
+/// Channel on which the triggers installed by
+/// [DatabaseClient::install_notification_triggers] announce new rows of the
+/// contract transaction index. The payload is `index:subindex` of the contract.
+pub const CONTRACT_NOTIFICATION_CHANNEL: &str = "concordium_cti";
+
+/// Number of rows queried at a time by subscriptions.
+const SUBSCRIPTION_BATCH_SIZE: i64 = 100;
+
+#[derive(SerdeSerialize, SerdeDeserialize, Debug, Clone, Copy)]
+/// The account or contract whose rows a subscription or page follows.
+enum Subject {
+    Account(AccountAddress),
+    Contract(ContractAddress),
+}
+
+impl Subject {
+    /// The channel and payload of notifications about new rows.
+    fn notification(&self) -> (&'static str, String) {
+        match self {
+            Subject::Account(acc) => (
+                ACCOUNT_NOTIFICATION_CHANNEL,
+                hex::encode(AsRef::<[u8]>::as_ref(acc)),
+            ),
+            Subject::Contract(c) => (
+                CONTRACT_NOTIFICATION_CHANNEL,
+                format!("{}:{}", u64::from(c.index), u64::from(c.subindex)),
+            ),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("Querying the database failed, the subscription can resume from row {next_id}: {source}")]
+/// Querying the database for a subscription failed, e.g., because the
+/// connection was lost. The subscription stream ends after this error.
+pub struct SubscriptionError {
+    /// The `id` of the next row that would have been delivered. Subscribing
+    /// again with this `start` resumes the subscription.
+    pub next_id: i64,
+    #[source]
+    pub source:  tokio_postgres::Error,
+}
+
+/// State of a subscription stream.
+struct Subscription<'a> {
+    db:            &'a DatabaseClient,
+    subject:       Subject,
+    /// The `id` of the next row to deliver.
+    next_id:       i64,
+    /// Rows queried but not yet delivered.
+    buffer:        VecDeque<DatabaseRow>,
+    /// One more than the `id` of the last row queried, including rows that
+    /// could not be parsed. Querying continues from here once the buffer is
+    /// empty, so that rows which cannot be parsed are only queried once.
+    fetched_until: i64,
+    /// Notifications if the triggers are installed.
+    notifications: Option<broadcast::Receiver<Notification>>,
+    poll_interval: std::time::Duration,
+    /// Whether querying the database failed, which ends the stream.
+    failed:        bool,
+}
+
+impl<'a> Subscription<'a> {
+    async fn fetch(&mut self) -> Result<(), tokio_postgres::Error> {
+        let order = QueryOrder::Ascending {
+            start: Some(self.next_id),
+        };
+        let rows = match self.subject {
+            Subject::Account(acc) => {
+                self.db
+                    .query_account_strict(&acc, SUBSCRIPTION_BATCH_SIZE, order)
+                    .await?
+                    .collect::<Vec<_>>()
+                    .await
+            }
+            Subject::Contract(c) => {
+                self.db
+                    .query_contract_strict(c, SUBSCRIPTION_BATCH_SIZE, order)
+                    .await?
+                    .collect::<Vec<_>>()
+                    .await
+            }
+        };
+        for row in rows {
+            match row {
+                Ok(row) => {
+                    self.fetched_until = row.id + 1;
+                    self.buffer.push_back(row);
+                }
+                Err(RowError::Database(e)) => return Err(e),
+                Err(e) => {
+                    // The row is skipped like in the lenient queries, but
+                    // later rows are still delivered.
+                    if let Some(id) = e.row_id() {
+                        self.fetched_until = id + 1;
+                    }
+                    self.db.skipped_rows.fetch_add(1, Ordering::Relaxed);
+                    log::warn!("Skipping a row of the transaction index: {}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait until there might be new rows, either because a notification about
+    /// the subject was received, or because the poll interval has passed.
+    async fn wait(&mut self) {
+        let deadline = tokio::time::Instant::now() + self.poll_interval;
+        let (channel, payload) = self.subject.notification();
+        let closed = match self.notifications.as_mut() {
+            None => false,
+            Some(receiver) => loop {
+                match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                    Err(_) => return,
+                    Ok(Ok(notification)) => {
+                        if notification.channel() == channel && notification.payload() == payload {
+                            return;
+                        }
+                    }
+                    // Some notifications were missed, one of which might be
+                    // about the subject.
+                    Ok(Err(broadcast::error::RecvError::Lagged(_))) => return,
+                    Ok(Err(broadcast::error::RecvError::Closed)) => break true,
+                }
+            },
+        };
+        if closed {
+            self.notifications = None;
+        }
+        tokio::time::sleep_until(deadline).await
+    }
+
+    /// Get the next row, waiting for it if needed. After an error `None` is
+    /// returned.
+    async fn next(mut self) -> Option<(Result<DatabaseRow, SubscriptionError>, Self)> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            if let Some(row) = self.buffer.pop_front() {
+                self.next_id = row.id + 1;
+                return Some((Ok(row), self));
+            }
+            self.next_id = std::cmp::max(self.next_id, self.fetched_until);
+            if let Err(source) = self.fetch().await {
+                self.failed = true;
+                let err = SubscriptionError {
+                    next_id: self.next_id,
+                    source,
+                };
+                return Some((Err(err), self));
+            }
+            if self.buffer.is_empty() {
+                self.wait().await;
+            }
+        }
+    }
+}
+
+impl DatabaseClient {
+    /// Install triggers that notify listeners about new rows in the account and
+    /// contract transaction indices, on the channels
+    /// [ACCOUNT_NOTIFICATION_CHANNEL] and [CONTRACT_NOTIFICATION_CHANNEL].
+    /// When they are installed subscriptions are notified of new rows as soon
+    /// as they are inserted, instead of polling. Installing them again replaces
+    /// the existing ones.
+    pub async fn install_notification_triggers(&self) -> Result<(), tokio_postgres::Error> {
+        let statements = format!(
+            "CREATE OR REPLACE FUNCTION {ati}() RETURNS trigger AS $$
+BEGIN
+  PERFORM pg_notify('{ati}', encode(NEW.account, 'hex'));
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+DROP TRIGGER IF EXISTS {ati} ON ati;
+CREATE TRIGGER {ati} AFTER INSERT ON ati FOR EACH ROW EXECUTE PROCEDURE {ati}();
+CREATE OR REPLACE FUNCTION {cti}() RETURNS trigger AS $$
+BEGIN
+  PERFORM pg_notify('{cti}', NEW.index || ':' || NEW.subindex);
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+DROP TRIGGER IF EXISTS {cti} ON cti;
+CREATE TRIGGER {cti} AFTER INSERT ON cti FOR EACH ROW EXECUTE PROCEDURE {cti}();",
+            ati = ACCOUNT_NOTIFICATION_CHANNEL,
+            cti = CONTRACT_NOTIFICATION_CHANNEL
+        );
+        self.as_ref().batch_execute(&statements).await
+    }
+
+    /// Whether the trigger notifying on the given channel is installed.
+    async fn has_trigger(&self, channel: &str) -> Result<bool, tokio_postgres::Error> {
+        let row = self
+            .as_ref()
+            .query_one(
+                "SELECT EXISTS (SELECT 1 FROM pg_trigger WHERE tgname = $1)",
+                &[&channel],
+            )
+            .await?;
+        Ok(row.get(0))
+    }
+
+    async fn subscribe(
+        &self,
+        subject: Subject,
+        start: Option<i64>,
+        poll_interval: std::time::Duration,
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, SubscriptionError>> + '_,
+        tokio_postgres::Error,
+    > {
+        let (channel, _) = subject.notification();
+        let notifications = if self.has_trigger(channel).await? {
+            // Subscribe before listening so that no notification is missed.
+            let receiver = self.notifications.subscribe();
+            self.as_ref()
+                .batch_execute(&format!("LISTEN {}", channel))
+                .await?;
+            Some(receiver)
+        } else {
+            None
+        };
+        let next_id = match start {
+            Some(start) => start,
+            None => {
+                let order = QueryOrder::Descending { start: None };
+                let mut last = match subject {
+                    Subject::Account(acc) => {
+                        self.query_account_strict(&acc, 1, order)
+                            .await?
+                            .collect::<Vec<_>>()
+                            .await
+                    }
+                    Subject::Contract(c) => {
+                        self.query_contract_strict(c, 1, order)
+                            .await?
+                            .collect::<Vec<_>>()
+                            .await
+                    }
+                };
+                // The last row counts even if it cannot be parsed.
+                match last.pop() {
+                    None => 0,
+                    Some(Ok(row)) => row.id + 1,
+                    Some(Err(RowError::Database(e))) => return Err(e),
+                    Some(Err(e)) => e.row_id().map_or(0, |id| id + 1),
+                }
+            }
+        };
+        let subscription = Subscription {
+            db: self,
+            subject,
+            next_id,
+            buffer: VecDeque::new(),
+            fetched_until: next_id,
+            notifications,
+            poll_interval,
+            failed: false,
+        };
+        Ok(futures::stream::unfold(subscription, Subscription::next))
+    }
+
+    /// Subscribe to transactions affecting the account. The returned stream
+    /// delivers the rows with `id` at least `start` in order, and then new rows
+    /// as they are added to the database. If `start` is not given only rows
+    /// added after the call are delivered.
+    ///
+    /// Rows are followed by their `id`, so the stream only delivers every row
+    /// if rows are committed in the order of their `id`s, which is the case
+    /// when there is a single writer, such as the node or one
+    /// [Indexer](indexer::Indexer). With concurrent writers a row whose
+    /// transaction commits after a row with a larger `id` was delivered is
+    /// missed.
+    ///
+    /// If the triggers installed by
+    /// [DatabaseClient::install_notification_triggers] are present, new rows
+    /// are queried as soon as the database announces them. Otherwise, and in
+    /// addition in case a notification is lost, the database is queried every
+    /// `poll_interval`.
+    ///
+    /// If querying the database fails, e.g., because the connection was lost,
+    /// the stream yields a [SubscriptionError] and ends. Subscribing again
+    /// with `start` set to its [next_id](SubscriptionError::next_id) resumes
+    /// the subscription without duplicates.
+    pub async fn subscribe_account(
+        &self,
+        acc: AccountAddress,
+        start: Option<i64>,
+        poll_interval: std::time::Duration,
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, SubscriptionError>> + '_,
+        tokio_postgres::Error,
+    > {
+        self.subscribe(Subject::Account(acc), start, poll_interval)
+            .await
+    }
+
+    /// Subscribe to transactions affecting the contract. This behaves like
+    /// [DatabaseClient::subscribe_account].
+    pub async fn subscribe_contract(
+        &self,
+        c: ContractAddress,
+        start: Option<i64>,
+        poll_interval: std::time::Duration,
+    ) -> Result<
+        impl futures::stream::Stream<Item = Result<DatabaseRow, SubscriptionError>> + '_,
+        tokio_postgres::Error,
+    > {
+        self.subscribe(Subject::Contract(c), start, poll_interval)
+            .await
+    }
+}
+
+#[derive(Error, Debug)]
+/// Reasons why a row of the transaction index could not be returned.
+pub enum RowError {
+    #[error("Database error: {0}")]
+    /// Querying the database failed, e.g., because the connection was lost.
+    Database(#[from] tokio_postgres::Error),
+    #[error("A row has an unexpected id: {source}")]
+    /// The id column of a row does not have the expected type.
+    Id { source: tokio_postgres::Error },
+    #[error("Row {id} has an unexpected column value: {source}")]
+    /// A column of the row does not have the expected type.
+    Column {
+        id:     i64,
+        source: tokio_postgres::Error,
+    },
+    #[error("Row {id} has a block hash of {length} bytes instead of 32.")]
+    /// The block hash of the row has the wrong length.
+    BlockHash { id: i64, length: usize },
+    #[error("The summary of row {id} could not be parsed: {source}")]
+    /// The summary is not a valid [DatabaseSummaryEntry]. This happens if the
+    /// node logs summaries in a format that the SDK does not yet support.
+    Summary {
+        id:     i64,
+        source: serde_json::Error,
+    },
+}
+
+impl RowError {
+    /// The id of the row that could not be parsed, if the error is about a
+    /// specific row.
+    pub fn row_id(&self) -> Option<i64> {
+        match self {
+            RowError::Database(_) => None,
+            RowError::Id { .. } => None,
+            RowError::Column { id, .. } => Some(*id),
+            RowError::BlockHash { id, .. } => Some(*id),
+            RowError::Summary { id, .. } => Some(*id),
+        }
+    }
+}
+
+/// Parse a row returned from the database.
+fn construct_row_strict(
     row_or_error: Result<tokio_postgres::Row, tokio_postgres::Error>,
-) -> Option<DatabaseRow> {
-    let row = row_or_error.ok()?;
-    let id = row.get(0);
-    let hash_bytes: &[u8] = row.get(1);
-    let block_hash = BlockHash::new(hash_bytes.try_into().ok()?);
-    let block_time = Timestamp::from(row.get::<_, i64>(2) as u64);
-    let block_height = AbsoluteBlockHeight::from(row.get::<_, i64>(3) as u64);
-    let summary = serde_json::from_value::<DatabaseSummaryEntry>(row.get(4)).ok()?;
-    Some(DatabaseRow {
+) -> Result<DatabaseRow, RowError> {
+    let row = row_or_error?;
+    let id: i64 = row.try_get(0).map_err(|source| RowError::Id { source })?;
+    let column = |source| RowError::Column { id, source };
+    let hash_bytes: &[u8] = row.try_get(1).map_err(column)?;
+    let block_hash = BlockHash::new(hash_bytes.try_into().map_err(|_| RowError::BlockHash {
+        id,
+        length: hash_bytes.len(),
+    })?);
+    let block_time = Timestamp::from(row.try_get::<_, i64>(2).map_err(column)? as u64);
+    let block_height = AbsoluteBlockHeight::from(row.try_get::<_, i64>(3).map_err(column)? as u64);
+    let summary = serde_json::from_value::<DatabaseSummaryEntry>(row.try_get(4).map_err(column)?)
+        .map_err(|source| RowError::Summary { id, source })?;
+    Ok(DatabaseRow {
         id,
         block_hash,
         block_time,
@@ -368,3 +1434,21 @@ fn construct_row(
         summary,
     })
 }
+
+/// Try to parse a row returned from the database. Rows that cannot be parsed
+/// are logged and counted in `skipped`, and `None` is returned. Database errors
+/// are not counted, but returned.
+fn construct_row(
+    row_or_error: Result<tokio_postgres::Row, tokio_postgres::Error>,
+    skipped: &AtomicU64,
+) -> Option<Result<DatabaseRow, tokio_postgres::Error>> {
+    match construct_row_strict(row_or_error) {
+        Ok(row) => Some(Ok(row)),
+        Err(RowError::Database(e)) => Some(Err(e)),
+        Err(e) => {
+            skipped.fetch_add(1, Ordering::Relaxed);
+            log::warn!("Skipping a row of the transaction index: {}", e);
+            None
+        }
+    }
+}
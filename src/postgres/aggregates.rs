@@ -0,0 +1,394 @@
+//! Aggregate statistics of the transaction index, computed by the database.
+//!
+//! Only block items are taken into account, protocol events such as rewards
+//! are not. The CCD moved by a block item is read from its `Transferred` and
+//! `Updated` events, so transfers with a schedule and encrypted transfers do
+//! not contribute to volumes. Transfers of an account to itself are not
+//! counted either.
+use super::{BlockRange, DatabaseClient, DynamicQuery, QueryFilter, Subject};
+use crate::types::{Address, Amount, ContractAddress, Energy};
+use id::types::AccountAddress;
+use thiserror::Error;
+use tokio_postgres::{
+    types::{Json, ToSql},
+    Row,
+};
+
+/// The events of the block item in `summaries`, for use in a `FROM` clause.
+const EVENTS: &str = "jsonb_array_elements(summaries.summary->'Left'->'result'->'events') AS e";
+
+#[derive(Debug, Clone, Copy)]
+/// Activity of an account or contract on a single day.
+pub struct DailyActivity {
+    /// The day, in UTC.
+    pub day:          chrono::NaiveDate,
+    /// Number of block items affecting the account or contract on the day.
+    pub transactions: u64,
+    /// The total amount transferred to and from the account or contract.
+    pub volume:       Amount,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Fees paid by an account for the transactions it sent.
+pub struct FeesPaid {
+    /// Number of transactions sent by the account.
+    pub transactions: u64,
+    /// The total amount the account was charged.
+    pub amount:       Amount,
+    /// The total energy used by the transactions.
+    pub energy:       Energy,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Transactions sent to a contract by a single account.
+pub struct SenderTotal {
+    /// The sender of the transactions.
+    pub sender:       AccountAddress,
+    /// Number of transactions that affected the contract.
+    pub transactions: u64,
+    /// The total amount the contract received in the transactions.
+    pub amount:       Amount,
+}
+
+#[derive(Error, Debug)]
+/// Errors that can occur when computing an aggregate.
+pub enum AggregateError {
+    #[error("Database error: {0}")]
+    /// Querying the database failed.
+    Database(#[from] tokio_postgres::Error),
+    #[error("The aggregate query returned no rows.")]
+    /// An aggregate without grouping returned no row, which the database
+    /// should never do.
+    NoRows,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How [DatabaseClient::top_senders] ranks the senders.
+pub enum SenderRanking {
+    /// By the number of transactions sent, then by the amount.
+    Transactions,
+    /// By the amount sent, then by the number of transactions.
+    Amount,
+}
+
+impl Subject {
+    /// The index table of the subject and the condition selecting its rows.
+    /// The parameters of the condition are added to the query.
+    fn index_condition(&self, query: &mut DynamicQuery) -> (&'static str, String) {
+        match self {
+            Subject::Account(acc) => {
+                let account = query.param(AsRef::<[u8]>::as_ref(acc).to_vec());
+                ("ati", format!("ati.account = {}", account))
+            }
+            Subject::Contract(c) => {
+                let index = query.param(u64::from(c.index) as i64);
+                let subindex = query.param(u64::from(c.subindex) as i64);
+                (
+                    "cti",
+                    format!("cti.index = {} AND cti.subindex = {}", index, subindex),
+                )
+            }
+        }
+    }
+}
+
+impl DynamicQuery {
+    /// A query of the amounts moved to and from the subject by the block item
+    /// in `summaries`, for use in a lateral join. It returns the
+    /// `counterparty` as JSON, the `amount` and whether it was `incoming`.
+    fn movements(&mut self, subject: &Subject) -> String {
+        let address = match subject {
+            Subject::Account(acc) => Address::Account(*acc),
+            Subject::Contract(c) => Address::Contract(*c),
+        };
+        let address =
+            self.param(serde_json::to_value(address).expect("Addresses can always be serialized."));
+        let mut sql = format!(
+            "SELECT e->'to' AS counterparty, (e->>'amount')::NUMERIC AS amount, FALSE AS incoming \
+             FROM {events} WHERE e->>'tag' = 'Transferred' AND e->'from' = {address} AND e->'to' \
+             <> {address} UNION ALL SELECT e->'from', (e->>'amount')::NUMERIC, TRUE FROM {events} \
+             WHERE e->>'tag' = 'Transferred' AND e->'to' = {address} AND e->'from' <> {address} \
+             UNION ALL SELECT jsonb_build_object('type', 'AddressContract', 'address', \
+             e->'address'), (e->>'amount')::NUMERIC, FALSE FROM {events} WHERE e->>'tag' = \
+             'Updated' AND e->'instigator' = {address}",
+            events = EVENTS,
+            address = address
+        );
+        if let Subject::Contract(c) = subject {
+            let contract =
+                self.param(serde_json::to_value(c).expect("Addresses can always be serialized."));
+            sql.push_str(&format!(
+                " UNION ALL SELECT e->'instigator', (e->>'amount')::NUMERIC, TRUE FROM {} WHERE \
+                 e->>'tag' = 'Updated' AND e->'address' = {}",
+                EVENTS, contract
+            ));
+        }
+        sql
+    }
+}
+
+/// Get a non-negative integer that the query returned as JSON. Decoding fails
+/// if it does not fit into a `u64`.
+fn get_u64(row: &Row, idx: usize) -> Result<u64, tokio_postgres::Error> {
+    row.try_get::<_, Json<u64>>(idx).map(|json| json.0)
+}
+
+fn get_count(row: &Row, idx: usize) -> Result<u64, tokio_postgres::Error> {
+    row.try_get::<_, i64>(idx).map(|count| count as u64)
+}
+
+impl DatabaseClient {
+    async fn query_aggregate(
+        &self,
+        query: &DynamicQuery,
+    ) -> Result<Vec<Row>, tokio_postgres::Error> {
+        let params = query
+            .params
+            .iter()
+            .map(|param| param.as_ref() as &(dyn ToSql + Sync))
+            .collect::<Vec<_>>();
+        self.as_ref().query(query.sql.as_str(), &params).await
+    }
+
+    async fn daily_activity(
+        &self,
+        subject: Subject,
+        filter: &QueryFilter,
+    ) -> Result<Vec<DailyActivity>, tokio_postgres::Error> {
+        let mut query = DynamicQuery::new();
+        let movements = query.movements(&subject);
+        let (table, condition) = subject.index_condition(&mut query);
+        query.sql = format!(
+            "SELECT (to_timestamp(summaries.timestamp / 1000.0) AT TIME ZONE 'UTC')::DATE AS day, \
+             COUNT(*), to_jsonb(COALESCE(SUM(volume.amount), 0)) FROM {table} JOIN summaries ON \
+             {table}.summary = summaries.id LEFT JOIN LATERAL (SELECT SUM(amount) AS amount FROM \
+             ({movements}) AS movement) AS volume ON TRUE WHERE {condition} AND \
+             summaries.summary->'Left' IS NOT NULL",
+            table = table,
+            movements = movements,
+            condition = condition
+        );
+        query.add_filter(filter);
+        query.sql.push_str(" GROUP BY day ORDER BY day");
+        self.query_aggregate(&query)
+            .await?
+            .iter()
+            .map(|row| {
+                Ok(DailyActivity {
+                    day:          row.try_get(0)?,
+                    transactions: get_count(row, 1)?,
+                    volume:       Amount {
+                        microgtu: get_u64(row, 2)?,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    /// The number of block items affecting the account and the amount
+    /// transferred to and from it, for each day with at least one block item
+    /// that satisfies the filter. The days are in increasing order.
+    pub async fn daily_account_activity(
+        &self,
+        acc: &AccountAddress,
+        filter: &QueryFilter,
+    ) -> Result<Vec<DailyActivity>, tokio_postgres::Error> {
+        self.daily_activity(Subject::Account(*acc), filter).await
+    }
+
+    /// Like [DatabaseClient::daily_account_activity], but for a contract. The
+    /// volume includes the amounts the contract is invoked with.
+    pub async fn daily_contract_activity(
+        &self,
+        c: ContractAddress,
+        filter: &QueryFilter,
+    ) -> Result<Vec<DailyActivity>, tokio_postgres::Error> {
+        self.daily_activity(Subject::Contract(c), filter).await
+    }
+
+    /// The total fees paid by the account for the transactions it sent that
+    /// satisfy the filter. Rejected transactions are charged as well.
+    pub async fn fees_paid(
+        &self,
+        acc: &AccountAddress,
+        filter: &QueryFilter,
+    ) -> Result<FeesPaid, AggregateError> {
+        let mut query = DynamicQuery::new();
+        let (table, condition) = Subject::Account(*acc).index_condition(&mut query);
+        let sender = query.param(acc.to_string());
+        query.sql = format!(
+            "SELECT COUNT(*), \
+             to_jsonb(COALESCE(SUM((summaries.summary->'Left'->>'cost')::NUMERIC), 0)), \
+             to_jsonb(COALESCE(SUM((summaries.summary->'Left'->>'energyCost')::NUMERIC), 0)) FROM \
+             {table} JOIN summaries ON {table}.summary = summaries.id WHERE {condition} AND \
+             summaries.summary->'Left'->>'sender' = {sender}",
+            table = table,
+            condition = condition,
+            sender = sender
+        );
+        query.add_filter(filter);
+        let rows = self.query_aggregate(&query).await?;
+        let row = rows.first().ok_or(AggregateError::NoRows)?;
+        Ok(FeesPaid {
+            transactions: get_count(row, 0)?,
+            amount:       Amount {
+                microgtu: get_u64(row, 1)?,
+            },
+            energy:       Energy {
+                energy: get_u64(row, 2)?,
+            },
+        })
+    }
+
+    async fn counterparties(
+        &self,
+        subject: Subject,
+        filter: &QueryFilter,
+    ) -> Result<u64, AggregateError> {
+        let mut query = DynamicQuery::new();
+        let movements = query.movements(&subject);
+        let (table, condition) = subject.index_condition(&mut query);
+        query.sql = format!(
+            "SELECT COUNT(DISTINCT movement.counterparty) FROM {table} JOIN summaries ON \
+             {table}.summary = summaries.id CROSS JOIN LATERAL ({movements}) AS movement WHERE \
+             {condition}",
+            table = table,
+            movements = movements,
+            condition = condition
+        );
+        query.add_filter(filter);
+        let rows = self.query_aggregate(&query).await?;
+        let row = rows.first().ok_or(AggregateError::NoRows)?;
+        Ok(get_count(row, 0)?)
+    }
+
+    /// The number of distinct accounts and contracts that the account
+    /// transferred CCD to or received CCD from, or whose contracts it invoked,
+    /// in the block items that satisfy the filter.
+    pub async fn account_counterparties(
+        &self,
+        acc: &AccountAddress,
+        filter: &QueryFilter,
+    ) -> Result<u64, AggregateError> {
+        self.counterparties(Subject::Account(*acc), filter).await
+    }
+
+    /// Like [DatabaseClient::account_counterparties], but for a contract. The
+    /// counterparties include the accounts and contracts that invoked it.
+    pub async fn contract_counterparties(
+        &self,
+        c: ContractAddress,
+        filter: &QueryFilter,
+    ) -> Result<u64, AggregateError> {
+        self.counterparties(Subject::Contract(c), filter).await
+    }
+
+    /// The accounts that sent the most transactions affecting the contract in
+    /// the given range of blocks, at most `limit` of them, ranked as given.
+    /// The amount of a sender is the total amount the contract was invoked
+    /// with, or received, in its transactions.
+    pub async fn top_senders(
+        &self,
+        c: ContractAddress,
+        range: BlockRange,
+        ranking: SenderRanking,
+        limit: u32,
+    ) -> Result<Vec<SenderTotal>, tokio_postgres::Error> {
+        let subject = Subject::Contract(c);
+        let mut query = DynamicQuery::new();
+        let movements = query.movements(&subject);
+        let (table, condition) = subject.index_condition(&mut query);
+        query.sql = format!(
+            "SELECT summaries.summary->'Left'->'sender', COUNT(*), \
+             to_jsonb(COALESCE(SUM(received.amount), 0)) FROM {table} JOIN summaries ON \
+             {table}.summary = summaries.id LEFT JOIN LATERAL (SELECT SUM(amount) AS amount FROM \
+             ({movements}) AS movement WHERE movement.incoming) AS received ON TRUE WHERE \
+             {condition} AND summaries.summary->'Left'->>'sender' IS NOT NULL",
+            table = table,
+            movements = movements,
+            condition = condition
+        );
+        query.add_filter(&QueryFilter {
+            range: Some(range),
+            ..QueryFilter::default()
+        });
+        let order = match ranking {
+            SenderRanking::Transactions => "COUNT(*) DESC, SUM(received.amount) DESC NULLS LAST",
+            SenderRanking::Amount => "SUM(received.amount) DESC NULLS LAST, COUNT(*) DESC",
+        };
+        let limit = query.param(i64::from(limit));
+        query.sql.push_str(&format!(
+            " GROUP BY summaries.summary->'Left'->'sender' ORDER BY {} LIMIT {}",
+            order, limit
+        ));
+        self.query_aggregate(&query)
+            .await?
+            .iter()
+            .map(|row| {
+                Ok(SenderTotal {
+                    sender:       row.try_get::<_, Json<AccountAddress>>(0)?.0,
+                    transactions: get_count(row, 1)?,
+                    amount:       Amount {
+                        microgtu: get_u64(row, 2)?,
+                    },
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The parameters of the query, as they are shown in logs.
+    fn params(query: &DynamicQuery) -> Vec<String> {
+        query
+            .params
+            .iter()
+            .map(|param| format!("{:?}", param))
+            .collect()
+    }
+
+    #[test]
+    fn test_index_condition() {
+        let acc = AccountAddress([1u8; 32]);
+        let mut query = DynamicQuery::new();
+        let (table, condition) = Subject::Account(acc).index_condition(&mut query);
+        assert_eq!(table, "ati");
+        assert_eq!(condition, "ati.account = $1");
+        assert_eq!(params(&query), vec![format!("{:?}", vec![1u8; 32])]);
+
+        // Placeholders continue after the existing parameters.
+        let c = ContractAddress::new(5.into(), 1.into());
+        let (table, condition) = Subject::Contract(c).index_condition(&mut query);
+        assert_eq!(table, "cti");
+        assert_eq!(condition, "cti.index = $2 AND cti.subindex = $3");
+        assert_eq!(&params(&query)[1..], &["5", "1"]);
+    }
+
+    #[test]
+    fn test_movements() {
+        let acc = AccountAddress([1u8; 32]);
+        let mut query = DynamicQuery::new();
+        let sql = query.movements(&Subject::Account(acc));
+        assert_eq!(sql.matches("UNION ALL").count(), 2);
+        assert_eq!(sql.matches("$1").count(), 5);
+        assert!(!sql.contains("$2"));
+        let address = serde_json::to_value(Address::Account(acc)).unwrap();
+        assert_eq!(params(&query), vec![format!("{:?}", address)]);
+
+        // Contracts also receive the amounts they are invoked with.
+        let c = ContractAddress::new(5.into(), 1.into());
+        let mut query = DynamicQuery::new();
+        let sql = query.movements(&Subject::Contract(c));
+        assert_eq!(sql.matches("UNION ALL").count(), 3);
+        assert!(sql.ends_with("WHERE e->>'tag' = 'Updated' AND e->'address' = $2"));
+        let address = serde_json::to_value(Address::Contract(c)).unwrap();
+        let contract = serde_json::to_value(c).unwrap();
+        assert_eq!(params(&query), vec![
+            format!("{:?}", address),
+            format!("{:?}", contract)
+        ]);
+    }
+}
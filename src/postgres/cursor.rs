@@ -0,0 +1,535 @@
+//! Opaque cursors for paginating through the transaction index.
+//!
+//! The row ids used by [QueryOrder] are internal to the database. A [Cursor]
+//! instead records everything needed to fetch the next page, the account or
+//! contract, the filter, the direction and the position, and is encrypted with
+//! ChaCha20-Poly1305 so that clients can neither read nor modify it. Services
+//! can thus hand cursors out in their APIs and resume from them without
+//! keeping any state.
+//!
+//! The position is a row id, and row ids are assigned anew when the database
+//! is rebuilt, so a cursor issued before a rebuild would point to the wrong
+//! rows afterwards. Each cursor therefore also records the row it continues
+//! from, by its id, block hash and transaction hash. When the cursor is used
+//! that row is looked up again, and if it is no longer there the cursor is
+//! rejected with [CursorError::Stale] instead of returning the wrong page.
+//! Clients then have to start from the first page again.
+use super::{DatabaseClient, DatabaseRow, DatabaseSummaryEntry, QueryFilter, QueryOrder, Subject};
+use crate::types::{
+    hashes::{BlockHash, TransactionHash},
+    ContractAddress,
+};
+use chacha20poly1305::{
+    aead::{Aead, NewAead, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use crypto_common::{SerdeDeserialize, SerdeSerialize};
+use futures::TryStreamExt;
+use id::types::AccountAddress;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// Version of the encoding of cursors.
+const CURSOR_VERSION: u8 = 1;
+
+/// Length of the nonce of a cursor.
+const NONCE_LENGTH: usize = 12;
+
+/// Length of the authentication tag of a cursor.
+const TAG_LENGTH: usize = 16;
+
+/// Secret key used to encrypt cursors. Cursors are only accepted by clients
+/// using the same key as the one that issued them.
+pub struct CursorKey {
+    cipher: ChaCha20Poly1305,
+}
+
+impl CursorKey {
+    /// Construct a key from a secret, which should be at least 32 random bytes.
+    /// The encryption key is the SHA-256 hash of the secret.
+    pub fn new(secret: impl AsRef<[u8]>) -> Self {
+        let key = Sha256::digest(secret.as_ref());
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+        }
+    }
+
+    /// Generate a random key.
+    pub fn generate() -> Self {
+        let secret: [u8; 32] = rand::thread_rng().gen();
+        Self::new(secret)
+    }
+
+    /// Encode and encrypt the cursor data. The version is authenticated but
+    /// not encrypted, and each cursor uses a fresh random nonce.
+    fn encode(&self, data: &CursorData) -> Cursor {
+        let plaintext = serde_json::to_vec(data).expect("Cursor data can always be serialized.");
+        let nonce: [u8; NONCE_LENGTH] = rand::thread_rng().gen();
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload {
+                msg: &plaintext,
+                aad: &[CURSOR_VERSION],
+            })
+            .expect("Cursors are far below the size limit of the cipher.");
+        let mut bytes = vec![CURSOR_VERSION];
+        bytes.extend_from_slice(&nonce);
+        bytes.extend_from_slice(&ciphertext);
+        Cursor {
+            encoded: hex::encode(bytes),
+        }
+    }
+
+    /// Check the authenticity of the cursor and decrypt it.
+    fn decode(&self, cursor: &Cursor) -> Result<CursorData, CursorError> {
+        let bytes = hex::decode(&cursor.encoded).map_err(|_| CursorError::Malformed)?;
+        if bytes.len() < 1 + NONCE_LENGTH + TAG_LENGTH || bytes[0] != CURSOR_VERSION {
+            return Err(CursorError::Malformed);
+        }
+        let (nonce, ciphertext) = bytes[1..].split_at(NONCE_LENGTH);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), Payload {
+                msg: ciphertext,
+                aad: &bytes[..1],
+            })
+            .map_err(|_| CursorError::InvalidTag)?;
+        serde_json::from_slice(&plaintext).map_err(|_| CursorError::Malformed)
+    }
+}
+
+#[derive(SerdeSerialize, SerdeDeserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(transparent)]
+/// An opaque position in the results of a query of the transaction index. See
+/// the [module documentation](self).
+pub struct Cursor {
+    encoded: String,
+}
+
+impl Cursor {
+    /// The cursor as a string, for use in APIs.
+    pub fn as_str(&self) -> &str { &self.encoded }
+}
+
+impl std::fmt::Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { f.write_str(&self.encoded) }
+}
+
+impl From<String> for Cursor {
+    fn from(encoded: String) -> Self { Self { encoded } }
+}
+
+#[derive(SerdeSerialize, SerdeDeserialize, Debug, Clone)]
+/// The query a cursor continues.
+struct CursorData {
+    subject:   Subject,
+    filter:    QueryFilter,
+    /// The order in which rows are presented.
+    ascending: bool,
+    /// Whether the cursor goes to the previous page, against the order.
+    backwards: bool,
+    /// The `id` of the first row to query, in the direction of the query.
+    start:     Option<i64>,
+    /// The row just before `start`, in the direction of the query. This is
+    /// the last row of the page the cursor was issued from.
+    anchor:    Option<Anchor>,
+}
+
+#[derive(SerdeSerialize, SerdeDeserialize, Debug, Clone, PartialEq, Eq)]
+/// Identification of a row that does not change as long as the database is
+/// not rebuilt.
+struct Anchor {
+    id:               i64,
+    block_hash:       BlockHash,
+    /// Hash of the transaction, if the row is a transaction.
+    transaction_hash: Option<TransactionHash>,
+}
+
+impl Anchor {
+    fn of(row: &DatabaseRow) -> Self {
+        let transaction_hash = match &row.summary {
+            DatabaseSummaryEntry::BlockItem(item) => Some(item.hash),
+            DatabaseSummaryEntry::ProtocolEvent(_) => None,
+        };
+        Self {
+            id: row.id,
+            block_hash: row.block_hash,
+            transaction_hash,
+        }
+    }
+}
+
+impl CursorData {
+    /// The `id` following the given one, in the order rows are presented if
+    /// `forward`, and against it otherwise.
+    fn step(&self, id: i64, forward: bool) -> i64 {
+        if self.ascending == forward {
+            id.saturating_add(1)
+        } else {
+            id.saturating_sub(1)
+        }
+    }
+
+    fn with_start(&self, backwards: bool, start: i64, anchor: Option<&DatabaseRow>) -> Self {
+        Self {
+            backwards,
+            start: Some(start),
+            anchor: anchor.map(Anchor::of),
+            ..self.clone()
+        }
+    }
+
+    /// The order of the query for the rows of the page.
+    fn query_order(&self, start: Option<i64>) -> QueryOrder {
+        if self.ascending != self.backwards {
+            QueryOrder::Ascending { start }
+        } else {
+            QueryOrder::Descending { start }
+        }
+    }
+
+    /// The order of the query for the row before `start`, going against the
+    /// direction of the query.
+    fn lookbehind_order(&self, start: i64) -> QueryOrder {
+        let start = Some(self.step(start, self.backwards));
+        if self.ascending != self.backwards {
+            QueryOrder::Descending { start }
+        } else {
+            QueryOrder::Ascending { start }
+        }
+    }
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+/// Reasons why a cursor is not accepted.
+pub enum CursorError {
+    #[error("The cursor is malformed.")]
+    /// The cursor was not issued by this library.
+    Malformed,
+    #[error("The cursor was not issued with this key, or it was modified.")]
+    /// The authentication tag of the cursor is invalid.
+    InvalidTag,
+    #[error("The cursor was issued before the database was rebuilt.")]
+    /// The row the cursor continues from is no longer in the database.
+    Stale,
+}
+
+#[derive(Error, Debug)]
+/// Errors that can occur when fetching a page.
+pub enum PageError {
+    #[error("Invalid cursor: {0}")]
+    /// The cursor is not valid.
+    Cursor(#[from] CursorError),
+    #[error("Database error: {0}")]
+    /// Querying the database failed.
+    Database(#[from] tokio_postgres::Error),
+}
+
+#[derive(Debug, Clone)]
+/// A page of rows together with cursors to the neighbouring pages.
+pub struct Page {
+    /// The rows of the page, in the requested order.
+    pub rows: Vec<DatabaseRow>,
+    /// Cursor to the following page, if there might be more rows.
+    pub next: Option<Cursor>,
+    /// Cursor to the preceding page, if this is not the first page.
+    pub prev: Option<Cursor>,
+}
+
+impl DatabaseClient {
+    /// Get the first page of transactions affecting the account that satisfy
+    /// the filter. At most `limit` rows are returned, but at least one is
+    /// requested even if `limit` is smaller. If `order` has a start only rows
+    /// from it on are returned.
+    pub async fn page_account(
+        &self,
+        key: &CursorKey,
+        acc: AccountAddress,
+        filter: QueryFilter,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<Page, PageError> {
+        self.first_page(key, Subject::Account(acc), filter, limit, order)
+            .await
+    }
+
+    /// Get the first page of transactions affecting the contract that satisfy
+    /// the filter. This behaves like [DatabaseClient::page_account].
+    pub async fn page_contract(
+        &self,
+        key: &CursorKey,
+        c: ContractAddress,
+        filter: QueryFilter,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<Page, PageError> {
+        self.first_page(key, Subject::Contract(c), filter, limit, order)
+            .await
+    }
+
+    /// Get the page the cursor points to, with at most `limit` rows. The
+    /// cursor must have been issued with the same key, and the row it continues
+    /// from must still be in the database.
+    pub async fn page(
+        &self,
+        key: &CursorKey,
+        cursor: &Cursor,
+        limit: i64,
+    ) -> Result<Page, PageError> {
+        let data = key.decode(cursor)?;
+        self.fetch_page(key, data, limit).await
+    }
+
+    async fn first_page(
+        &self,
+        key: &CursorKey,
+        subject: Subject,
+        filter: QueryFilter,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<Page, PageError> {
+        let (ascending, start) = match order {
+            QueryOrder::Ascending { start } => (true, start),
+            QueryOrder::Descending { start } => (false, start),
+        };
+        let data = CursorData {
+            subject,
+            filter,
+            ascending,
+            backwards: false,
+            start,
+            anchor: None,
+        };
+        self.fetch_page(key, data, limit).await
+    }
+
+    async fn query_rows(
+        &self,
+        data: &CursorData,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<Vec<DatabaseRow>, tokio_postgres::Error> {
+        match data.subject {
+            Subject::Account(acc) => {
+                self.query_account_filtered(&acc, &data.filter, limit, order)
+                    .await?
+                    .try_collect()
+                    .await
+            }
+            Subject::Contract(c) => {
+                self.query_contract_filtered(c, &data.filter, limit, order)
+                    .await?
+                    .try_collect()
+                    .await
+            }
+        }
+    }
+
+    async fn fetch_page(
+        &self,
+        key: &CursorKey,
+        data: CursorData,
+        limit: i64,
+    ) -> Result<Page, PageError> {
+        let limit = limit.max(1);
+        // The row before the start, against the direction of the query. This
+        // tells whether there are rows on the other side of the page, and it
+        // must be the anchor if the cursor has one.
+        let behind = match data.start {
+            Some(start) => self
+                .query_rows(&data, 1, data.lookbehind_order(start))
+                .await?
+                .pop(),
+            None => None,
+        };
+        if let Some(anchor) = &data.anchor {
+            if behind.as_ref().map(Anchor::of).as_ref() != Some(anchor) {
+                return Err(CursorError::Stale.into());
+            }
+        }
+        // Query one more row than needed to find out whether there are more.
+        let query_limit = limit.saturating_add(1);
+        let mut rows = self
+            .query_rows(&data, query_limit, data.query_order(data.start))
+            .await?;
+        let more = rows.len() as i64 > limit;
+        rows.truncate(limit as usize);
+        if data.backwards {
+            rows.reverse();
+        }
+
+        let (has_next, has_prev) = if data.backwards {
+            (behind.is_some(), more)
+        } else {
+            (more, behind.is_some())
+        };
+        // If the page is empty the neighbouring pages are relative to the
+        // start of the query.
+        let next_start = match rows.last() {
+            Some(row) => data.step(row.id, true),
+            None => match data.start {
+                Some(start) if data.backwards => data.step(start, true),
+                Some(start) => start,
+                None => 0,
+            },
+        };
+        let prev_start = match rows.first() {
+            Some(row) => data.step(row.id, false),
+            None => match data.start {
+                Some(start) if data.backwards => start,
+                Some(start) => data.step(start, false),
+                None => 0,
+            },
+        };
+        // The anchors of the neighbouring cursors are the rows at the edges of
+        // the page. If the page is empty it is the row found before it, if it
+        // is on the side of the cursor.
+        let next_anchor = match rows.last() {
+            Some(row) => Some(row),
+            None if !data.backwards => behind.as_ref(),
+            None => None,
+        };
+        let prev_anchor = match rows.first() {
+            Some(row) => Some(row),
+            None if data.backwards => behind.as_ref(),
+            None => None,
+        };
+        let next = if has_next {
+            Some(key.encode(&data.with_start(false, next_start, next_anchor)))
+        } else {
+            None
+        };
+        let prev = if has_prev {
+            Some(key.encode(&data.with_start(true, prev_start, prev_anchor)))
+        } else {
+            None
+        };
+        Ok(Page { rows, next, prev })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_data() -> CursorData {
+        CursorData {
+            subject:   Subject::Contract(ContractAddress::new(5.into(), 0.into())),
+            filter:    QueryFilter::default(),
+            ascending: false,
+            backwards: true,
+            start:     Some(1234),
+            anchor:    Some(Anchor {
+                id:               1235,
+                block_hash:       BlockHash::new([1u8; 32]),
+                transaction_hash: Some(TransactionHash::new([2u8; 32])),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_cursor_roundtrip() {
+        let key = CursorKey::new([7u8; 32]);
+        let cursor = key.encode(&test_data());
+        let decoded = key
+            .decode(&Cursor::from(cursor.to_string()))
+            .expect("Valid cursor.");
+        assert!(!decoded.ascending);
+        assert!(decoded.backwards);
+        assert_eq!(decoded.start, Some(1234));
+        assert_eq!(decoded.anchor, test_data().anchor);
+        let expected = ContractAddress::new(5.into(), 0.into());
+        assert!(matches!(decoded.subject, Subject::Contract(c) if c == expected));
+    }
+
+    #[test]
+    fn test_cursor_integrity() {
+        let key = CursorKey::new([7u8; 32]);
+        let cursor = key.encode(&test_data());
+
+        let other_key = CursorKey::new([8u8; 32]);
+        assert_eq!(
+            other_key.decode(&cursor).err(),
+            Some(CursorError::InvalidTag)
+        );
+
+        // Flip a bit in the nonce, and in the ciphertext.
+        let bytes = hex::decode(cursor.as_str()).expect("Cursors are hex.");
+        for &position in &[5, bytes.len() - 20] {
+            let mut bytes = bytes.clone();
+            bytes[position] ^= 1;
+            let modified = Cursor::from(hex::encode(&bytes));
+            assert_eq!(key.decode(&modified).err(), Some(CursorError::InvalidTag));
+        }
+        // An unknown version.
+        let mut bytes = bytes;
+        bytes[0] = 0;
+        let modified = Cursor::from(hex::encode(&bytes));
+        assert_eq!(key.decode(&modified).err(), Some(CursorError::Malformed));
+
+        assert_eq!(
+            key.decode(&Cursor::from("not a cursor".to_string())).err(),
+            Some(CursorError::Malformed)
+        );
+        assert_eq!(
+            key.decode(&Cursor::from(String::from("00"))).err(),
+            Some(CursorError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_cursor_opacity() {
+        let key = CursorKey::new([7u8; 32]);
+        let first = key.encode(&test_data());
+        let second = key.encode(&test_data());
+        // Each cursor has its own nonce.
+        assert_ne!(first, second);
+        // The position is not visible in the cursor.
+        let bytes = hex::decode(first.as_str()).expect("Cursors are hex.");
+        assert!(!bytes.windows(4).any(|window| window == b"1234"));
+        assert!(!bytes.windows(5).any(|window| window == b"start"));
+    }
+
+    #[test]
+    fn test_step() {
+        let mut data = test_data();
+        // Descending order: the next row has a smaller id.
+        assert_eq!(data.step(10, true), 9);
+        assert_eq!(data.step(10, false), 11);
+        data.ascending = true;
+        assert_eq!(data.step(10, true), 11);
+        assert_eq!(data.step(i64::MAX, true), i64::MAX);
+    }
+
+    #[test]
+    fn test_query_orders() {
+        let mut data = test_data();
+        // Going backwards through a descending order the page is queried in
+        // ascending order, and the anchor is the first row below the start.
+        assert!(matches!(
+            data.query_order(Some(10)),
+            QueryOrder::Ascending { start: Some(10) }
+        ));
+        assert!(matches!(
+            data.lookbehind_order(10),
+            QueryOrder::Descending { start: Some(9) }
+        ));
+        data.backwards = false;
+        assert!(matches!(
+            data.query_order(Some(10)),
+            QueryOrder::Descending { start: Some(10) }
+        ));
+        assert!(matches!(data.lookbehind_order(10), QueryOrder::Ascending {
+            start: Some(11),
+        }));
+        data.ascending = true;
+        assert!(matches!(data.query_order(None), QueryOrder::Ascending {
+            start: None,
+        }));
+        assert!(matches!(
+            data.lookbehind_order(10),
+            QueryOrder::Descending { start: Some(9) }
+        ));
+    }
+}
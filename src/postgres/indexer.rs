@@ -0,0 +1,331 @@
+//! An indexer that writes finalized transactions to the transaction index.
+//!
+//! A node that is configured to log transactions maintains the `summaries`,
+//! `ati` (account transaction index) and `cti` (contract transaction index)
+//! tables that [DatabaseClient] reads from. The [Indexer] maintains the same
+//! tables by following the finalized blocks of a node through its GRPC
+//! interface, so the index can be built without access to the node's
+//! configuration.
+//!
+//! Each block is written in a single database transaction. The indexer can
+//! thus be stopped at any point, and when started again it resumes from the
+//! block following the last one written.
+use super::{storage::TransactionIndex, DatabaseClient, DatabaseSummaryEntry};
+use crate::{
+    blocks::{finalized_blocks, FinalizedBlock, StreamEnd},
+    endpoints::{self, QueryError},
+    types::{AbsoluteBlockHeight, ContractAddress},
+};
+use futures::StreamExt;
+use id::types::AccountAddress;
+use thiserror::Error;
+use tokio_postgres::{types::Json, Statement};
+
+/// Statements that create the tables of the transaction index, and the
+/// indices used by the queries of [DatabaseClient], if they do not exist.
+pub const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS summaries(
+  id SERIAL8 PRIMARY KEY UNIQUE,
+  block BYTEA NOT NULL,
+  timestamp INT8 NOT NULL,
+  height INT8 NOT NULL,
+  summary JSONB NOT NULL);
+CREATE TABLE IF NOT EXISTS ati(
+  id SERIAL8,
+  account BYTEA NOT NULL,
+  summary INT8 NOT NULL,
+  CONSTRAINT ati_pkey PRIMARY KEY (account, id),
+  CONSTRAINT ati_summary_fkey FOREIGN KEY(summary) REFERENCES summaries(id)
+    ON DELETE RESTRICT ON UPDATE RESTRICT);
+CREATE TABLE IF NOT EXISTS cti(
+  id SERIAL8,
+  index INT8 NOT NULL,
+  subindex INT8 NOT NULL,
+  summary INT8 NOT NULL,
+  CONSTRAINT cti_pkey PRIMARY KEY (index, subindex, id),
+  CONSTRAINT cti_summary_fkey FOREIGN KEY(summary) REFERENCES summaries(id)
+    ON DELETE RESTRICT ON UPDATE RESTRICT);
+CREATE INDEX IF NOT EXISTS summaries_height ON summaries(height);
+CREATE INDEX IF NOT EXISTS summaries_timestamp ON summaries(timestamp);";
+
+#[derive(Error, Debug)]
+/// Errors that can occur while indexing. The type parameter is the error type
+/// of the [TransactionIndex] the indexer writes to.
+pub enum IndexerError<E: std::error::Error + 'static = tokio_postgres::Error> {
+    #[error("Error querying the node: {0}")]
+    /// Querying the node failed.
+    Query(#[from] QueryError),
+    #[error("Database error: {0}")]
+    /// Reading from or writing to the database failed.
+    Database(#[source] E),
+}
+
+/// An entry of the `summaries` table, together with the accounts and contracts
+/// it is indexed under.
+pub(crate) struct IndexEntry {
+    pub(crate) summary:   DatabaseSummaryEntry,
+    pub(crate) accounts:  Vec<AccountAddress>,
+    pub(crate) contracts: Vec<ContractAddress>,
+}
+
+/// The entries to add to the index for the block, in the order they are
+/// added.
+pub(crate) fn index_entries(block: &FinalizedBlock) -> Vec<IndexEntry> {
+    let items = block
+        .summary
+        .transaction_summaries
+        .iter()
+        .map(|item| IndexEntry {
+            summary:   DatabaseSummaryEntry::BlockItem(item.clone()),
+            accounts:  item.affected_addresses(),
+            contracts: item.affected_contracts(),
+        });
+    let outcomes = block
+        .summary
+        .special_events
+        .iter()
+        .map(|outcome| IndexEntry {
+            summary:   DatabaseSummaryEntry::ProtocolEvent(outcome.clone()),
+            accounts:  outcome.affected_addresses(),
+            contracts: Vec::new(),
+        });
+    items.chain(outcomes).collect()
+}
+
+/// Statements that add a block to the index. They are prepared on first use
+/// and reused for later blocks on the same connection.
+#[derive(Clone)]
+pub(crate) struct InsertStatements {
+    summary:  Statement,
+    account:  Statement,
+    contract: Statement,
+}
+
+impl InsertStatements {
+    async fn prepare(client: &tokio_postgres::Client) -> Result<Self, tokio_postgres::Error> {
+        Ok(Self {
+            summary:  client
+                .prepare(
+                    "INSERT INTO summaries (block, timestamp, height, summary) VALUES ($1, $2, \
+                     $3, $4) RETURNING id",
+                )
+                .await?,
+            account:  client
+                .prepare("INSERT INTO ati (account, summary) VALUES ($1, $2)")
+                .await?,
+            contract: client
+                .prepare("INSERT INTO cti (index, subindex, summary) VALUES ($1, $2, $3)")
+                .await?,
+        })
+    }
+}
+
+impl DatabaseClient {
+    /// Create the tables of the transaction index if they do not exist. The
+    /// client must have been created with [DatabaseClient::create], since the
+    /// prepared statements of [DatabaseClient::create_prepared] require the
+    /// tables to exist.
+    pub async fn create_schema(&self) -> Result<(), tokio_postgres::Error> {
+        self.as_ref().batch_execute(SCHEMA).await
+    }
+
+    /// Height of the last block in the index, if any.
+    pub async fn last_indexed_height(
+        &self,
+    ) -> Result<Option<AbsoluteBlockHeight>, tokio_postgres::Error> {
+        let row = self
+            .as_ref()
+            .query_one("SELECT MAX(height) FROM summaries", &[])
+            .await?;
+        let height: Option<i64> = row.get(0);
+        Ok(height.map(|height| AbsoluteBlockHeight::from(height as u64)))
+    }
+
+    /// Add the transactions and special outcomes of the block to the index,
+    /// together with the accounts and contracts they affect. Either all of
+    /// them are added, or none of them are.
+    pub async fn insert_block(
+        &mut self,
+        block: &FinalizedBlock,
+    ) -> Result<(), tokio_postgres::Error> {
+        let entries = index_entries(block);
+        let block_hash: &[u8] = block.info.block_hash.as_ref();
+        let timestamp = block.info.block_slot_time.timestamp_millis();
+        let height = u64::from(block.info.block_height) as i64;
+
+        let statements = match &self.insert_statements {
+            Some(statements) => statements.clone(),
+            None => {
+                let statements = InsertStatements::prepare(self.as_ref()).await?;
+                self.insert_statements = Some(statements.clone());
+                statements
+            }
+        };
+        let tx = self.as_mut().transaction().await?;
+        for entry in entries {
+            let summary = Json(&entry.summary);
+            let row = tx
+                .query_one(&statements.summary, &[
+                    &block_hash,
+                    &timestamp,
+                    &height,
+                    &summary,
+                ])
+                .await?;
+            let id: i64 = row.get(0);
+            for acc in entry.accounts {
+                let acc_raw: &[u8] = acc.as_ref();
+                tx.execute(&statements.account, &[&acc_raw, &id]).await?;
+            }
+            for c in entry.contracts {
+                let index = u64::from(c.index) as i64;
+                let subindex = u64::from(c.subindex) as i64;
+                tx.execute(&statements.contract, &[&index, &subindex, &id])
+                    .await?;
+            }
+        }
+        tx.commit().await
+    }
+}
+
+/// The height of the first block that is not yet in the index, i.e., the
+/// block following the highest one in the index, or the genesis block if the
+/// index is empty.
+pub async fn next_height<S: TransactionIndex>(db: &S) -> Result<AbsoluteBlockHeight, S::Error> {
+    let last = db.last_indexed_height().await?;
+    Ok(last.map_or(AbsoluteBlockHeight::from(0), |height| {
+        AbsoluteBlockHeight::from(u64::from(height) + 1)
+    }))
+}
+
+/// Follows the finalized blocks of a node and writes them to a transaction
+/// index, by default the one in a Postgres database.
+pub struct Indexer<S = DatabaseClient> {
+    node:          endpoints::Client,
+    db:            S,
+    /// How often to check for new finalized blocks when the indexer has
+    /// reached the end of the chain.
+    poll_interval: std::time::Duration,
+}
+
+impl<S: TransactionIndex> Indexer<S> {
+    /// Construct an indexer. A Postgres [DatabaseClient] must have been
+    /// created with [DatabaseClient::create] if the tables of the index do not
+    /// yet exist.
+    pub fn new(node: endpoints::Client, db: S, poll_interval: std::time::Duration) -> Self {
+        Self {
+            node,
+            db,
+            poll_interval,
+        }
+    }
+
+    /// The height of the first block that is not yet in the index.
+    pub async fn next_height(&self) -> Result<AbsoluteBlockHeight, S::Error> {
+        next_height(&self.db).await
+    }
+
+    /// Create the tables of the index if needed, and index finalized blocks
+    /// starting from the block following the last one in the index, until
+    /// `end`. On success returns the height of the last block written, if any
+    /// blocks were written.
+    ///
+    /// If an error occurs the blocks indexed before it remain in the index, and
+    /// the indexer can be run again to resume.
+    pub async fn run(
+        &mut self,
+        end: StreamEnd,
+    ) -> Result<Option<AbsoluteBlockHeight>, IndexerError<S::Error>> {
+        self.db
+            .create_schema()
+            .await
+            .map_err(IndexerError::Database)?;
+        let start = self.next_height().await.map_err(IndexerError::Database)?;
+        let mut blocks = Box::pin(finalized_blocks(
+            self.node.clone(),
+            start,
+            end,
+            self.poll_interval,
+        ));
+        let mut last = None;
+        while let Some(block) = blocks.next().await {
+            let block = block?;
+            self.db
+                .insert_block(&block)
+                .await
+                .map_err(IndexerError::Database)?;
+            last = Some(block.info.block_height);
+        }
+        Ok(last)
+    }
+
+    /// Stop the indexer and return the clients it was using.
+    pub fn into_inner(self) -> (endpoints::Client, S) { (self.node, self.db) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postgres::{DatabaseRow, QueryOrder};
+    use async_trait::async_trait;
+    use futures::stream::BoxStream;
+
+    /// An index that only knows the height of its last block.
+    struct LastHeight(Option<AbsoluteBlockHeight>);
+
+    #[async_trait]
+    impl TransactionIndex for LastHeight {
+        type Error = std::convert::Infallible;
+
+        async fn query_account(
+            &self,
+            _acc: AccountAddress,
+            _limit: i64,
+            _order: QueryOrder,
+        ) -> Result<BoxStream<'_, Result<DatabaseRow, Self::Error>>, Self::Error> {
+            unimplemented!()
+        }
+
+        async fn query_contract(
+            &self,
+            _c: ContractAddress,
+            _limit: i64,
+            _order: QueryOrder,
+        ) -> Result<BoxStream<'_, Result<DatabaseRow, Self::Error>>, Self::Error> {
+            unimplemented!()
+        }
+
+        async fn create_schema(&self) -> Result<(), Self::Error> { Ok(()) }
+
+        async fn last_indexed_height(&self) -> Result<Option<AbsoluteBlockHeight>, Self::Error> {
+            Ok(self.0)
+        }
+
+        async fn insert_block(&mut self, _block: &FinalizedBlock) -> Result<(), Self::Error> {
+            unimplemented!()
+        }
+
+        fn skipped_rows(&self) -> u64 { 0 }
+    }
+
+    #[tokio::test]
+    async fn test_next_height() {
+        // An empty index starts from genesis.
+        assert_eq!(
+            next_height(&LastHeight(None)).await.unwrap(),
+            AbsoluteBlockHeight::from(0)
+        );
+        // Otherwise indexing resumes after the last block.
+        assert_eq!(
+            next_height(&LastHeight(Some(AbsoluteBlockHeight::from(0))))
+                .await
+                .unwrap(),
+            AbsoluteBlockHeight::from(1)
+        );
+        assert_eq!(
+            next_height(&LastHeight(Some(AbsoluteBlockHeight::from(41))))
+                .await
+                .unwrap(),
+            AbsoluteBlockHeight::from(42)
+        );
+    }
+}
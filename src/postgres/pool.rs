@@ -0,0 +1,320 @@
+//! A pool of connections to the transaction index.
+//!
+//! A [DatabaseClient] holds a single connection, and once the connection is
+//! lost it cannot be used anymore. The [Pool] keeps a number of clients, hands
+//! them out for concurrent use, and replaces clients whose connection has been
+//! lost with new ones. Each new client prepares its own statements, so prepared
+//! statements are always valid for the connection they are used on. When a
+//! client is returned to the pool it stops listening on all notification
+//! channels, so that subscriptions of one user do not leak to the next.
+use super::DatabaseClient;
+use std::{
+    ops::Deref,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_postgres::{tls::MakeTlsConnect, Socket};
+
+#[derive(Debug, Clone, Copy)]
+/// Configuration of a [Pool].
+pub struct PoolConfig {
+    /// Maximum number of connections open at the same time.
+    pub size:                  usize,
+    /// Whether to create prepared statements on each connection. See
+    /// [DatabaseClient::create_prepared].
+    pub prepared:              bool,
+    /// Connections that have not been used for at least this long are checked
+    /// with a trivial query before they are handed out.
+    pub health_check_interval: Duration,
+    /// Number of attempts to establish a new connection before giving up.
+    pub connect_attempts:      u32,
+    /// Delay between attempts to establish a new connection.
+    pub connect_retry_delay:   Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            size:                  8,
+            prepared:              true,
+            health_check_interval: Duration::from_secs(30),
+            connect_attempts:      3,
+            connect_retry_delay:   Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("The pool must allow at least one connection.")]
+/// The [PoolConfig] has a `size` of 0.
+pub struct EmptyPoolError;
+
+/// A client that is not in use, together with the time it was last used.
+struct Idle {
+    client:    DatabaseClient,
+    last_used: Instant,
+}
+
+type IdleClients = Arc<Mutex<Vec<Idle>>>;
+
+/// Lock the idle clients. Since the clients are only pushed and popped the
+/// list remains valid if another thread panicked with the lock held.
+fn lock_idle(idle: &Mutex<Vec<Idle>>) -> MutexGuard<Vec<Idle>> {
+    idle.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+struct PoolInner<T> {
+    db_config:    tokio_postgres::Config,
+    tls:          T,
+    config:       PoolConfig,
+    idle:         IdleClients,
+    /// Limits the number of clients in use to the size of the pool.
+    permits:      Arc<Semaphore>,
+    /// Rows skipped by all the clients of the pool, see
+    /// [DatabaseClient::skipped_rows].
+    skipped_rows: Arc<AtomicU64>,
+}
+
+impl<T> PoolInner<T> {
+    fn idle(&self) -> MutexGuard<Vec<Idle>> { lock_idle(&self.idle) }
+}
+
+/// A pool of [DatabaseClient]s. The pool is cheap to clone, and all clones
+/// share the same connections.
+pub struct Pool<T> {
+    inner: Arc<PoolInner<T>>,
+}
+
+impl<T> Clone for Pool<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Pool<T>
+where
+    T: MakeTlsConnect<Socket> + Clone + Send + Sync + 'static,
+    T::Stream: Send + 'static,
+{
+    /// Construct a new pool. Connections are only established when they are
+    /// needed. Fails if the configured `size` is 0.
+    pub fn new(
+        db_config: tokio_postgres::Config,
+        tls: T,
+        config: PoolConfig,
+    ) -> Result<Self, EmptyPoolError> {
+        if config.size == 0 {
+            return Err(EmptyPoolError);
+        }
+        Ok(Self {
+            inner: Arc::new(PoolInner {
+                db_config,
+                tls,
+                config,
+                idle: Arc::new(Mutex::new(Vec::with_capacity(config.size))),
+                permits: Arc::new(Semaphore::new(config.size)),
+                skipped_rows: Arc::new(AtomicU64::new(0)),
+            }),
+        })
+    }
+
+    /// Get a client from the pool, waiting until one is available if all of
+    /// them are in use. An idle client is reused if its connection is still
+    /// working, otherwise a new connection is established. The client returns
+    /// to the pool when the [PooledClient] is dropped.
+    pub async fn get(&self) -> Result<PooledClient<T>, tokio_postgres::Error> {
+        let permit = self
+            .inner
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("The semaphore is never closed.");
+        loop {
+            let idle = self.inner.idle().pop();
+            match idle {
+                Some(idle) => {
+                    if self.is_healthy(&idle).await {
+                        return Ok(self.pooled(idle.client, permit));
+                    }
+                    // The connection task has ended or is about to, stopping
+                    // it only waits for it to do so.
+                    let _ = idle.client.stop().await;
+                }
+                None => {
+                    let client = self.connect().await?;
+                    return Ok(self.pooled(client, permit));
+                }
+            }
+        }
+    }
+
+    /// Close all the idle connections. Clients that are in use are returned to
+    /// the pool as usual.
+    pub async fn close_idle(&self) {
+        let idle = std::mem::take(&mut *self.inner.idle());
+        for idle in idle {
+            let _ = idle.client.stop().await;
+        }
+    }
+
+    /// Number of idle connections in the pool.
+    pub fn idle_connections(&self) -> usize { self.inner.idle().len() }
+
+    /// Number of rows skipped by the lenient queries of all the clients of the
+    /// pool, including those that have since been closed. The clients share
+    /// the counter, so [DatabaseClient::skipped_rows] of a pooled client
+    /// returns the same number.
+    pub fn skipped_rows(&self) -> u64 { self.inner.skipped_rows.load(Ordering::Relaxed) }
+
+    async fn is_healthy(&self, idle: &Idle) -> bool {
+        if idle.client.is_closed() {
+            return false;
+        }
+        if idle.last_used.elapsed() < self.inner.config.health_check_interval {
+            return true;
+        }
+        idle.client.as_ref().simple_query("SELECT 1").await.is_ok()
+    }
+
+    async fn connect(&self) -> Result<DatabaseClient, tokio_postgres::Error> {
+        let config = &self.inner.config;
+        let mut attempt = 1;
+        loop {
+            let result = DatabaseClient::connect(
+                self.inner.db_config.clone(),
+                self.inner.tls.clone(),
+                config.prepared,
+            )
+            .await;
+            match result {
+                Err(_) if attempt < config.connect_attempts => {
+                    attempt += 1;
+                    tokio::time::sleep(config.connect_retry_delay).await;
+                }
+                Ok(mut client) => {
+                    client.skipped_rows = self.inner.skipped_rows.clone();
+                    return Ok(client);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn pooled(&self, client: DatabaseClient, permit: OwnedSemaphorePermit) -> PooledClient<T> {
+        PooledClient {
+            client: Some(client),
+            idle:   self.inner.idle.clone(),
+            permit: Some(permit),
+            _tls:   std::marker::PhantomData,
+        }
+    }
+}
+
+/// A client borrowed from a [Pool]. It dereferences to a [DatabaseClient], and
+/// returns the client to the pool when dropped.
+pub struct PooledClient<T> {
+    /// Always `Some` until the client is dropped.
+    client: Option<DatabaseClient>,
+    idle:   IdleClients,
+    /// Held to count the client towards the size of the pool. Always `Some`
+    /// until the client is dropped.
+    permit: Option<OwnedSemaphorePermit>,
+    _tls:   std::marker::PhantomData<T>,
+}
+
+impl<T> Deref for PooledClient<T> {
+    type Target = DatabaseClient;
+
+    fn deref(&self) -> &Self::Target {
+        self.client
+            .as_ref()
+            .expect("The client is only taken on drop.")
+    }
+}
+
+impl<T> Drop for PooledClient<T> {
+    fn drop(&mut self) {
+        let client = match self.client.take() {
+            Some(client) => client,
+            None => return,
+        };
+        // Clients whose connection was lost are not returned. Their connection
+        // task has ended, so dropping them is enough. The same goes for clients
+        // dropped outside of a runtime, whose connection task is dropped with
+        // the runtime.
+        if client.is_closed() {
+            return;
+        }
+        let runtime = match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => runtime,
+            Err(_) => return,
+        };
+        // The permit is only released once the client is back in the pool, so
+        // that the pool does not connect a new client in the meantime.
+        let permit = self.permit.take();
+        let idle = self.idle.clone();
+        runtime.spawn(async move {
+            match client.as_ref().batch_execute("UNLISTEN *").await {
+                Ok(()) => lock_idle(&idle).push(Idle {
+                    client,
+                    last_used: Instant::now(),
+                }),
+                Err(_) => {
+                    let _ = client.stop().await;
+                }
+            }
+            drop(permit);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(size: usize) -> Result<Pool<tokio_postgres::NoTls>, EmptyPoolError> {
+        let config = PoolConfig {
+            size,
+            ..PoolConfig::default()
+        };
+        Pool::new(tokio_postgres::Config::new(), tokio_postgres::NoTls, config)
+    }
+
+    #[test]
+    fn test_new() {
+        assert!(pool(0).is_err());
+        let pool = pool(2).expect("A pool with connections is valid.");
+        assert_eq!(pool.idle_connections(), 0);
+        assert_eq!(pool.skipped_rows(), 0);
+        assert_eq!(pool.inner.permits.available_permits(), 2);
+        // Clones share the connections and the counter.
+        pool.inner.skipped_rows.fetch_add(3, Ordering::Relaxed);
+        assert_eq!(pool.clone().skipped_rows(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_connect_failure() {
+        // Nothing listens on port 1, so connecting fails, and the permit is
+        // released again.
+        let mut db_config = tokio_postgres::Config::new();
+        db_config.host("127.0.0.1").port(1).user("test");
+        let config = PoolConfig {
+            size: 1,
+            connect_attempts: 2,
+            connect_retry_delay: Duration::from_millis(1),
+            ..PoolConfig::default()
+        };
+        let pool = Pool::new(db_config, tokio_postgres::NoTls, config).unwrap();
+        assert!(pool.get().await.is_err());
+        assert_eq!(pool.inner.permits.available_permits(), 1);
+        assert_eq!(pool.idle_connections(), 0);
+    }
+}
@@ -0,0 +1,127 @@
+//! Storage backends for the transaction index.
+//!
+//! The [TransactionIndex] trait covers the operations on the account and
+//! contract transaction index that do not depend on the database, querying and
+//! adding blocks. It is implemented by the Postgres [DatabaseClient], and with
+//! the `sqlite` feature by `sqlite::SqliteIndex`, which keeps the index in a
+//! file and is useful for small deployments and tests. Both return the same
+//! [DatabaseRow]s.
+//!
+//! Queries by block range, filtered queries, subscriptions and pagination are
+//! out of scope of the trait. They rely on features of Postgres, such as
+//! dynamically built queries and notifications, and are only available on the
+//! [DatabaseClient].
+use super::{DatabaseClient, DatabaseRow, QueryOrder};
+use crate::{
+    blocks::FinalizedBlock,
+    types::{AbsoluteBlockHeight, ContractAddress},
+};
+use async_trait::async_trait;
+use futures::{stream::BoxStream, StreamExt};
+use id::types::AccountAddress;
+
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+#[async_trait]
+/// Storage of the account and contract transaction index. Rows are returned in
+/// the order of their `id`, which increases as rows are added. Rows that cannot
+/// be parsed are skipped and counted, see [TransactionIndex::skipped_rows],
+/// while errors of the storage itself are returned in the streams.
+pub trait TransactionIndex: Send + Sync {
+    /// Errors that can occur when accessing the storage.
+    type Error: std::error::Error + Send + Sync + 'static;
+
+    /// Get the list of transactions affecting the given account. The `limit`
+    /// value limits the number of rows that will be returned.
+    async fn query_account(
+        &self,
+        acc: AccountAddress,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<BoxStream<'_, Result<DatabaseRow, Self::Error>>, Self::Error>;
+
+    /// Get the list of transactions affecting the given contract. The `limit`
+    /// value limits the number of rows that will be returned.
+    async fn query_contract(
+        &self,
+        c: ContractAddress,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<BoxStream<'_, Result<DatabaseRow, Self::Error>>, Self::Error>;
+
+    /// Return all transactions affecting the account, starting with the given
+    /// row id.
+    async fn iterate_account(
+        &self,
+        acc: AccountAddress,
+        start: Option<i64>,
+    ) -> Result<BoxStream<'_, Result<DatabaseRow, Self::Error>>, Self::Error> {
+        self.query_account(acc, i64::MAX, QueryOrder::Ascending { start })
+            .await
+    }
+
+    /// Return all transactions affecting the contract, starting with the given
+    /// row id.
+    async fn iterate_contract(
+        &self,
+        c: ContractAddress,
+        start: Option<i64>,
+    ) -> Result<BoxStream<'_, Result<DatabaseRow, Self::Error>>, Self::Error> {
+        self.query_contract(c, i64::MAX, QueryOrder::Ascending { start })
+            .await
+    }
+
+    /// Create the tables of the index if they do not exist.
+    async fn create_schema(&self) -> Result<(), Self::Error>;
+
+    /// Height of the last block in the index, if any.
+    async fn last_indexed_height(&self) -> Result<Option<AbsoluteBlockHeight>, Self::Error>;
+
+    /// Add the transactions and special outcomes of the block to the index,
+    /// together with the accounts and contracts they affect. Either all of
+    /// them are added, or none of them are.
+    async fn insert_block(&mut self, block: &FinalizedBlock) -> Result<(), Self::Error>;
+
+    /// Number of rows the queries skipped because they could not be parsed.
+    fn skipped_rows(&self) -> u64;
+}
+
+#[async_trait]
+impl TransactionIndex for DatabaseClient {
+    type Error = tokio_postgres::Error;
+
+    async fn query_account(
+        &self,
+        acc: AccountAddress,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<BoxStream<'_, Result<DatabaseRow, Self::Error>>, Self::Error> {
+        let rows = self.query_account_rows(&acc, limit, order).await?;
+        Ok(self.parse_lenient(rows).boxed())
+    }
+
+    async fn query_contract(
+        &self,
+        c: ContractAddress,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<BoxStream<'_, Result<DatabaseRow, Self::Error>>, Self::Error> {
+        let rows = self.query_contract_rows(c, limit, order).await?;
+        Ok(self.parse_lenient(rows).boxed())
+    }
+
+    async fn create_schema(&self) -> Result<(), Self::Error> {
+        DatabaseClient::create_schema(self).await
+    }
+
+    async fn last_indexed_height(&self) -> Result<Option<AbsoluteBlockHeight>, Self::Error> {
+        DatabaseClient::last_indexed_height(self).await
+    }
+
+    async fn insert_block(&mut self, block: &FinalizedBlock) -> Result<(), Self::Error> {
+        DatabaseClient::insert_block(self, block).await
+    }
+
+    fn skipped_rows(&self) -> u64 { DatabaseClient::skipped_rows(self) }
+}
@@ -0,0 +1,551 @@
+//! A transaction index stored in an SQLite database file.
+//!
+//! The tables have the same structure as the Postgres ones, with the summaries
+//! stored as JSON text. Since SQLite connections are blocking, queries are run
+//! on the blocking thread pool of tokio, one at a time. Query results are read
+//! completely before they are returned.
+use super::TransactionIndex;
+use crate::{
+    blocks::FinalizedBlock,
+    postgres::{
+        indexer::{index_entries, IndexEntry},
+        DatabaseRow, DatabaseSummaryEntry, QueryOrder,
+    },
+    types::{hashes::BlockHash, AbsoluteBlockHeight, ContractAddress},
+};
+use async_trait::async_trait;
+use crypto_common::types::Timestamp;
+use futures::{stream::BoxStream, StreamExt};
+use id::types::AccountAddress;
+use rusqlite::{params, Connection, ToSql};
+use std::{
+    convert::TryInto,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use thiserror::Error;
+
+/// Statements that create the tables of the transaction index if they do not
+/// exist. `AUTOINCREMENT` ensures that ids are never reused, so that they
+/// increase as rows are added.
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS summaries(
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  block BLOB NOT NULL,
+  timestamp INTEGER NOT NULL,
+  height INTEGER NOT NULL,
+  summary TEXT NOT NULL);
+CREATE TABLE IF NOT EXISTS ati(
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  account BLOB NOT NULL,
+  summary INTEGER NOT NULL REFERENCES summaries(id));
+CREATE TABLE IF NOT EXISTS cti(
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  \"index\" INTEGER NOT NULL,
+  subindex INTEGER NOT NULL,
+  summary INTEGER NOT NULL REFERENCES summaries(id));
+CREATE INDEX IF NOT EXISTS ati_account ON ati(account, id);
+CREATE INDEX IF NOT EXISTS cti_contract ON cti(\"index\", subindex, id);
+CREATE INDEX IF NOT EXISTS summaries_height ON summaries(height);";
+
+const QUERY_ACCOUNT_ASC: &str = "SELECT ati.id, summaries.block, summaries.timestamp, \
+                                 summaries.height, summaries.summary FROM ati JOIN summaries ON \
+                                 ati.summary = summaries.id WHERE ati.account = ?1 AND ati.id >= \
+                                 ?2 ORDER BY ati.id ASC LIMIT ?3";
+
+const QUERY_ACCOUNT_DESC: &str = "SELECT ati.id, summaries.block, summaries.timestamp, \
+                                  summaries.height, summaries.summary FROM ati JOIN summaries ON \
+                                  ati.summary = summaries.id WHERE ati.account = ?1 AND ati.id <= \
+                                  ?2 ORDER BY ati.id DESC LIMIT ?3";
+
+const QUERY_CONTRACT_ASC: &str = "SELECT cti.id, summaries.block, summaries.timestamp, \
+                                  summaries.height, summaries.summary FROM cti JOIN summaries ON \
+                                  cti.summary = summaries.id WHERE cti.\"index\" = ?1 AND \
+                                  cti.subindex = ?2 AND cti.id >= ?3 ORDER BY cti.id ASC LIMIT ?4";
+
+const QUERY_CONTRACT_DESC: &str =
+    "SELECT cti.id, summaries.block, summaries.timestamp, summaries.height, summaries.summary \
+     FROM cti JOIN summaries ON cti.summary = summaries.id WHERE cti.\"index\" = ?1 AND \
+     cti.subindex = ?2 AND cti.id <= ?3 ORDER BY cti.id DESC LIMIT ?4";
+
+#[derive(Error, Debug)]
+/// Errors that can occur when accessing an [SqliteIndex].
+pub enum SqliteError {
+    #[error("SQLite error: {0}")]
+    /// The database returned an error.
+    Sqlite(#[from] rusqlite::Error),
+    #[error("The database task failed: {0}")]
+    /// The task accessing the database panicked or was cancelled.
+    Task(#[from] tokio::task::JoinError),
+    #[error("Could not serialize a summary: {0}")]
+    /// A summary could not be converted to JSON.
+    Json(#[from] serde_json::Error),
+}
+
+/// A transaction index in an SQLite database. Cloning the index gives another
+/// handle to the same connection.
+#[derive(Clone)]
+pub struct SqliteIndex {
+    connection:   Arc<Mutex<Connection>>,
+    /// Number of rows skipped by the queries because they could not be parsed.
+    skipped_rows: Arc<AtomicU64>,
+}
+
+/// Parse the columns of a row of a query. Rows that cannot be parsed are
+/// logged, counted and skipped.
+fn construct_row(
+    skipped: &AtomicU64,
+    id: i64,
+    block: Vec<u8>,
+    timestamp: i64,
+    height: i64,
+    summary: String,
+) -> Option<DatabaseRow> {
+    let block_hash = match block.as_slice().try_into() {
+        Ok(hash) => BlockHash::new(hash),
+        Err(_) => {
+            log::warn!("Skipping row {}: block hash of {} bytes.", id, block.len());
+            skipped.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+    };
+    let summary = match serde_json::from_str::<DatabaseSummaryEntry>(&summary) {
+        Ok(summary) => summary,
+        Err(e) => {
+            log::warn!(
+                "Skipping row {}: the summary could not be parsed: {}",
+                id,
+                e
+            );
+            skipped.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+    };
+    Some(DatabaseRow {
+        id,
+        block_hash,
+        block_time: Timestamp::from(timestamp as u64),
+        block_height: AbsoluteBlockHeight::from(height as u64),
+        summary,
+    })
+}
+
+fn query_rows(
+    skipped: &AtomicU64,
+    connection: &Connection,
+    sql: &str,
+    params: &[&dyn ToSql],
+) -> rusqlite::Result<Vec<DatabaseRow>> {
+    let mut statement = connection.prepare_cached(sql)?;
+    let rows = statement.query_map(params, |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+        ))
+    })?;
+    let mut out = Vec::new();
+    for row in rows {
+        let (id, block, timestamp, height, summary) = row?;
+        out.extend(construct_row(
+            skipped, id, block, timestamp, height, summary,
+        ));
+    }
+    Ok(out)
+}
+
+impl SqliteIndex {
+    /// Open the database in the given file, creating it if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, SqliteError> {
+        Ok(Self::from_connection(Connection::open(path)?))
+    }
+
+    /// Open a database in memory. It is removed when the last handle to it is
+    /// dropped.
+    pub fn open_in_memory() -> Result<Self, SqliteError> {
+        Ok(Self::from_connection(Connection::open_in_memory()?))
+    }
+
+    /// Use an existing connection.
+    pub fn from_connection(connection: Connection) -> Self {
+        Self {
+            connection:   Arc::new(Mutex::new(connection)),
+            skipped_rows: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Number of rows skipped by the queries because they could not be
+    /// parsed, since the index was opened. Handles to the same connection
+    /// share the count.
+    pub fn skipped_rows(&self) -> u64 { self.skipped_rows.load(Ordering::Relaxed) }
+
+    /// Run the function with the connection on the blocking thread pool.
+    async fn with_connection<T, F>(&self, f: F) -> Result<T, SqliteError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, SqliteError> + Send + 'static, {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || {
+            // A panic while the lock was held cannot leave the connection in
+            // an inconsistent state, since changes are made in transactions.
+            let mut connection = connection.lock().unwrap_or_else(|e| e.into_inner());
+            f(&mut connection)
+        })
+        .await?
+    }
+}
+
+#[async_trait]
+impl TransactionIndex for SqliteIndex {
+    type Error = SqliteError;
+
+    async fn query_account(
+        &self,
+        acc: AccountAddress,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<BoxStream<'_, Result<DatabaseRow, Self::Error>>, Self::Error> {
+        let skipped = self.skipped_rows.clone();
+        let rows = self
+            .with_connection(move |connection| {
+                let acc_raw: &[u8] = acc.as_ref();
+                let (sql, start) = match order {
+                    QueryOrder::Ascending { start } => {
+                        (QUERY_ACCOUNT_ASC, start.unwrap_or(i64::MIN))
+                    }
+                    QueryOrder::Descending { start } => {
+                        (QUERY_ACCOUNT_DESC, start.unwrap_or(i64::MAX))
+                    }
+                };
+                Ok(query_rows(&skipped, connection, sql, &[
+                    &acc_raw, &start, &limit,
+                ])?)
+            })
+            .await?;
+        Ok(futures::stream::iter(rows.into_iter().map(Ok)).boxed())
+    }
+
+    async fn query_contract(
+        &self,
+        c: ContractAddress,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Result<BoxStream<'_, Result<DatabaseRow, Self::Error>>, Self::Error> {
+        let skipped = self.skipped_rows.clone();
+        let rows = self
+            .with_connection(move |connection| {
+                let index = u64::from(c.index) as i64;
+                let subindex = u64::from(c.subindex) as i64;
+                let (sql, start) = match order {
+                    QueryOrder::Ascending { start } => {
+                        (QUERY_CONTRACT_ASC, start.unwrap_or(i64::MIN))
+                    }
+                    QueryOrder::Descending { start } => {
+                        (QUERY_CONTRACT_DESC, start.unwrap_or(i64::MAX))
+                    }
+                };
+                Ok(query_rows(&skipped, connection, sql, &[
+                    &index, &subindex, &start, &limit,
+                ])?)
+            })
+            .await?;
+        Ok(futures::stream::iter(rows.into_iter().map(Ok)).boxed())
+    }
+
+    async fn create_schema(&self) -> Result<(), Self::Error> {
+        self.with_connection(|connection| Ok(connection.execute_batch(SCHEMA)?))
+            .await
+    }
+
+    async fn last_indexed_height(&self) -> Result<Option<AbsoluteBlockHeight>, Self::Error> {
+        self.with_connection(|connection| {
+            let height: Option<i64> =
+                connection.query_row("SELECT MAX(height) FROM summaries", params![], |row| {
+                    row.get(0)
+                })?;
+            Ok(height.map(|height| AbsoluteBlockHeight::from(height as u64)))
+        })
+        .await
+    }
+
+    async fn insert_block(&mut self, block: &FinalizedBlock) -> Result<(), Self::Error> {
+        self.insert_entries(
+            block.info.block_hash,
+            block.info.block_slot_time.timestamp_millis(),
+            u64::from(block.info.block_height) as i64,
+            index_entries(block),
+        )
+        .await
+    }
+
+    fn skipped_rows(&self) -> u64 { SqliteIndex::skipped_rows(self) }
+}
+
+impl SqliteIndex {
+    /// Add the entries of a block in a single transaction.
+    async fn insert_entries(
+        &self,
+        block_hash: BlockHash,
+        timestamp: i64,
+        height: i64,
+        index_entries: Vec<IndexEntry>,
+    ) -> Result<(), SqliteError> {
+        let mut entries = Vec::new();
+        for entry in index_entries {
+            entries.push((
+                serde_json::to_string(&entry.summary)?,
+                entry.accounts,
+                entry.contracts,
+            ));
+        }
+        let block_hash = AsRef::<[u8]>::as_ref(&block_hash).to_vec();
+
+        self.with_connection(move |connection| {
+            let tx = connection.transaction()?;
+            {
+                let mut insert_summary = tx.prepare_cached(
+                    "INSERT INTO summaries (block, timestamp, height, summary) VALUES (?1, ?2, \
+                     ?3, ?4)",
+                )?;
+                let mut insert_account =
+                    tx.prepare_cached("INSERT INTO ati (account, summary) VALUES (?1, ?2)")?;
+                let mut insert_contract = tx.prepare_cached(
+                    "INSERT INTO cti (\"index\", subindex, summary) VALUES (?1, ?2, ?3)",
+                )?;
+                for (summary, accounts, contracts) in entries {
+                    insert_summary.execute(params![block_hash, timestamp, height, summary])?;
+                    let id = tx.last_insert_rowid();
+                    for acc in accounts {
+                        let acc_raw: &[u8] = acc.as_ref();
+                        insert_account.execute(params![acc_raw, id])?;
+                    }
+                    for c in contracts {
+                        let index = u64::from(c.index) as i64;
+                        let subindex = u64::from(c.subindex) as i64;
+                        insert_contract.execute(params![index, subindex, id])?;
+                    }
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::types::{
+        self, hashes::TransactionHash, AccountTransactionDetails, AccountTransactionEffects,
+        BlockItemSummary, BlockItemSummaryDetails, Energy,
+    };
+    use crypto_common::types::Amount;
+    use futures::TryStreamExt;
+
+    fn account(byte: u8) -> AccountAddress { AccountAddress([byte; 32]) }
+
+    /// A transfer from account 1 to account 2, identified by its hash, that
+    /// also affects the given contracts.
+    fn entry(hash: u8, contracts: Vec<ContractAddress>) -> IndexEntry {
+        let summary = BlockItemSummary {
+            index:       types::TransactionIndex { index: 0 },
+            energy_cost: Energy { energy: 500 },
+            hash:        TransactionHash::new([hash; 32]),
+            details:     BlockItemSummaryDetails::AccountTransaction(AccountTransactionDetails {
+                cost:    Amount { microgtu: 5 },
+                sender:  account(1),
+                effects: AccountTransactionEffects::AccountTransfer {
+                    amount: Amount { microgtu: 100 },
+                    to:     account(2),
+                },
+            }),
+        };
+        IndexEntry {
+            summary: DatabaseSummaryEntry::BlockItem(summary),
+            accounts: vec![account(1), account(2)],
+            contracts,
+        }
+    }
+
+    /// The transaction hashes and heights of the rows, to compare results.
+    fn describe(rows: &[DatabaseRow]) -> Vec<(u8, u64)> {
+        rows.iter()
+            .map(|row| {
+                let hash = match &row.summary {
+                    DatabaseSummaryEntry::BlockItem(item) => AsRef::<[u8]>::as_ref(&item.hash)[0],
+                    DatabaseSummaryEntry::ProtocolEvent(_) => 0,
+                };
+                (hash, row.block_height.height)
+            })
+            .collect()
+    }
+
+    async fn test_index() -> SqliteIndex {
+        let index = SqliteIndex::open_in_memory().expect("In-memory databases can be opened.");
+        index.create_schema().await.expect("Schema is valid.");
+        let contract = ContractAddress::new(3.into(), 0.into());
+        index
+            .insert_entries(BlockHash::new([5; 32]), 5000, 5, vec![
+                entry(1, vec![contract]),
+                entry(2, Vec::new()),
+                entry(3, vec![contract]),
+            ])
+            .await
+            .expect("Block can be inserted.");
+        index
+            .insert_entries(BlockHash::new([6; 32]), 6000, 6, vec![entry(4, vec![
+                contract,
+            ])])
+            .await
+            .expect("Block can be inserted.");
+        index
+    }
+
+    async fn account_rows(
+        index: &SqliteIndex,
+        acc: AccountAddress,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Vec<DatabaseRow> {
+        index
+            .query_account(acc, limit, order)
+            .await
+            .expect("Query succeeds.")
+            .try_collect()
+            .await
+            .expect("Rows can be read.")
+    }
+
+    async fn contract_rows(
+        index: &SqliteIndex,
+        c: ContractAddress,
+        limit: i64,
+        order: QueryOrder,
+    ) -> Vec<DatabaseRow> {
+        index
+            .query_contract(c, limit, order)
+            .await
+            .expect("Query succeeds.")
+            .try_collect()
+            .await
+            .expect("Rows can be read.")
+    }
+
+    #[tokio::test]
+    async fn test_query_account() {
+        let index = test_index().await;
+        assert_eq!(
+            index.last_indexed_height().await.expect("Query succeeds."),
+            Some(AbsoluteBlockHeight::from(6))
+        );
+        let acc = account(1);
+        let first = QueryOrder::Ascending { start: None };
+        let last = QueryOrder::Descending { start: None };
+
+        let all = account_rows(&index, acc, 10, first).await;
+        assert_eq!(describe(&all), vec![(1, 5), (2, 5), (3, 5), (4, 6)]);
+        assert!(all.windows(2).all(|rows| rows[0].id < rows[1].id));
+        assert_eq!(all[0].block_hash, BlockHash::new([5; 32]));
+        assert_eq!(all[3].block_time, Timestamp::from(6000));
+
+        let descending = account_rows(&index, acc, 10, last).await;
+        assert_eq!(describe(&descending), vec![(4, 6), (3, 5), (2, 5), (1, 5)]);
+
+        // The start is inclusive in both directions.
+        let start = Some(all[1].id);
+        let ascending = account_rows(&index, acc, 2, QueryOrder::Ascending { start }).await;
+        assert_eq!(describe(&ascending), vec![(2, 5), (3, 5)]);
+        let descending = account_rows(&index, acc, 10, QueryOrder::Descending { start }).await;
+        assert_eq!(describe(&descending), vec![(2, 5), (1, 5)]);
+
+        let start = Some(all[3].id + 1);
+        let past_end = account_rows(&index, acc, 10, QueryOrder::Ascending { start }).await;
+        assert!(past_end.is_empty());
+
+        assert!(account_rows(&index, account(7), 10, first).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_next_height() {
+        let empty = SqliteIndex::open_in_memory().expect("In-memory databases can be opened.");
+        empty.create_schema().await.expect("Schema is valid.");
+        assert_eq!(
+            crate::postgres::indexer::next_height(&empty).await.unwrap(),
+            AbsoluteBlockHeight::from(0)
+        );
+        // The index has blocks at heights 5 and 6.
+        assert_eq!(
+            crate::postgres::indexer::next_height(&test_index().await)
+                .await
+                .unwrap(),
+            AbsoluteBlockHeight::from(7)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_contract() {
+        let index = test_index().await;
+        let contract = ContractAddress::new(3.into(), 0.into());
+        let first = QueryOrder::Ascending { start: None };
+        let last = QueryOrder::Descending { start: None };
+
+        let all = contract_rows(&index, contract, 10, first).await;
+        assert_eq!(describe(&all), vec![(1, 5), (3, 5), (4, 6)]);
+
+        let descending = contract_rows(&index, contract, 2, last).await;
+        assert_eq!(describe(&descending), vec![(4, 6), (3, 5)]);
+
+        // The start is inclusive in both directions.
+        let start = Some(all[1].id);
+        let ascending = contract_rows(&index, contract, 10, QueryOrder::Ascending { start }).await;
+        assert_eq!(describe(&ascending), vec![(3, 5), (4, 6)]);
+        let descending =
+            contract_rows(&index, contract, 10, QueryOrder::Descending { start }).await;
+        assert_eq!(describe(&descending), vec![(3, 5), (1, 5)]);
+
+        let other = ContractAddress::new(3.into(), 1.into());
+        assert!(contract_rows(&index, other, 10, first).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_skipped_rows() {
+        let index = test_index().await;
+        // A row whose summary is not valid JSON, affecting account 1.
+        index
+            .with_connection(|connection| {
+                connection.execute(
+                    "INSERT INTO summaries (block, timestamp, height, summary) VALUES (?1, 7000, \
+                     7, 'invalid')",
+                    params![vec![7u8; 32]],
+                )?;
+                let id = connection.last_insert_rowid();
+                let acc_raw = vec![1u8; 32];
+                connection.execute(
+                    "INSERT INTO ati (account, summary) VALUES (?1, ?2)",
+                    params![acc_raw, id],
+                )?;
+                Ok(())
+            })
+            .await
+            .expect("Rows can be inserted.");
+        assert_eq!(index.skipped_rows(), 0);
+        let rows = account_rows(&index, account(1), 10, QueryOrder::Ascending {
+            start: None,
+        })
+        .await;
+        assert_eq!(describe(&rows), vec![(1, 5), (2, 5), (3, 5), (4, 6)]);
+        assert_eq!(index.skipped_rows(), 1);
+        // The count is shared with other handles and available via the trait.
+        let handle = index.clone();
+        account_rows(&handle, account(1), 10, QueryOrder::Descending {
+            start: None,
+        })
+        .await;
+        assert_eq!(TransactionIndex::skipped_rows(&index), 2);
+    }
+}
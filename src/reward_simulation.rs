@@ -0,0 +1,416 @@
+//! Simulation of the distribution of transaction fees and newly minted CCD.
+//!
+//! The transaction fees of a block are split according to the
+//! [TransactionFeeDistribution] between the baker, the GAS account, and the
+//! foundation. In addition the baker receives a share of the previous balance
+//! of the GAS account, which is larger the more special items the block
+//! includes, as determined by the [GASRewards]. Newly minted CCD are split
+//! according to the [MintDistribution] between the baking and finalization
+//! reward accounts and the foundation. The functions in this module compute
+//! these splits exactly as the node does, so that the effects of changes to
+//! the parameters can be assessed, and the computation can be validated
+//! against the [BlockReward](SpecialTransactionOutcome::BlockReward) outcomes
+//! of real blocks.
+use crate::{
+    blocks::{finalized_blocks, FinalizedBlock, StreamEnd},
+    endpoints::{self, QueryResult},
+    types::{
+        hashes::BlockHash, AbsoluteBlockHeight, BlockItemSummaryDetails, GASRewards,
+        MintDistribution, RewardFraction, RewardParameters, RewardsOverview,
+        SpecialTransactionOutcome, TransactionFeeDistribution,
+    },
+};
+use crypto_common::types::Amount;
+use futures::StreamExt;
+use num::{BigUint, ToPrimitive};
+
+#[derive(Debug, Clone, Copy)]
+/// The contents of a hypothetical block that determine how rewards are
+/// distributed.
+pub struct SimulatedBlock {
+    /// Total transaction fees paid in the block.
+    pub transaction_fees:   Amount,
+    /// Number of account creations in the block.
+    pub account_creations:  u32,
+    /// Number of chain updates in the block.
+    pub chain_updates:      u32,
+    /// Whether the block contains a finalization proof.
+    pub finalization_proof: bool,
+    /// Number of slots since the parent block. CCD is minted for each of them.
+    pub mint_slots:         u64,
+}
+
+impl SimulatedBlock {
+    /// The contents of a real block. The number of slots since the parent is
+    /// not known from the block alone, so it is set to 0.
+    pub fn from_block(block: &FinalizedBlock) -> Self {
+        let mut account_creations = 0;
+        let mut chain_updates = 0;
+        for summary in block.summary.transaction_summaries.iter() {
+            match summary.details {
+                BlockItemSummaryDetails::AccountTransaction(_) => (),
+                BlockItemSummaryDetails::AccountCreation(_) => account_creations += 1,
+                BlockItemSummaryDetails::Update(_) => chain_updates += 1,
+            }
+        }
+        let transaction_fees = block
+            .summary
+            .special_events
+            .iter()
+            .find_map(|outcome| match outcome {
+                SpecialTransactionOutcome::BlockReward {
+                    transaction_fees, ..
+                } => Some(*transaction_fees),
+                _ => None,
+            })
+            .unwrap_or(Amount { microgtu: 0 });
+        Self {
+            transaction_fees,
+            account_creations,
+            chain_updates,
+            finalization_proof: block.summary.finalization_data.is_some(),
+            mint_slots: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Distribution of the transaction fees and the GAS account of a block. These
+/// are the amounts recorded in the
+/// [BlockReward](SpecialTransactionOutcome::BlockReward) outcome.
+pub struct FeeDistributionOutcome {
+    /// The amount paid to the baker of the block.
+    pub baker_reward:      Amount,
+    /// The amount paid to the foundation.
+    pub foundation_charge: Amount,
+    /// Balance of the GAS account after the block.
+    pub new_gas_account:   Amount,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Distribution of the CCD minted in a block. These are the amounts recorded in
+/// the [Mint](SpecialTransactionOutcome::Mint) outcome.
+pub struct MintOutcome {
+    /// Total amount minted.
+    pub minted: Amount,
+    /// The part that goes to the baking reward account.
+    pub baking_reward: Amount,
+    /// The part that goes to the finalization reward account.
+    pub finalization_reward: Amount,
+    /// The part that goes to the foundation.
+    pub platform_development_charge: Amount,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// All rewards resulting from a simulated block.
+pub struct SimulatedRewards {
+    /// Distribution of the transaction fees and the GAS account.
+    pub fees: FeeDistributionOutcome,
+    /// Distribution of the newly minted CCD.
+    pub mint: MintOutcome,
+}
+
+impl SimulatedRewards {
+    /// Everything the foundation receives, from both fees and minting.
+    pub fn foundation_total(&self) -> Amount {
+        Amount {
+            microgtu: self.fees.foundation_charge.microgtu
+                + self.mint.platform_development_charge.microgtu,
+        }
+    }
+}
+
+/// `1 - fraction` in parts per hundred thousand.
+fn complement(fraction: RewardFraction) -> u32 {
+    100_000 - fraction.parts_per_hundred_thousands.parts
+}
+
+/// Distribute the transaction fees of the block, and the previous balance of
+/// the GAS account.
+///
+/// The baker and the GAS account receive their fractions of the fees, rounded
+/// down, and the foundation the rest. Of the previous GAS account the part
+/// `gas * (1 - baker) * (1 - finalization_proof)^p * (1 - account_creation)^a *
+/// (1 - chain_update)^u`, rounded up, remains in the GAS account and the rest
+/// goes to the baker, where `p`, `a` and `u` are the numbers of the respective
+/// items in the block.
+pub fn distribute_fees(
+    fee_distribution: &TransactionFeeDistribution,
+    gas_rewards: &GASRewards,
+    old_gas_account: Amount,
+    block: &SimulatedBlock,
+) -> FeeDistributionOutcome {
+    let fees = block.transaction_fees.microgtu;
+    let baker_fees = fee_distribution
+        .baker
+        .take_fraction(block.transaction_fees)
+        .microgtu;
+    let gas_fees = fee_distribution
+        .gas_account
+        .take_fraction(block.transaction_fees)
+        .microgtu;
+    let platform_fees = fees - baker_fees - gas_fees;
+
+    let factors = [
+        (gas_rewards.baker, 1),
+        (
+            gas_rewards.finalization_proof,
+            u32::from(block.finalization_proof),
+        ),
+        (gas_rewards.account_creation, block.account_creations),
+        (gas_rewards.chain_update, block.chain_updates),
+    ];
+    let mut numerator = BigUint::from(old_gas_account.microgtu);
+    let mut denominator = BigUint::from(1u32);
+    for (fraction, count) in factors {
+        numerator *= BigUint::from(complement(fraction)).pow(count);
+        denominator *= BigUint::from(100_000u32).pow(count);
+    }
+    let gas_gas = ((numerator + &denominator - 1u32) / denominator)
+        .to_u64()
+        .expect("The result is at most the old GAS account.");
+    let baker_gas = old_gas_account.microgtu - gas_gas;
+
+    FeeDistributionOutcome {
+        baker_reward:      Amount {
+            microgtu: baker_fees + baker_gas,
+        },
+        foundation_charge: Amount {
+            microgtu: platform_fees,
+        },
+        new_gas_account:   Amount {
+            microgtu: gas_fees + gas_gas,
+        },
+    }
+}
+
+/// Compute the amount minted over the given number of slots, starting from the
+/// given total supply, and split it. Minting is compounded, i.e., the amount
+/// minted in each slot is computed from the total supply including the amounts
+/// minted in the previous slots. The reward accounts receive their fractions
+/// of the total minted amount, rounded down, and the foundation the rest.
+pub fn distribute_mint(
+    distribution: &MintDistribution,
+    total_amount: Amount,
+    slots: u64,
+) -> MintOutcome {
+    let rate = distribution.mint_per_slot();
+    let mut total = total_amount.microgtu;
+    for _ in 0..slots {
+        total += rate.mint_amount(Amount { microgtu: total }).microgtu;
+    }
+    let minted = Amount {
+        microgtu: total - total_amount.microgtu,
+    };
+    let baking_reward = distribution.baking_reward().take_fraction(minted);
+    let finalization_reward = distribution.finalization_reward().take_fraction(minted);
+    MintOutcome {
+        minted,
+        baking_reward,
+        finalization_reward,
+        platform_development_charge: Amount {
+            microgtu: minted.microgtu - baking_reward.microgtu - finalization_reward.microgtu,
+        },
+    }
+}
+
+/// Simulate the rewards of a block with the given reward parameters, when the
+/// supply before the block is as given.
+pub fn simulate(
+    parameters: &RewardParameters,
+    supply: &RewardsOverview,
+    block: &SimulatedBlock,
+) -> SimulatedRewards {
+    SimulatedRewards {
+        fees: distribute_fees(
+            &parameters.transaction_fee_distribution,
+            &parameters.gas_rewards,
+            supply.gas_account,
+            block,
+        ),
+        mint: distribute_mint(
+            &parameters.mint_distribution,
+            supply.total_amount,
+            block.mint_slots,
+        ),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+/// Comparison of the simulated and the actual distribution of fees in a block.
+pub struct BlockRewardCheck {
+    /// Hash of the block.
+    pub block_hash: BlockHash,
+    /// Height of the block.
+    pub height:     AbsoluteBlockHeight,
+    /// The distribution computed by [distribute_fees].
+    pub expected:   FeeDistributionOutcome,
+    /// The distribution recorded in the block.
+    pub actual:     FeeDistributionOutcome,
+}
+
+impl BlockRewardCheck {
+    /// Whether the simulation agrees with the block.
+    pub fn is_match(&self) -> bool { self.expected == self.actual }
+}
+
+/// Simulate the distribution of fees in the block, with the parameters in
+/// effect in the block, and compare it to the block's
+/// [BlockReward](SpecialTransactionOutcome::BlockReward) outcome. `None` if the
+/// block has no such outcome.
+pub fn check_block_reward(block: &FinalizedBlock) -> Option<BlockRewardCheck> {
+    let (old_gas_account, actual) =
+        block
+            .summary
+            .special_events
+            .iter()
+            .find_map(|outcome| match outcome {
+                SpecialTransactionOutcome::BlockReward {
+                    old_gas_account,
+                    new_gas_account,
+                    baker_reward,
+                    foundation_charge,
+                    ..
+                } => Some((*old_gas_account, FeeDistributionOutcome {
+                    baker_reward:      *baker_reward,
+                    foundation_charge: *foundation_charge,
+                    new_gas_account:   *new_gas_account,
+                })),
+                _ => None,
+            })?;
+    let parameters = &block.summary.updates.chain_parameters.reward_parameters;
+    let expected = distribute_fees(
+        &parameters.transaction_fee_distribution,
+        &parameters.gas_rewards,
+        old_gas_account,
+        &SimulatedBlock::from_block(block),
+    );
+    Some(BlockRewardCheck {
+        block_hash: block.info.block_hash,
+        height: block.info.block_height,
+        expected,
+        actual,
+    })
+}
+
+/// Check the distribution of fees in the finalized blocks in the given range of
+/// heights, both ends inclusive, and return the blocks where the simulation
+/// does not agree with the chain.
+pub async fn block_reward_mismatches(
+    client: endpoints::Client,
+    from: AbsoluteBlockHeight,
+    to: AbsoluteBlockHeight,
+) -> QueryResult<Vec<BlockRewardCheck>> {
+    let mut blocks = Box::pin(finalized_blocks(
+        client,
+        from,
+        StreamEnd::AtHeight(to),
+        std::time::Duration::from_secs(1),
+    ));
+    let mut mismatches = Vec::new();
+    while let Some(block) = blocks.next().await {
+        if let Some(check) = check_block_reward(&block?) {
+            if !check.is_match() {
+                mismatches.push(check);
+            }
+        }
+    }
+    Ok(mismatches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{MintRate, PartsPerHundredThousands};
+
+    fn fraction(parts: u32) -> RewardFraction {
+        RewardFraction {
+            parts_per_hundred_thousands: PartsPerHundredThousands { parts },
+        }
+    }
+
+    #[test]
+    fn test_distribute_mint() {
+        // 0.1% minted per slot, 60% of it to bakers and 30% to finalizers.
+        let rate = MintRate {
+            mantissa: 1,
+            exponent: 3,
+        };
+        let distribution = MintDistribution::new(rate, fraction(60_000), fraction(30_000))
+            .expect("Fractions are below 100%.");
+        // 1000 is minted in the first slot, and 1001 in the second since the
+        // supply has grown.
+        let outcome = distribute_mint(
+            &distribution,
+            Amount {
+                microgtu: 1_000_000,
+            },
+            2,
+        );
+        assert_eq!(outcome, MintOutcome {
+            minted: Amount { microgtu: 2001 },
+            baking_reward: Amount { microgtu: 1200 },
+            finalization_reward: Amount { microgtu: 600 },
+            platform_development_charge: Amount { microgtu: 201 },
+        });
+        let outcome = distribute_mint(
+            &distribution,
+            Amount {
+                microgtu: 1_000_000,
+            },
+            0,
+        );
+        assert_eq!(outcome.minted.microgtu, 0);
+        assert_eq!(outcome.platform_development_charge.microgtu, 0);
+    }
+
+    #[test]
+    fn test_distribute_fees() {
+        let fee_distribution = TransactionFeeDistribution {
+            baker:       fraction(45_000),
+            gas_account: fraction(45_000),
+        };
+        let gas_rewards = GASRewards {
+            baker:              fraction(25_000),
+            finalization_proof: fraction(50),
+            account_creation:   fraction(2_000),
+            chain_update:       fraction(5),
+        };
+        let mut block = SimulatedBlock {
+            transaction_fees:   Amount { microgtu: 1_000 },
+            account_creations:  0,
+            chain_updates:      0,
+            finalization_proof: true,
+            mint_slots:         0,
+        };
+        // 10000 * 0.75 * 0.9995 = 7496.25 remains in the GAS account, rounded
+        // up.
+        let outcome = distribute_fees(
+            &fee_distribution,
+            &gas_rewards,
+            Amount { microgtu: 10_000 },
+            &block,
+        );
+        assert_eq!(outcome, FeeDistributionOutcome {
+            baker_reward:      Amount {
+                microgtu: 450 + 2_503,
+            },
+            foundation_charge: Amount { microgtu: 100 },
+            new_gas_account:   Amount {
+                microgtu: 450 + 7_497,
+            },
+        });
+
+        // 10000 * 0.75 * 0.98^2 = 7203 exactly.
+        block.finalization_proof = false;
+        block.account_creations = 2;
+        let outcome = distribute_fees(
+            &fee_distribution,
+            &gas_rewards,
+            Amount { microgtu: 10_000 },
+            &block,
+        );
+        assert_eq!(outcome.new_gas_account.microgtu, 450 + 7_203);
+        assert_eq!(outcome.baker_reward.microgtu, 450 + 2_797);
+    }
+}
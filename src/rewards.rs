@@ -0,0 +1,398 @@
+//! Accounting of the rewards earned by bakers.
+//!
+//! Bakers are rewarded via special transaction outcomes in block summaries:
+//! [BakingRewards](SpecialTransactionOutcome::BakingRewards) are paid at the
+//! start of each epoch for the blocks baked in the previous epoch,
+//! [FinalizationRewards](SpecialTransactionOutcome::FinalizationRewards) are
+//! paid in blocks that contain a finalization record, and a
+//! [BlockReward](SpecialTransactionOutcome::BlockReward) with the baker's share
+//! of the transaction fees is paid in every block. The [RewardReport] collects
+//! these over a range of blocks, per account and per epoch.
+use crate::{
+    blocks::{finalized_blocks, FinalizedBlock, StreamEnd},
+    clock::{ChainClock, ClockError},
+    endpoints::{self, QueryError, QueryResult},
+    types::{AbsoluteBlockHeight, BakerId, Epoch, GenesisIndex, SpecialTransactionOutcome},
+};
+use crypto_common::types::Amount;
+use futures::StreamExt;
+use id::types::AccountAddress;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+/// Errors that can occur when collecting rewards.
+pub enum RewardError {
+    #[error("Error querying the node: {0}")]
+    /// Querying the node failed.
+    Query(#[from] QueryError),
+    #[error("Could not determine the epochs of the chain: {0}")]
+    /// The slot and epoch durations of the chain could not be determined.
+    Clock(#[from] ClockError),
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Rewards of a single account, split by their source.
+pub struct RewardAmounts {
+    /// Rewards for baking blocks.
+    pub baking:           Amount,
+    /// Rewards for participating in finalization.
+    pub finalization:     Amount,
+    /// The baker's share of transaction fees of the blocks it baked.
+    pub transaction_fees: Amount,
+}
+
+impl RewardAmounts {
+    /// The sum of all the rewards.
+    pub fn total(&self) -> Amount {
+        Amount {
+            microgtu: self.baking.microgtu
+                + self.finalization.microgtu
+                + self.transaction_fees.microgtu,
+        }
+    }
+
+    fn add(&mut self, other: &RewardAmounts) {
+        self.baking.microgtu += other.baking.microgtu;
+        self.finalization.microgtu += other.finalization.microgtu;
+        self.transaction_fees.microgtu += other.transaction_fees.microgtu;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// An epoch of a specific era of the chain. Epochs are numbered from the start
+/// of each era, so the genesis index is needed to identify an epoch uniquely.
+pub struct EraEpoch {
+    /// The era the epoch belongs to.
+    pub genesis_index: GenesisIndex,
+    /// The epoch within the era.
+    pub epoch:         Epoch,
+}
+
+#[derive(Debug, Clone)]
+/// Rewards of an account, in total and per epoch.
+pub struct AccountRewards {
+    /// The baker the account belongs to, if the account was a baker in the
+    /// scanned range.
+    pub baker_id:  Option<BakerId>,
+    /// The total rewards in the scanned range.
+    pub total:     RewardAmounts,
+    /// Rewards in each epoch, in which the account received a reward.
+    pub per_epoch: BTreeMap<EraEpoch, RewardAmounts>,
+}
+
+#[derive(Debug, Default)]
+/// Rewards of all accounts that received rewards in a range of blocks.
+pub struct RewardReport {
+    /// Rewards of each account.
+    pub accounts: BTreeMap<AccountAddress, AccountRewards>,
+}
+
+impl RewardReport {
+    fn record(
+        &mut self,
+        account: AccountAddress,
+        epoch: EraEpoch,
+        baker_id: Option<BakerId>,
+        amounts: RewardAmounts,
+    ) {
+        let entry = self
+            .accounts
+            .entry(account)
+            .or_insert_with(|| AccountRewards {
+                baker_id,
+                total: RewardAmounts::default(),
+                per_epoch: BTreeMap::new(),
+            });
+        entry.baker_id = entry.baker_id.or(baker_id);
+        entry.total.add(&amounts);
+        entry.per_epoch.entry(epoch).or_default().add(&amounts);
+    }
+
+    /// Rewards of each baker. Rewards of accounts that could not be associated
+    /// with a baker are not included.
+    pub fn per_baker(&self) -> BTreeMap<BakerId, &AccountRewards> {
+        self.accounts
+            .values()
+            .filter_map(|rewards| Some((rewards.baker_id?, rewards)))
+            .collect()
+    }
+
+    /// Write the per-epoch rewards as CSV, with one row per account and epoch.
+    /// Amounts are written in CCD.
+    pub fn write_csv<W: std::io::Write>(&self, out: W) -> csv::Result<()> {
+        let mut writer = csv::Writer::from_writer(out);
+        writer.write_record(&[
+            "genesis_index",
+            "epoch",
+            "account",
+            "baker_id",
+            "baking",
+            "finalization",
+            "transaction_fees",
+            "total",
+        ])?;
+        for (account, rewards) in self.accounts.iter() {
+            let baker_id = rewards
+                .baker_id
+                .map(|id| id.to_string())
+                .unwrap_or_default();
+            for (epoch, amounts) in rewards.per_epoch.iter() {
+                writer.write_record(&[
+                    epoch.genesis_index.to_string(),
+                    epoch.epoch.to_string(),
+                    account.to_string(),
+                    baker_id.clone(),
+                    amounts.baking.to_string(),
+                    amounts.finalization.to_string(),
+                    amounts.transaction_fees.to_string(),
+                    amounts.total().to_string(),
+                ])?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Collects rewards from blocks. The accumulator keeps track of which accounts
+/// belong to which bakers, and queries the node for the current bakers when it
+/// encounters an unknown account. The bakers are queried at most once per
+/// epoch, so accounts that are not bakers do not cause a query per reward.
+pub struct RewardAccumulator {
+    client:        endpoints::Client,
+    clock:         ChainClock,
+    bakers:        BTreeMap<AccountAddress, BakerId>,
+    /// Epochs for which the bakers have already been queried.
+    queried_epoch: BTreeSet<EraEpoch>,
+    report:        RewardReport,
+}
+
+impl RewardAccumulator {
+    /// Construct a new accumulator with an empty report.
+    pub async fn new(mut client: endpoints::Client) -> Result<Self, ClockError> {
+        let clock = ChainClock::from_client(&mut client).await?;
+        Ok(Self {
+            client,
+            clock,
+            bakers: BTreeMap::new(),
+            queried_epoch: BTreeSet::new(),
+            report: RewardReport::default(),
+        })
+    }
+
+    async fn baker_id(
+        &mut self,
+        block: &FinalizedBlock,
+        epoch: EraEpoch,
+        account: AccountAddress,
+    ) -> QueryResult<Option<BakerId>> {
+        if let Some(id) = self.bakers.get(&account) {
+            return Ok(Some(*id));
+        }
+        // The bakers of this epoch are already known, and the account is not
+        // one of them.
+        if !self.queried_epoch.insert(epoch) {
+            return Ok(None);
+        }
+        let birk = self
+            .client
+            .get_birk_parameters(&block.info.block_hash)
+            .await?;
+        for baker in birk.bakers {
+            self.bakers.insert(baker.baker_account, baker.baker_id);
+        }
+        Ok(self.bakers.get(&account).copied())
+    }
+
+    /// Add the rewards paid in the block to the report.
+    pub async fn add_block(&mut self, block: &FinalizedBlock) -> QueryResult<()> {
+        let epoch = EraEpoch {
+            genesis_index: block.info.genesis_index,
+            epoch:         self.clock.epoch_of_slot(block.info.block_slot),
+        };
+        let mut rewards = Vec::new();
+        for outcome in block.summary.special_events.iter() {
+            match outcome {
+                SpecialTransactionOutcome::BakingRewards { baker_rewards, .. } => {
+                    rewards.extend(baker_rewards.iter().map(|(acc, amount)| {
+                        (*acc, RewardAmounts {
+                            baking: *amount,
+                            ..Default::default()
+                        })
+                    }))
+                }
+                SpecialTransactionOutcome::FinalizationRewards {
+                    finalization_rewards,
+                    ..
+                } => rewards.extend(finalization_rewards.iter().map(|(acc, amount)| {
+                    (*acc, RewardAmounts {
+                        finalization: *amount,
+                        ..Default::default()
+                    })
+                })),
+                SpecialTransactionOutcome::BlockReward {
+                    baker,
+                    baker_reward,
+                    ..
+                } => rewards.push((*baker, RewardAmounts {
+                    transaction_fees: *baker_reward,
+                    ..Default::default()
+                })),
+                SpecialTransactionOutcome::Mint { .. } => (),
+            }
+        }
+        for (account, amounts) in rewards {
+            let baker_id = self.baker_id(block, epoch, account).await?;
+            self.report.record(account, epoch, baker_id, amounts);
+        }
+        Ok(())
+    }
+
+    /// The rewards accumulated so far.
+    pub fn report(&self) -> &RewardReport { &self.report }
+
+    /// Finish accumulating and return the report.
+    pub fn into_report(self) -> RewardReport { self.report }
+}
+
+/// Collect the rewards paid in the finalized blocks in the given range of
+/// heights, both ends inclusive. If `time_range` is given only blocks with slot
+/// time in the range are considered.
+pub async fn rewards_in_range(
+    client: endpoints::Client,
+    from: AbsoluteBlockHeight,
+    to: AbsoluteBlockHeight,
+    time_range: Option<Range<chrono::DateTime<chrono::Utc>>>,
+) -> Result<RewardReport, RewardError> {
+    let mut accumulator = RewardAccumulator::new(client.clone()).await?;
+    let mut blocks = Box::pin(finalized_blocks(
+        client,
+        from,
+        StreamEnd::AtHeight(to),
+        std::time::Duration::from_secs(1),
+    ));
+    while let Some(block) = blocks.next().await {
+        let block = block?;
+        if let Some(range) = &time_range {
+            if !range.contains(&block.info.block_slot_time) {
+                continue;
+            }
+        }
+        accumulator.add_block(&block).await?;
+    }
+    Ok(accumulator.into_report())
+}
+
+/// Collect the rewards paid in the finalized blocks with slot time in the
+/// given range. The heights of the first and last block in the range are
+/// found with
+/// [find_finalized_block_at_time](endpoints::Client::find_finalized_block_at_time),
+/// after which this is the same as [rewards_in_range].
+pub async fn rewards_in_time_range(
+    mut client: endpoints::Client,
+    time_range: Range<chrono::DateTime<chrono::Utc>>,
+) -> Result<RewardReport, RewardError> {
+    let to = match client.find_finalized_block_at_time(time_range.end).await {
+        Ok(block) => block.height,
+        // The range ends before genesis, so there are no blocks in it.
+        Err(QueryError::NotFound) => return Ok(RewardReport::default()),
+        Err(e) => return Err(e.into()),
+    };
+    let from = match client.find_finalized_block_at_time(time_range.start).await {
+        Ok(block) => block.height,
+        Err(QueryError::NotFound) => 0.into(),
+        Err(e) => return Err(e.into()),
+    };
+    rewards_in_range(client, from, to, Some(time_range)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn era_epoch(genesis_index: u32, epoch: u64) -> EraEpoch {
+        EraEpoch {
+            genesis_index: genesis_index.into(),
+            epoch:         Epoch { epoch },
+        }
+    }
+
+    fn baking(microgtu: u64) -> RewardAmounts {
+        RewardAmounts {
+            baking: Amount { microgtu },
+            ..Default::default()
+        }
+    }
+
+    fn fees(microgtu: u64) -> RewardAmounts {
+        RewardAmounts {
+            transaction_fees: Amount { microgtu },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let baker = AccountAddress([1u8; 32]);
+        let other = AccountAddress([2u8; 32]);
+        let mut report = RewardReport::default();
+        report.record(baker, era_epoch(0, 1), None, baking(10));
+        report.record(baker, era_epoch(0, 1), Some(BakerId { id: 7 }), fees(3));
+        report.record(baker, era_epoch(1, 0), None, fees(5));
+        report.record(other, era_epoch(0, 1), None, baking(1));
+
+        let rewards = &report.accounts[&baker];
+        // The baker id is kept once it is known.
+        assert_eq!(rewards.baker_id, Some(BakerId { id: 7 }));
+        assert_eq!(rewards.total.baking.microgtu, 10);
+        assert_eq!(rewards.total.transaction_fees.microgtu, 8);
+        assert_eq!(rewards.total.total().microgtu, 18);
+        assert_eq!(rewards.per_epoch.len(), 2);
+        assert_eq!(rewards.per_epoch[&era_epoch(0, 1)].total().microgtu, 13);
+        assert_eq!(rewards.per_epoch[&era_epoch(1, 0)].total().microgtu, 5);
+
+        let per_baker = report.per_baker();
+        assert_eq!(per_baker.len(), 1);
+        assert_eq!(per_baker[&BakerId { id: 7 }].total.total().microgtu, 18);
+    }
+
+    #[test]
+    fn test_write_csv() {
+        let account = AccountAddress([1u8; 32]);
+        let mut report = RewardReport::default();
+        report.record(account, era_epoch(1, 0), Some(BakerId { id: 7 }), fees(5));
+        report.record(
+            account,
+            era_epoch(0, 2),
+            Some(BakerId { id: 7 }),
+            baking(10),
+        );
+        let mut out = Vec::new();
+        report
+            .write_csv(&mut out)
+            .expect("Writing to a vector succeeds.");
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[0],
+            "genesis_index,epoch,account,baker_id,baking,finalization,transaction_fees,total"
+        );
+        let zero = Amount { microgtu: 0 }.to_string();
+        let five = Amount { microgtu: 5 }.to_string();
+        let ten = Amount { microgtu: 10 }.to_string();
+        // Epochs are written in chronological order.
+        assert_eq!(
+            lines[1],
+            format!("0,2,{},7,{},{},{},{}", account, ten, zero, zero, ten)
+        );
+        assert_eq!(
+            lines[2],
+            format!("1,0,{},7,{},{},{},{}", account, zero, zero, five, five)
+        );
+        assert_eq!(lines.len(), 3);
+    }
+}
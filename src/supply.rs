@@ -0,0 +1,565 @@
+//! Tracking of the total CCD supply.
+//!
+//! New CCD is minted in every block, in proportion to the number of slots since
+//! the parent block, and split between the baking reward account, the
+//! finalization reward account, and the foundation according to the
+//! [MintDistribution](crate::types::MintDistribution) in effect. The reward
+//! accounts are paid out to bakers, and transaction fees move between accounts
+//! and the GAS account. The [SupplyTracker] follows all of these through the
+//! special outcomes of consecutive blocks, and cross-checks its accounting
+//! against the [RewardsOverview] the node reports for each block.
+use crate::{
+    blocks::{finalized_blocks, FinalizedBlock, StreamEnd},
+    endpoints::{self, QueryResult},
+    reward_simulation::distribute_mint,
+    types::{
+        hashes::BlockHash, AbsoluteBlockHeight, AccountTransactionEffects, BlockItemSummary,
+        BlockItemSummaryDetails, GenesisIndex, MintDistribution, RewardsOverview, Slot,
+        SpecialTransactionOutcome,
+    },
+};
+use crypto_common::types::Amount;
+use futures::StreamExt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A quantity tracked by the [SupplyTracker].
+pub enum SupplyField {
+    /// Total amount of CCD in existence.
+    TotalAmount,
+    /// Total amount of CCD in encrypted balances.
+    TotalEncryptedAmount,
+    /// Balance of the baking reward account.
+    BakingRewardAccount,
+    /// Balance of the finalization reward account.
+    FinalizationRewardAccount,
+    /// Balance of the GAS account.
+    GasAccount,
+    /// Total amount minted in a block.
+    Mint,
+    /// Part of the minted amount that goes to the baking reward account.
+    MintBakingReward,
+    /// Part of the minted amount that goes to the finalization reward account.
+    MintFinalizationReward,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// A difference between the tracker's accounting and the chain.
+pub struct SupplyMismatch {
+    /// Height of the block where the difference was found.
+    pub height:     AbsoluteBlockHeight,
+    /// Hash of the block where the difference was found.
+    pub block_hash: BlockHash,
+    /// The quantity that differs.
+    pub field:      SupplyField,
+    /// The value computed by the tracker.
+    pub expected:   Amount,
+    /// The value reported by the node, or recorded in the block.
+    pub actual:     Amount,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// The supply after a block.
+pub struct SupplyPoint {
+    /// Height of the block.
+    pub height:          AbsoluteBlockHeight,
+    /// Hash of the block.
+    pub block_hash:      BlockHash,
+    /// Slot time of the block.
+    pub slot_time:       chrono::DateTime<chrono::Utc>,
+    /// Total supply and balances of the reward accounts, as reported by the
+    /// node.
+    pub supply:          RewardsOverview,
+    /// CCD minted in the block.
+    pub minted:          Amount,
+    /// Cumulative amount minted for the foundation since the start of tracking.
+    pub foundation_mint: Amount,
+    /// Cumulative share of transaction fees paid to the foundation since the
+    /// start of tracking.
+    pub foundation_fees: Amount,
+}
+
+#[derive(Debug, Default)]
+/// Time series of the supply over a range of blocks.
+pub struct SupplyReport {
+    /// The supply after each block, in order of height.
+    pub points:     Vec<SupplyPoint>,
+    /// Differences between the tracker's accounting and the chain, in order of
+    /// height.
+    pub mismatches: Vec<SupplyMismatch>,
+}
+
+impl SupplyReport {
+    /// Whether the tracker's accounting agreed with the chain in every block.
+    pub fn is_consistent(&self) -> bool { self.mismatches.is_empty() }
+}
+
+#[derive(Debug, Clone)]
+/// The supply after a block, computed from the supply before the block and
+/// the outcomes of the block.
+pub struct AppliedBlock {
+    /// The computed supply after the block.
+    pub supply:          RewardsOverview,
+    /// CCD minted in the block.
+    pub minted:          Amount,
+    /// Part of the minted amount that went to the foundation.
+    pub foundation_mint: Amount,
+    /// Share of the transaction fees that went to the foundation.
+    pub foundation_fees: Amount,
+    /// Differences between the outcomes of the block and the accounting, e.g.,
+    /// a minted amount that does not match the mint rate, or a payout that
+    /// exceeds the balance of a reward account. In the latter case `expected`
+    /// is the balance and `actual` the payout.
+    pub mismatches:      Vec<SupplyMismatch>,
+}
+
+fn add(target: &mut Amount, amount: Amount) { target.microgtu += amount.microgtu; }
+
+/// Collects the mismatches found in a single block.
+struct Mismatches {
+    height:     AbsoluteBlockHeight,
+    block_hash: BlockHash,
+    mismatches: Vec<SupplyMismatch>,
+}
+
+impl Mismatches {
+    fn check(&mut self, field: SupplyField, expected: Amount, actual: Amount) {
+        if expected != actual {
+            self.mismatches.push(SupplyMismatch {
+                height: self.height,
+                block_hash: self.block_hash,
+                field,
+                expected,
+                actual,
+            });
+        }
+    }
+
+    /// Subtract the amount from the balance of the field. A balance that is too
+    /// small is recorded as a mismatch and set to zero.
+    fn sub(&mut self, field: SupplyField, balance: &mut Amount, amount: Amount) {
+        match balance.microgtu.checked_sub(amount.microgtu) {
+            Some(rest) => balance.microgtu = rest,
+            None => {
+                self.check(field, *balance, amount);
+                balance.microgtu = 0;
+            }
+        }
+    }
+}
+
+/// Apply the outcomes of the block to the supply before it. `mint_slots` is
+/// the number of slots since the parent block, if known, and is needed to check
+/// the minted amount. The result is not compared to the supply the node
+/// reports after the block, see [compare_supply] for that.
+pub fn apply_block(
+    supply: &RewardsOverview,
+    mint_slots: Option<u64>,
+    block: &FinalizedBlock,
+) -> AppliedBlock {
+    apply_outcomes(
+        supply,
+        mint_slots,
+        &block
+            .summary
+            .updates
+            .chain_parameters
+            .reward_parameters
+            .mint_distribution,
+        &block.summary.special_events,
+        &block.summary.transaction_summaries,
+        block.info.block_height,
+        block.info.block_hash,
+    )
+}
+
+fn apply_outcomes(
+    supply: &RewardsOverview,
+    mint_slots: Option<u64>,
+    distribution: &MintDistribution,
+    special_events: &[SpecialTransactionOutcome],
+    transaction_summaries: &[BlockItemSummary],
+    height: AbsoluteBlockHeight,
+    block_hash: BlockHash,
+) -> AppliedBlock {
+    let mut mismatches = Mismatches {
+        height,
+        block_hash,
+        mismatches: Vec::new(),
+    };
+    let mut expected = *supply;
+    let mut minted = Amount { microgtu: 0 };
+    let mut foundation_mint = Amount { microgtu: 0 };
+    let mut foundation_fees = Amount { microgtu: 0 };
+    for outcome in special_events.iter() {
+        match outcome {
+            SpecialTransactionOutcome::BakingRewards { baker_rewards, .. } => {
+                for amount in baker_rewards.values() {
+                    mismatches.sub(
+                        SupplyField::BakingRewardAccount,
+                        &mut expected.baking_reward_account,
+                        *amount,
+                    );
+                }
+            }
+            SpecialTransactionOutcome::Mint {
+                mint_baking_reward,
+                mint_finalization_reward,
+                mint_platform_development_charge,
+                ..
+            } => {
+                add(&mut minted, *mint_baking_reward);
+                add(&mut minted, *mint_finalization_reward);
+                add(&mut minted, *mint_platform_development_charge);
+                if let Some(slots) = mint_slots {
+                    let expected = distribute_mint(distribution, supply.total_amount, slots);
+                    mismatches.check(SupplyField::Mint, expected.minted, minted);
+                }
+                let baking = distribution.baking_reward().take_fraction(minted);
+                let finalization = distribution.finalization_reward().take_fraction(minted);
+                mismatches.check(SupplyField::MintBakingReward, baking, *mint_baking_reward);
+                mismatches.check(
+                    SupplyField::MintFinalizationReward,
+                    finalization,
+                    *mint_finalization_reward,
+                );
+                add(&mut expected.total_amount, minted);
+                add(&mut expected.baking_reward_account, *mint_baking_reward);
+                add(
+                    &mut expected.finalization_reward_account,
+                    *mint_finalization_reward,
+                );
+                add(&mut foundation_mint, *mint_platform_development_charge);
+            }
+            SpecialTransactionOutcome::FinalizationRewards {
+                finalization_rewards,
+                ..
+            } => {
+                for amount in finalization_rewards.values() {
+                    mismatches.sub(
+                        SupplyField::FinalizationRewardAccount,
+                        &mut expected.finalization_reward_account,
+                        *amount,
+                    );
+                }
+            }
+            SpecialTransactionOutcome::BlockReward {
+                old_gas_account,
+                new_gas_account,
+                foundation_charge,
+                ..
+            } => {
+                mismatches.check(
+                    SupplyField::GasAccount,
+                    expected.gas_account,
+                    *old_gas_account,
+                );
+                expected.gas_account = *new_gas_account;
+                add(&mut foundation_fees, *foundation_charge);
+            }
+        }
+    }
+    for summary in transaction_summaries.iter() {
+        if let BlockItemSummaryDetails::AccountTransaction(at) = &summary.details {
+            match &at.effects {
+                AccountTransactionEffects::TransferredToEncrypted { data } => {
+                    add(&mut expected.total_encrypted_amount, data.amount)
+                }
+                AccountTransactionEffects::TransferredToPublic { amount, .. } => mismatches.sub(
+                    SupplyField::TotalEncryptedAmount,
+                    &mut expected.total_encrypted_amount,
+                    *amount,
+                ),
+                _ => (),
+            }
+        }
+    }
+    AppliedBlock {
+        supply: expected,
+        minted,
+        foundation_mint,
+        foundation_fees,
+        mismatches: mismatches.mismatches,
+    }
+}
+
+/// Compare the supply computed for the block with height `height` and hash
+/// `block_hash` to the supply reported by the node for it.
+pub fn compare_supply(
+    height: AbsoluteBlockHeight,
+    block_hash: BlockHash,
+    expected: &RewardsOverview,
+    actual: &RewardsOverview,
+) -> Vec<SupplyMismatch> {
+    let mut mismatches = Mismatches {
+        height,
+        block_hash,
+        mismatches: Vec::new(),
+    };
+    mismatches.check(
+        SupplyField::TotalAmount,
+        expected.total_amount,
+        actual.total_amount,
+    );
+    mismatches.check(
+        SupplyField::TotalEncryptedAmount,
+        expected.total_encrypted_amount,
+        actual.total_encrypted_amount,
+    );
+    mismatches.check(
+        SupplyField::BakingRewardAccount,
+        expected.baking_reward_account,
+        actual.baking_reward_account,
+    );
+    mismatches.check(
+        SupplyField::FinalizationRewardAccount,
+        expected.finalization_reward_account,
+        actual.finalization_reward_account,
+    );
+    mismatches.check(
+        SupplyField::GasAccount,
+        expected.gas_account,
+        actual.gas_account,
+    );
+    mismatches.mismatches
+}
+
+/// Follows the supply through consecutive finalized blocks. The first block
+/// added is the baseline, its supply is taken from the node. For each later
+/// block the supply is computed from the previous one and the block's
+/// outcomes, see [apply_block], and compared to what the node reports. After a
+/// mismatch the tracker continues from the node's values.
+pub struct SupplyTracker {
+    client:          endpoints::Client,
+    /// Era and slot of the last block added.
+    last:            Option<(GenesisIndex, Slot)>,
+    supply:          Option<RewardsOverview>,
+    foundation_mint: Amount,
+    foundation_fees: Amount,
+    report:          SupplyReport,
+}
+
+impl SupplyTracker {
+    /// Construct a new tracker.
+    pub fn new(client: endpoints::Client) -> Self {
+        Self {
+            client,
+            last: None,
+            supply: None,
+            foundation_mint: Amount { microgtu: 0 },
+            foundation_fees: Amount { microgtu: 0 },
+            report: SupplyReport::default(),
+        }
+    }
+
+    /// The number of slots since the previous block. `None` if the previous
+    /// block is not known or belongs to a different era.
+    fn mint_slots(&self, block: &FinalizedBlock) -> Option<u64> {
+        let (era, slot) = self.last?;
+        if era != block.info.genesis_index || slot >= block.info.block_slot {
+            return None;
+        }
+        Some(block.info.block_slot.slot - slot.slot)
+    }
+
+    /// Add the next block. Blocks must be added in order of increasing height
+    /// without gaps.
+    pub async fn add_block(&mut self, block: &FinalizedBlock) -> QueryResult<()> {
+        let actual = self
+            .client
+            .get_reward_status(&block.info.block_hash)
+            .await?;
+        let mut minted = Amount { microgtu: 0 };
+        if let Some(supply) = self.supply {
+            let applied = apply_block(&supply, self.mint_slots(block), block);
+            minted = applied.minted;
+            add(&mut self.foundation_mint, applied.foundation_mint);
+            add(&mut self.foundation_fees, applied.foundation_fees);
+            self.report.mismatches.extend(applied.mismatches);
+            self.report.mismatches.extend(compare_supply(
+                block.info.block_height,
+                block.info.block_hash,
+                &applied.supply,
+                &actual,
+            ));
+        }
+        self.supply = Some(actual);
+        self.last = Some((block.info.genesis_index, block.info.block_slot));
+        self.report.points.push(SupplyPoint {
+            height: block.info.block_height,
+            block_hash: block.info.block_hash,
+            slot_time: block.info.block_slot_time,
+            supply: actual,
+            minted,
+            foundation_mint: self.foundation_mint,
+            foundation_fees: self.foundation_fees,
+        });
+        Ok(())
+    }
+
+    /// The report of the blocks added so far.
+    pub fn report(&self) -> &SupplyReport { &self.report }
+
+    /// Finish tracking and return the report.
+    pub fn into_report(self) -> SupplyReport { self.report }
+}
+
+/// Track the supply through the finalized blocks in the given range of
+/// heights, both ends inclusive. The block at height `from` is the baseline.
+pub async fn track_supply(
+    client: endpoints::Client,
+    from: AbsoluteBlockHeight,
+    to: AbsoluteBlockHeight,
+) -> QueryResult<SupplyReport> {
+    let mut tracker = SupplyTracker::new(client.clone());
+    let mut blocks = Box::pin(finalized_blocks(
+        client,
+        from,
+        StreamEnd::AtHeight(to),
+        std::time::Duration::from_secs(1),
+    ));
+    while let Some(block) = blocks.next().await {
+        tracker.add_block(&block?).await?;
+    }
+    Ok(tracker.into_report())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{MintRate, PartsPerHundredThousands, RewardFraction};
+    use id::types::AccountAddress;
+
+    fn amount(microgtu: u64) -> Amount { Amount { microgtu } }
+
+    fn fraction(parts: u32) -> RewardFraction {
+        RewardFraction {
+            parts_per_hundred_thousands: PartsPerHundredThousands { parts },
+        }
+    }
+
+    /// 0.1% minted per slot, 60% of it to bakers and 30% to finalizers.
+    fn distribution() -> MintDistribution {
+        let rate = MintRate {
+            mantissa: 1,
+            exponent: 3,
+        };
+        MintDistribution::new(rate, fraction(60_000), fraction(30_000))
+            .expect("Fractions are below 100%.")
+    }
+
+    fn supply() -> RewardsOverview {
+        RewardsOverview {
+            total_amount:                amount(1_000_000),
+            total_encrypted_amount:      amount(500),
+            baking_reward_account:       amount(100),
+            finalization_reward_account: amount(50),
+            gas_account:                 amount(30),
+        }
+    }
+
+    fn mint(baking: u64, finalization: u64, foundation: u64) -> SpecialTransactionOutcome {
+        SpecialTransactionOutcome::Mint {
+            mint_baking_reward:               amount(baking),
+            mint_finalization_reward:         amount(finalization),
+            mint_platform_development_charge: amount(foundation),
+            foundation_account:               AccountAddress([0u8; 32]),
+        }
+    }
+
+    fn baking_rewards(reward: u64) -> SpecialTransactionOutcome {
+        SpecialTransactionOutcome::BakingRewards {
+            baker_rewards: std::iter::once((AccountAddress([1u8; 32]), amount(reward))).collect(),
+            remainder:     amount(0),
+        }
+    }
+
+    fn apply(events: &[SpecialTransactionOutcome]) -> AppliedBlock {
+        apply_outcomes(
+            &supply(),
+            Some(2),
+            &distribution(),
+            events,
+            &[],
+            1.into(),
+            BlockHash::new([0u8; 32]),
+        )
+    }
+
+    #[test]
+    fn test_apply_block() {
+        let events = [
+            // 1000 minted in the first slot, 1001 in the second. 60% and 30% of
+            // 2001 are 1200 and 600 rounded down.
+            mint(1200, 600, 201),
+            baking_rewards(1000),
+            SpecialTransactionOutcome::FinalizationRewards {
+                finalization_rewards: std::iter::once((AccountAddress([1u8; 32]), amount(650)))
+                    .collect(),
+                remainder:            amount(0),
+            },
+            SpecialTransactionOutcome::BlockReward {
+                transaction_fees:   amount(10),
+                old_gas_account:    amount(30),
+                new_gas_account:    amount(35),
+                baker_reward:       amount(4),
+                foundation_charge:  amount(1),
+                baker:              AccountAddress([1u8; 32]),
+                foundation_account: AccountAddress([0u8; 32]),
+            },
+        ];
+        let applied = apply(&events);
+        assert!(applied.mismatches.is_empty(), "{:?}", applied.mismatches);
+        assert_eq!(applied.minted, amount(2001));
+        assert_eq!(applied.foundation_mint, amount(201));
+        assert_eq!(applied.foundation_fees, amount(1));
+        assert_eq!(applied.supply.total_amount, amount(1_002_001));
+        assert_eq!(applied.supply.total_encrypted_amount, amount(500));
+        assert_eq!(applied.supply.baking_reward_account, amount(300));
+        assert_eq!(applied.supply.finalization_reward_account, amount(0));
+        assert_eq!(applied.supply.gas_account, amount(35));
+
+        let height = 1.into();
+        let hash = BlockHash::new([0u8; 32]);
+        assert!(compare_supply(height, hash, &applied.supply, &applied.supply).is_empty());
+        let mut actual = applied.supply;
+        actual.gas_account = amount(36);
+        let mismatches = compare_supply(height, hash, &applied.supply, &actual);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].field, SupplyField::GasAccount);
+        assert_eq!(mismatches[0].expected, amount(35));
+        assert_eq!(mismatches[0].actual, amount(36));
+    }
+
+    #[test]
+    fn test_mint_mismatch() {
+        let applied = apply(&[mint(1200, 600, 200)]);
+        assert_eq!(applied.mismatches.len(), 1);
+        assert_eq!(applied.mismatches[0].field, SupplyField::Mint);
+        assert_eq!(applied.mismatches[0].expected, amount(2001));
+        assert_eq!(applied.mismatches[0].actual, amount(2000));
+        // Without the parent's slot the minted amount cannot be checked.
+        let applied = apply_outcomes(
+            &supply(),
+            None,
+            &distribution(),
+            &[mint(1200, 600, 200)],
+            &[],
+            1.into(),
+            BlockHash::new([0u8; 32]),
+        );
+        assert!(applied.mismatches.is_empty());
+    }
+
+    #[test]
+    fn test_payout_underflow() {
+        let applied = apply(&[baking_rewards(150)]);
+        assert_eq!(applied.mismatches.len(), 1);
+        assert_eq!(
+            applied.mismatches[0].field,
+            SupplyField::BakingRewardAccount
+        );
+        assert_eq!(applied.mismatches[0].expected, amount(100));
+        assert_eq!(applied.mismatches[0].actual, amount(150));
+        assert_eq!(applied.supply.baking_reward_account, amount(0));
+    }
+}
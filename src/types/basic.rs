@@ -1,5 +1,6 @@
 use crypto_common::{
     derive::{SerdeBase16Serialize, Serial, Serialize},
+    types::Amount,
     Buffer, Deserial, Get, ParseResult, Put, ReadBytesExt, SerdeDeserialize, SerdeSerialize,
     Serial,
 };
@@ -279,7 +280,7 @@ impl ContractAddress {
     }
 }
 
-#[derive(SerdeSerialize, SerdeDeserialize, Debug, Clone)]
+#[derive(SerdeSerialize, SerdeDeserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(tag = "type", content = "address")]
 /// Either an account or contract address. Some operations are allowed on both
 /// types of items, hence the need for this type.
@@ -377,6 +378,13 @@ impl BakerElectionSignKey {
             sign_key: ecvrf::SecretKey::generate(csprng),
         }
     }
+
+    /// Compute the VRF proof of the message. This is used to determine whether
+    /// the baker won the leadership election in a slot.
+    pub fn prove(&self, message: &[u8]) -> ecvrf::Proof {
+        let public_key = ecvrf::PublicKey::from(&self.sign_key);
+        self.sign_key.prove(&public_key, message)
+    }
 }
 
 /// FIXME: Move higher up in the dependency
@@ -400,7 +408,7 @@ impl From<&BakerElectionSignKey> for BakerElectionVerifyKey {
 ///
 /// Note: This type contains unencrypted secret keys and should be treated
 /// carefully.
-#[derive(SerdeSerialize, Serialize)]
+#[derive(SerdeSerialize, SerdeDeserialize, Serialize)]
 pub struct BakerKeyPairs {
     #[serde(rename = "signatureSignKey")]
     pub signature_sign:     BakerSignatureSignKey,
@@ -441,7 +449,7 @@ impl BakerKeyPairs {
 ///
 /// Note: This type contains unencrypted secret keys and should be treated
 /// carefully.
-#[derive(SerdeSerialize)]
+#[derive(SerdeSerialize, SerdeDeserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BakerCredentials {
     baker_id: BakerId,
@@ -453,6 +461,12 @@ impl BakerCredentials {
     pub fn new(baker_id: BakerId, keys: BakerKeyPairs) -> Self {
         BakerCredentials { baker_id, keys }
     }
+
+    /// The ID of the baker the keys belong to.
+    pub fn baker_id(&self) -> BakerId { self.baker_id }
+
+    /// The keys of the baker.
+    pub fn keys(&self) -> &BakerKeyPairs { &self.keys }
 }
 
 /// FIXME: Move to somewhere else in the dependency. This belongs to rust-src.
@@ -502,6 +516,12 @@ pub struct ElectionDifficulty {
     parts_per_hundred_thousands: PartsPerHundredThousands,
 }
 
+impl ElectionDifficulty {
+    /// The difficulty as a fraction between 0 and 1. This is the probability
+    /// that a baker with all of the lottery power wins a given slot.
+    pub fn as_f64(&self) -> f64 { f64::from(self.parts_per_hundred_thousands.parts) / 100_000f64 }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct PartsPerHundredThousands {
     pub(crate) parts: u32,
@@ -578,18 +598,69 @@ impl Deserial for MintDistribution {
     }
 }
 
+impl MintDistribution {
+    /// Construct a new mint distribution. `None` if the fractions of the reward
+    /// accounts exceed 100%.
+    pub fn new(
+        mint_per_slot: MintRate,
+        baking_reward: RewardFraction,
+        finalization_reward: RewardFraction,
+    ) -> Option<Self> {
+        (baking_reward + finalization_reward)?;
+        Some(Self {
+            mint_per_slot,
+            baking_reward,
+            finalization_reward,
+        })
+    }
+
+    /// The fraction of the total supply that is minted in each slot.
+    pub fn mint_per_slot(&self) -> MintRate { self.mint_per_slot }
+
+    /// The fraction of newly minted CCD that goes to the baking reward account.
+    pub fn baking_reward(&self) -> RewardFraction { self.baking_reward }
+
+    /// The fraction of newly minted CCD that goes to the finalization reward
+    /// account. The remainder goes to the foundation.
+    pub fn finalization_reward(&self) -> RewardFraction { self.finalization_reward }
+}
+
 #[derive(Debug, Serialize, Clone, Copy)]
 pub struct MintRate {
     pub mantissa: u32,
     pub exponent: u8,
 }
 
+impl MintRate {
+    /// The amount minted in a single slot when the total supply is the given
+    /// amount, i.e., `mantissa * 10^(-exponent) * total`, rounded down.
+    pub fn mint_amount(&self, total: Amount) -> Amount {
+        let minted = match 10u128.checked_pow(self.exponent.into()) {
+            Some(divisor) => u128::from(total.microgtu) * u128::from(self.mantissa) / divisor,
+            None => 0,
+        };
+        Amount {
+            microgtu: u64::try_from(minted).unwrap_or(u64::MAX),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, SerdeSerialize, SerdeDeserialize, Serialize)]
 #[serde(transparent)]
 pub struct RewardFraction {
     pub(crate) parts_per_hundred_thousands: PartsPerHundredThousands,
 }
 
+impl RewardFraction {
+    /// The fraction of the given amount, rounded down.
+    pub fn take_fraction(&self, amount: Amount) -> Amount {
+        let parts = u128::from(self.parts_per_hundred_thousands.parts);
+        Amount {
+            microgtu: (u128::from(amount.microgtu) * parts / 100_000) as u64,
+        }
+    }
+}
+
 /// Sequential index of finalization.
 
 #[derive(SerdeSerialize, SerdeDeserialize, Serialize)]
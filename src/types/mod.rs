@@ -5,6 +5,7 @@ pub mod queries;
 pub mod smart_contracts;
 mod summary_helper;
 pub mod transactions;
+pub mod updates;
 
 use crate::constants::*;
 pub use crate::generated_types::PeerStatsResponse;
@@ -190,7 +191,7 @@ pub struct BirkBaker {
     pub baker_account:       AccountAddress,
 }
 
-#[derive(SerdeSerialize, SerdeDeserialize, Debug)]
+#[derive(SerdeSerialize, SerdeDeserialize, Debug, Clone, Copy)]
 #[serde(tag = "change")]
 /// Pending change in the baker's stake.
 pub enum BakerPendingChange {
@@ -205,7 +206,7 @@ pub enum BakerPendingChange {
     RemoveBaker { epoch: Epoch },
 }
 
-#[derive(SerdeSerialize, SerdeDeserialize, Debug)]
+#[derive(SerdeSerialize, SerdeDeserialize, Debug, Clone, Copy)]
 #[serde(rename_all = "camelCase")]
 /// Current balance statistics.
 pub struct RewardsOverview {
@@ -1380,7 +1381,7 @@ impl Deserial for Memo {
     }
 }
 
-#[derive(Debug, SerdeSerialize, SerdeDeserialize)]
+#[derive(Debug, SerdeSerialize, SerdeDeserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 /// The current collection of keys allowd to do updates.
 pub struct UpdateKeysCollection {
@@ -1391,7 +1392,7 @@ pub struct UpdateKeysCollection {
     pub level_2_keys: Authorizations,
 }
 
-#[derive(Debug, SerdeSerialize, SerdeDeserialize)]
+#[derive(Debug, SerdeSerialize, SerdeDeserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 /// Values of chain parameters that can be updated via chain updates.
 pub struct ChainParameters {
@@ -1415,7 +1416,23 @@ pub struct ChainParameters {
     pub minimum_threshold_for_baking: Amount,
 }
 
-#[derive(Debug, SerdeSerialize, SerdeDeserialize)]
+impl ChainParameters {
+    /// The cost in CCD of the given amount of energy at the current exchange
+    /// rates, rounded up.
+    pub fn energy_cost(&self, energy: Energy) -> Amount {
+        let numerator = num::BigUint::from(energy.energy)
+            * self.euro_per_energy.numerator
+            * self.micro_gtu_per_euro.numerator;
+        let denominator = num::BigUint::from(self.euro_per_energy.denominator)
+            * self.micro_gtu_per_euro.denominator;
+        let cost = (numerator + &denominator - 1u32) / denominator;
+        Amount {
+            microgtu: num::ToPrimitive::to_u64(&cost).unwrap_or(u64::MAX),
+        }
+    }
+}
+
+#[derive(Debug, SerdeSerialize, SerdeDeserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 /// Values of reward parameters.
 pub struct RewardParameters {
@@ -38,6 +38,26 @@ pub struct ReceiveName {
     pub name: String,
 }
 
+impl ReceiveName {
+    /// Get the name of the contract the receive function belongs to. This is
+    /// the part of the name before the first `.`.
+    pub fn contract_name(&self) -> &str {
+        match self.name.split_once('.') {
+            Some((contract, _)) => contract,
+            None => self.name.as_str(),
+        }
+    }
+
+    /// Get the name of the entrypoint, i.e., the part of the name after the
+    /// first `.`.
+    pub fn entrypoint_name(&self) -> &str {
+        match self.name.split_once('.') {
+            Some((_, entrypoint)) => entrypoint,
+            None => "",
+        }
+    }
+}
+
 impl From<ReceiveName> for String {
     fn from(n: ReceiveName) -> Self { n.name }
 }
@@ -75,6 +95,16 @@ pub struct InitName {
     name: String,
 }
 
+impl InitName {
+    /// Get the name of the contract, i.e., the name of the init function
+    /// without the `init_` prefix.
+    pub fn contract_name(&self) -> &str {
+        self.name
+            .strip_prefix("init_")
+            .unwrap_or(self.name.as_str())
+    }
+}
+
 impl From<InitName> for String {
     fn from(n: InitName) -> Self { n.name }
 }
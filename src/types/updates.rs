@@ -0,0 +1,398 @@
+//! Chain parameters and update keys in effect at a given time.
+//!
+//! Chain updates are scheduled to take effect at some time in the future. Until
+//! then they are kept in the [PendingUpdates] queues of the block state. The
+//! functions in this module apply the queued updates to the current values to
+//! determine the values that will be in effect at a later time, and list the
+//! scheduled changes in the order they take effect.
+
+use super::{
+    basic::*, Authorizations, ChainParameters, GASRewards, HigherLevelAccessStructure,
+    Level1KeysKind, PendingUpdates, ProtocolUpdate, RootKeysKind, TransactionFeeDistribution,
+    UpdateKeysCollection, UpdateQueue, Updates,
+};
+use crypto_common::types::{Amount, TransactionTime};
+
+#[derive(Debug, Clone)]
+/// A change of a chain parameter or of the update keys.
+pub enum ScheduledChange {
+    /// Update of the root keys.
+    RootKeys(HigherLevelAccessStructure<RootKeysKind>),
+    /// Update of the level 1 keys.
+    Level1Keys(HigherLevelAccessStructure<Level1KeysKind>),
+    /// Update of the level 2 keys, which authorize parameter updates.
+    Level2Keys(Box<Authorizations>),
+    /// A protocol update. The chain stops when it takes effect, so no changes
+    /// take effect after it.
+    Protocol(ProtocolUpdate),
+    /// Update of the election difficulty.
+    ElectionDifficulty(ElectionDifficulty),
+    /// Update of the euro per energy exchange rate.
+    EuroPerEnergy(ExchangeRate),
+    /// Update of the microCCD per euro exchange rate.
+    MicroGTUPerEuro(ExchangeRate),
+    /// Update of the foundation account.
+    FoundationAccount(AccountIndex),
+    /// Update of the mint distribution.
+    MintDistribution(MintDistribution),
+    /// Update of the transaction fee distribution.
+    TransactionFeeDistribution(TransactionFeeDistribution),
+    /// Update of the GAS rewards.
+    GASRewards(GASRewards),
+    /// Update of the minimum stake required for baking.
+    BakerStakeThreshold(Amount),
+    /// A new anonymity revoker.
+    AddAnonymityRevoker(Box<id::types::ArInfo<id::constants::ArCurve>>),
+    /// A new identity provider.
+    AddIdentityProvider(Box<id::types::IpInfo<id::constants::IpPairing>>),
+}
+
+#[derive(Debug, Clone)]
+/// A change together with the time it takes effect.
+pub struct TimelineEntry {
+    /// The time at which the change takes effect.
+    pub effective_time: TransactionTime,
+    /// The change.
+    pub change:         ScheduledChange,
+}
+
+#[derive(Debug, Clone)]
+/// Chain parameters and update keys in effect at some time.
+pub struct EffectiveParameters {
+    /// Values of the chain parameters.
+    pub chain_parameters: ChainParameters,
+    /// Keys allowed to perform updates.
+    pub keys:             UpdateKeysCollection,
+    /// The protocol update that has taken effect, if any. Only the first one
+    /// is recorded, since the chain stops when it takes effect.
+    pub protocol_update:  Option<ProtocolUpdate>,
+}
+
+impl EffectiveParameters {
+    /// Apply the change to the values.
+    pub fn apply(&mut self, change: ScheduledChange) {
+        let parameters = &mut self.chain_parameters;
+        match change {
+            ScheduledChange::RootKeys(keys) => self.keys.root_keys = keys,
+            ScheduledChange::Level1Keys(keys) => self.keys.level_1_keys = keys,
+            ScheduledChange::Level2Keys(keys) => self.keys.level_2_keys = *keys,
+            ScheduledChange::Protocol(update) => {
+                self.protocol_update.get_or_insert(update);
+            }
+            ScheduledChange::ElectionDifficulty(difficulty) => {
+                parameters.election_difficulty = difficulty
+            }
+            ScheduledChange::EuroPerEnergy(rate) => parameters.euro_per_energy = rate,
+            ScheduledChange::MicroGTUPerEuro(rate) => parameters.micro_gtu_per_euro = rate,
+            ScheduledChange::FoundationAccount(index) => {
+                parameters.foundation_account_index = index
+            }
+            ScheduledChange::MintDistribution(distribution) => {
+                parameters.reward_parameters.mint_distribution = distribution
+            }
+            ScheduledChange::TransactionFeeDistribution(distribution) => {
+                parameters.reward_parameters.transaction_fee_distribution = distribution
+            }
+            ScheduledChange::GASRewards(rewards) => {
+                parameters.reward_parameters.gas_rewards = rewards
+            }
+            ScheduledChange::BakerStakeThreshold(threshold) => {
+                parameters.minimum_threshold_for_baking = threshold
+            }
+            // Identity providers and anonymity revokers are not part of the
+            // parameters.
+            ScheduledChange::AddAnonymityRevoker(_) => (),
+            ScheduledChange::AddIdentityProvider(_) => (),
+        }
+    }
+}
+
+fn push_queue<T: Clone>(
+    timeline: &mut Vec<TimelineEntry>,
+    queue: &UpdateQueue<T>,
+    make: impl Fn(T) -> ScheduledChange,
+) {
+    timeline.extend(queue.queue.iter().map(|update| TimelineEntry {
+        effective_time: update.effective_time,
+        change:         make(update.update.clone()),
+    }));
+}
+
+/// The values after the changes at each time in the timeline, starting with
+/// the given values, for the times up to `until` if given. Changes that take
+/// effect at the same time result in a single entry. The entries end with a
+/// protocol update, since the chain stops when it takes effect.
+fn fold_timeline<T: Clone>(
+    values: T,
+    timeline: Vec<TimelineEntry>,
+    until: Option<TransactionTime>,
+    mut apply: impl FnMut(&mut T, ScheduledChange),
+) -> Vec<(Option<TransactionTime>, T)> {
+    let mut current = values;
+    let mut out = vec![(None, current.clone())];
+    for entry in timeline {
+        let time = entry.effective_time;
+        if until.map_or(false, |until| time.seconds > until.seconds) {
+            break;
+        }
+        let protocol_update = matches!(entry.change, ScheduledChange::Protocol(_));
+        apply(&mut current, entry.change);
+        if matches!(out.last(), Some((Some(last), _)) if last.seconds == time.seconds) {
+            out.pop();
+        }
+        out.push((Some(time), current.clone()));
+        if protocol_update {
+            break;
+        }
+    }
+    out
+}
+
+impl PendingUpdates {
+    /// All the scheduled changes, ordered by the time they take effect.
+    /// Changes that take effect at the same time are listed in the order of
+    /// the fields of [PendingUpdates].
+    pub fn timeline(&self) -> Vec<TimelineEntry> {
+        let mut timeline = Vec::new();
+        push_queue(&mut timeline, &self.root_keys, ScheduledChange::RootKeys);
+        push_queue(
+            &mut timeline,
+            &self.level_1_keys,
+            ScheduledChange::Level1Keys,
+        );
+        push_queue(&mut timeline, &self.level_2_keys, |keys| {
+            ScheduledChange::Level2Keys(Box::new(keys))
+        });
+        push_queue(&mut timeline, &self.protocol, ScheduledChange::Protocol);
+        push_queue(
+            &mut timeline,
+            &self.election_difficulty,
+            ScheduledChange::ElectionDifficulty,
+        );
+        push_queue(
+            &mut timeline,
+            &self.euro_per_energy,
+            ScheduledChange::EuroPerEnergy,
+        );
+        push_queue(
+            &mut timeline,
+            &self.micro_gtu_per_euro,
+            ScheduledChange::MicroGTUPerEuro,
+        );
+        push_queue(
+            &mut timeline,
+            &self.foundation_account,
+            ScheduledChange::FoundationAccount,
+        );
+        push_queue(
+            &mut timeline,
+            &self.mint_distribution,
+            ScheduledChange::MintDistribution,
+        );
+        push_queue(
+            &mut timeline,
+            &self.transaction_fee_distribution,
+            ScheduledChange::TransactionFeeDistribution,
+        );
+        push_queue(
+            &mut timeline,
+            &self.gas_rewards,
+            ScheduledChange::GASRewards,
+        );
+        push_queue(
+            &mut timeline,
+            &self.baker_stake_threshold,
+            ScheduledChange::BakerStakeThreshold,
+        );
+        push_queue(&mut timeline, &self.add_anonymity_revoker, |ar| {
+            ScheduledChange::AddAnonymityRevoker(Box::new(ar))
+        });
+        push_queue(&mut timeline, &self.add_identity_provider, |ip| {
+            ScheduledChange::AddIdentityProvider(Box::new(ip))
+        });
+        // The sort is stable, so the order within each queue is kept.
+        timeline.sort_by_key(|entry| entry.effective_time.seconds);
+        timeline
+    }
+
+    /// Apply the changes that take effect at or before the given time to the
+    /// given values. The chain stops when a protocol update takes effect, so
+    /// the changes after the first protocol update in the [timeline
+    /// ](Self::timeline) are not applied, and no changes are applied if the
+    /// values already have a protocol update.
+    pub fn apply_until(
+        &self,
+        values: EffectiveParameters,
+        time: TransactionTime,
+    ) -> EffectiveParameters {
+        if values.protocol_update.is_some() {
+            return values;
+        }
+        fold_timeline(
+            values,
+            self.timeline(),
+            Some(time),
+            EffectiveParameters::apply,
+        )
+        .pop()
+        .map(|(_, values)| values)
+        .expect("The initial values are always included.")
+    }
+}
+
+impl Updates {
+    /// The chain parameters and update keys currently in effect.
+    pub fn current(&self) -> EffectiveParameters {
+        EffectiveParameters {
+            chain_parameters: self.chain_parameters.clone(),
+            keys:             self.keys.clone(),
+            protocol_update:  self.protocol_update.clone(),
+        }
+    }
+
+    /// The chain parameters and update keys that will be in effect at the
+    /// given time, assuming no further updates are scheduled.
+    pub fn effective_at(&self, time: TransactionTime) -> EffectiveParameters {
+        self.update_queues.apply_until(self.current(), time)
+    }
+
+    /// The values in effect after each scheduled change, starting with the
+    /// current values. Each entry gives the time from which the values are in
+    /// effect, which is `None` for the current values. Changes that take
+    /// effect at the same time result in a single entry. The list ends with
+    /// the first protocol update, since the chain stops when it takes effect.
+    pub fn parameter_timeline(&self) -> Vec<(Option<TransactionTime>, EffectiveParameters)> {
+        let current = self.current();
+        if current.protocol_update.is_some() {
+            return vec![(None, current)];
+        }
+        fold_timeline(
+            current,
+            self.update_queues.timeline(),
+            None,
+            EffectiveParameters::apply,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{hashes, ScheduledUpdate};
+
+    fn queue<T>(updates: Vec<(u64, T)>) -> UpdateQueue<T> {
+        UpdateQueue {
+            next_sequence_number: UpdateSequenceNumber {
+                number: updates.len() as u64 + 1,
+            },
+            queue:                updates
+                .into_iter()
+                .map(|(seconds, update)| ScheduledUpdate {
+                    effective_time: TransactionTime::from_seconds(seconds),
+                    update,
+                })
+                .collect(),
+        }
+    }
+
+    fn rate(numerator: u64) -> ExchangeRate {
+        ExchangeRate {
+            numerator,
+            denominator: 1,
+        }
+    }
+
+    fn test_updates() -> PendingUpdates {
+        let protocol = ProtocolUpdate {
+            message: "P2".into(),
+            specification_url: "https://example.com".into(),
+            specification_hash: hashes::Hash::new([0; 32]),
+            specification_auxiliary_data: Vec::new(),
+        };
+        PendingUpdates {
+            root_keys:                    queue(Vec::new()),
+            level_1_keys:                 queue(Vec::new()),
+            level_2_keys:                 queue(Vec::new()),
+            protocol:                     queue(vec![(40, protocol)]),
+            election_difficulty:          queue(Vec::new()),
+            euro_per_energy:              queue(vec![(10, rate(1)), (30, rate(3))]),
+            micro_gtu_per_euro:           queue(vec![(20, rate(2)), (30, rate(4))]),
+            foundation_account:           queue(Vec::new()),
+            mint_distribution:            queue(Vec::new()),
+            transaction_fee_distribution: queue(Vec::new()),
+            gas_rewards:                  queue(Vec::new()),
+            baker_stake_threshold:        queue(vec![(50, Amount { microgtu: 5 })]),
+            add_anonymity_revoker:        queue(Vec::new()),
+            add_identity_provider:        queue(Vec::new()),
+        }
+    }
+
+    /// A short description of the change, to compare timelines.
+    fn describe(change: &ScheduledChange) -> String {
+        match change {
+            ScheduledChange::EuroPerEnergy(rate) => format!("euro {}", rate.numerator),
+            ScheduledChange::MicroGTUPerEuro(rate) => format!("micro {}", rate.numerator),
+            ScheduledChange::Protocol(update) => format!("protocol {}", update.message),
+            ScheduledChange::BakerStakeThreshold(amount) => {
+                format!("threshold {}", amount.microgtu)
+            }
+            _ => "other".into(),
+        }
+    }
+
+    #[test]
+    fn test_timeline() {
+        let timeline = test_updates().timeline();
+        let times = timeline
+            .iter()
+            .map(|entry| entry.effective_time.seconds)
+            .collect::<Vec<_>>();
+        assert_eq!(times, vec![10, 20, 30, 30, 40, 50]);
+        let changes = timeline
+            .iter()
+            .map(|entry| describe(&entry.change))
+            .collect::<Vec<_>>();
+        // Changes at the same time are in the order of the queues.
+        assert_eq!(changes, vec![
+            "euro 1",
+            "micro 2",
+            "euro 3",
+            "micro 4",
+            "protocol P2",
+            "threshold 5"
+        ]);
+    }
+
+    #[test]
+    fn test_fold_timeline() {
+        let apply =
+            |applied: &mut Vec<String>, change: ScheduledChange| applied.push(describe(&change));
+        let timeline = test_updates().timeline();
+        let folded = fold_timeline(Vec::new(), timeline.clone(), None, apply);
+        let times = folded
+            .iter()
+            .map(|(time, _)| time.map(|time| time.seconds))
+            .collect::<Vec<_>>();
+        // The changes at time 30 give a single entry, and the change after the
+        // protocol update is not applied.
+        assert_eq!(times, vec![None, Some(10), Some(20), Some(30), Some(40)]);
+        assert!(folded[0].1.is_empty());
+        assert_eq!(folded[3].1, vec!["euro 1", "micro 2", "euro 3", "micro 4"]);
+        assert_eq!(folded[4].1.last().map(String::as_str), Some("protocol P2"));
+
+        let until = fold_timeline(
+            Vec::new(),
+            timeline.clone(),
+            Some(TransactionTime::from_seconds(29)),
+            apply,
+        );
+        assert_eq!(until.last().map(|(_, applied)| applied.len()), Some(2));
+        let until = fold_timeline(
+            Vec::new(),
+            timeline,
+            Some(TransactionTime::from_seconds(100)),
+            apply,
+        );
+        assert_eq!(until.last().map(|(_, applied)| applied.len()), Some(5));
+    }
+}